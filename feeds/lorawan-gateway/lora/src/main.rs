@@ -1,5 +1,4 @@
 use std::{thread, time::Duration};
-use std::process::Command;
 
 use gpio_cdev::{Chip, LineRequestFlags};
 
@@ -9,13 +8,15 @@ const DEFAULT_SX1302_POWER_EN: u32 = 0;
 const DEFAULT_SX1302_RESET: u32 = 2;
 const DEFAULT_SX1261_RESET: u32 = 1;
 
-// UCI configuration paths
-const UCI_SX1302_POWER_EN_CHIP: &str = "hardware.hardware.sx1302_power_en_chip";
-const UCI_SX1302_POWER_EN_PIN: &str = "hardware.hardware.sx1302_power_en_pin";
-const UCI_SX1302_RESET_CHIP: &str = "hardware.hardware.sx1302_reset_chip";
-const UCI_SX1302_RESET_PIN: &str = "hardware.hardware.sx1302_reset_pin";
-const UCI_SX1261_RESET_CHIP: &str = "hardware.hardware.sx1261_reset_chip";
-const UCI_SX1261_RESET_PIN: &str = "hardware.hardware.sx1261_reset_pin";
+// UCI options in hardware.hardware
+const UCI_PACKAGE: &str = "hardware";
+const UCI_SECTION: &str = "hardware";
+const UCI_SX1302_POWER_EN_CHIP: &str = "sx1302_power_en_chip";
+const UCI_SX1302_POWER_EN_PIN: &str = "sx1302_power_en_pin";
+const UCI_SX1302_RESET_CHIP: &str = "sx1302_reset_chip";
+const UCI_SX1302_RESET_PIN: &str = "sx1302_reset_pin";
+const UCI_SX1261_RESET_CHIP: &str = "sx1261_reset_chip";
+const UCI_SX1261_RESET_PIN: &str = "sx1261_reset_pin";
 
 struct GpioConfig {
     power_en_chip: String,
@@ -26,52 +27,31 @@ struct GpioConfig {
     sx1261_pin: u32,
 }
 
-/// Get UCI configuration value by key
-fn get_uci_config(key: &str) -> Option<String> {
-    let output = Command::new("uci")
-        .arg("get")
-        .arg(key)
-        .output();
-
-    match output {
-        Ok(output) => {
-            if output.status.success() {
-                let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                if !value.is_empty() {
-                    Some(value)
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        }
-        Err(_) => None,
-    }
-}
-
 /// Load GPIO configuration from UCI, with fallback to defaults
 fn load_gpio_config() -> GpioConfig {
+    let hardware = uci::load(UCI_PACKAGE).unwrap_or_else(|e| {
+        eprintln!("Failed to read UCI {}: {}, using defaults", UCI_PACKAGE, e);
+        uci::Package::default()
+    });
+    let get_chip = |option: &str| {
+        hardware
+            .get(UCI_SECTION, option)
+            .filter(|value| !value.is_empty())
+            .map_or_else(|| DEFAULT_GPIOCHIP.to_string(), str::to_string)
+    };
+    let get_pin = |option: &str, default: u32| hardware.get_parsed(UCI_SECTION, option).unwrap_or(default);
+
     // SX1302 POWER_EN configuration
-    let power_en_chip = get_uci_config(UCI_SX1302_POWER_EN_CHIP)
-        .unwrap_or_else(|| DEFAULT_GPIOCHIP.to_string());
-    let power_en_pin = get_uci_config(UCI_SX1302_POWER_EN_PIN)
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(DEFAULT_SX1302_POWER_EN);
+    let power_en_chip = get_chip(UCI_SX1302_POWER_EN_CHIP);
+    let power_en_pin = get_pin(UCI_SX1302_POWER_EN_PIN, DEFAULT_SX1302_POWER_EN);
 
     // SX1302 RESET configuration
-    let reset_chip = get_uci_config(UCI_SX1302_RESET_CHIP)
-        .unwrap_or_else(|| DEFAULT_GPIOCHIP.to_string());
-    let reset_pin = get_uci_config(UCI_SX1302_RESET_PIN)
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(DEFAULT_SX1302_RESET);
+    let reset_chip = get_chip(UCI_SX1302_RESET_CHIP);
+    let reset_pin = get_pin(UCI_SX1302_RESET_PIN, DEFAULT_SX1302_RESET);
 
     // SX1261 RESET configuration
-    let sx1261_chip = get_uci_config(UCI_SX1261_RESET_CHIP)
-        .unwrap_or_else(|| DEFAULT_GPIOCHIP.to_string());
-    let sx1261_pin = get_uci_config(UCI_SX1261_RESET_PIN)
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(DEFAULT_SX1261_RESET);
+    let sx1261_chip = get_chip(UCI_SX1261_RESET_CHIP);
+    let sx1261_pin = get_pin(UCI_SX1261_RESET_PIN, DEFAULT_SX1261_RESET);
 
     GpioConfig {
         power_en_chip,
@@ -0,0 +1,153 @@
+use rumqttc::tokio_rustls::rustls::{ClientConfig as RustlsClientConfig, RootCertStore};
+use rumqttc::{MqttOptions, Transport};
+use rustls_pemfile::{certs, pkcs8_private_keys};
+use std::time::Duration;
+
+// Where and how to reach the broker; the transport and authentication options of a
+// UCI `config mqtt` section, as the LuCI pages of all daemons present them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Broker {
+    // tcp, ssl/tls, ws or wss
+    pub transport: String,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub client_id: String,
+    pub keepalive: u64,
+    // none, user-pass, tls-server or mutual-tls
+    pub auth_mode: String,
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+}
+
+impl Broker {
+    pub fn load(package: &uci::Package, section: &str, default_client_id: &str) -> Broker {
+        let get = |option: &str| package.get(section, option).map(|value| value.trim().to_string());
+        Broker {
+            transport: get("transport").unwrap_or_else(|| "tcp".to_string()),
+            host: get("host").unwrap_or_default(),
+            port: package.get_parsed(section, "port").unwrap_or(1883),
+            username: get("username"),
+            password: get("password"),
+            client_id: get("client_id").unwrap_or_else(|| default_client_id.to_string()),
+            keepalive: package.get_parsed(section, "keepalive").unwrap_or(30),
+            auth_mode: get("auth_mode").unwrap_or_else(|| "none".to_string()),
+            ca_cert: get("ca_cert"),
+            client_cert: get("client_cert"),
+            client_key: get("client_key"),
+        }
+    }
+
+    // Client options for this broker; callers add what is specific to them (last will, ...)
+    pub fn options(&self) -> Result<MqttOptions, Box<dyn std::error::Error + Send + Sync>> {
+        // WebSocket transports take the broker as a URL
+        let broker = match self.transport.as_str() {
+            "ws" => format!("ws://{}:{}/mqtt", self.host, self.port),
+            "wss" => format!("wss://{}:{}/mqtt", self.host, self.port),
+            _ => self.host.clone(),
+        };
+        let mut options = MqttOptions::new(&self.client_id, broker, self.port);
+        options.set_keep_alive(Duration::from_secs(self.keepalive));
+        if let Some(username) = &self.username {
+            options.set_credentials(username, self.password.as_deref().unwrap_or(""));
+        }
+
+        match self.transport.as_str() {
+            "ssl" | "tls" => options.set_transport(Transport::tls_with_config(self.tls_config()?.into())),
+            "ws" => options.set_transport(Transport::Ws),
+            "wss" => options.set_transport(Transport::wss_with_config(self.tls_config()?.into())),
+            // Plain TCP (default)
+            _ => options.set_transport(Transport::Tcp),
+        };
+        Ok(options)
+    }
+
+    // The uploaded CA is only offered, and so only used, in the certificate modes;
+    // otherwise the server is verified against the built-in web roots
+    fn root_certificates(&self) -> Result<RootCertStore, Box<dyn std::error::Error + Send + Sync>> {
+        let mut root_cert_store = RootCertStore::empty();
+        match &self.ca_cert {
+            Some(ca_cert) if matches!(self.auth_mode.as_str(), "tls-server" | "mutual-tls") => {
+                let mut cursor = std::io::Cursor::new(ca_cert.as_bytes());
+                for cert in certs(&mut cursor) {
+                    root_cert_store.add(cert?)?;
+                }
+            }
+            _ => root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        Ok(root_cert_store)
+    }
+
+    fn tls_config(&self) -> Result<RustlsClientConfig, Box<dyn std::error::Error + Send + Sync>> {
+        let builder = RustlsClientConfig::builder().with_root_certificates(self.root_certificates()?);
+        if self.auth_mode != "mutual-tls" {
+            return Ok(builder.with_no_client_auth());
+        }
+        // Mutual TLS - load client certificate and private key
+        let (Some(client_cert_pem), Some(client_key_pem)) = (&self.client_cert, &self.client_key) else {
+            return Err("Mutual TLS requires both client certificate and private key".into());
+        };
+        let mut cert_cursor = std::io::Cursor::new(client_cert_pem.as_bytes());
+        let certs: Vec<_> = certs(&mut cert_cursor).collect::<Result<_, _>>()?;
+        let mut key_cursor = std::io::Cursor::new(client_key_pem.as_bytes());
+        let mut keys = pkcs8_private_keys(&mut key_cursor).collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err("No private key found".into());
+        }
+        Ok(builder.with_client_auth_cert(certs, keys.remove(0).into())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn broker(text: &str) -> Broker {
+        Broker::load(&uci::Package::parse(text).unwrap(), "mqtt", "default_id")
+    }
+
+    #[test]
+    fn defaults() {
+        let broker = broker("config mqtt 'mqtt'\n\toption host 'broker.local'\n");
+        assert_eq!(broker.transport, "tcp");
+        assert_eq!(broker.port, 1883);
+        assert_eq!(broker.client_id, "default_id");
+        assert_eq!(broker.keepalive, 30);
+        assert_eq!(broker.auth_mode, "none");
+
+        let options = broker.options().unwrap();
+        assert_eq!(options.broker_address(), ("broker.local".to_string(), 1883));
+        assert_eq!(options.client_id(), "default_id");
+        assert_eq!(options.credentials(), None);
+    }
+
+    #[test]
+    fn username_without_password() {
+        let broker = broker("config mqtt 'mqtt'\n\toption host 'h'\n\toption username 'user'\n");
+        assert_eq!(broker.options().unwrap().credentials(), Some(("user".to_string(), String::new())));
+    }
+
+    #[test]
+    fn websocket_takes_a_url() {
+        let broker = broker("config mqtt 'mqtt'\n\toption host 'h'\n\toption port '8083'\n\toption transport 'ws'\n");
+        assert_eq!(broker.options().unwrap().broker_address(), ("ws://h:8083/mqtt".to_string(), 8083));
+    }
+
+    #[test]
+    fn ca_certificate_only_in_certificate_modes() {
+        // Not a certificate: only an error when it is actually used
+        let ca = "option ca_cert '-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----'\n";
+        let plain = broker(&format!("config mqtt 'mqtt'\n\toption transport 'tls'\n\t{}", ca));
+        assert!(plain.options().is_ok());
+        let server = broker(&format!("config mqtt 'mqtt'\n\toption transport 'tls'\n\toption auth_mode 'tls-server'\n\t{}", ca));
+        assert!(server.options().is_err());
+    }
+
+    #[test]
+    fn mutual_tls_needs_certificate_and_key() {
+        let broker = broker("config mqtt 'mqtt'\n\toption transport 'tls'\n\toption auth_mode 'mutual-tls'\n");
+        assert!(broker.options().is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use rumqttc::{AsyncClient, Incoming, MqttOptions, Outgoing, Publish, QoS};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// Requests queued towards the event loop; publishes beyond this fail instead of blocking
+const CAPACITY: usize = 64;
+
+// What the connection task reports to the main loop
+#[derive(Debug)]
+pub enum Event {
+    // ConnAck received; subscriptions have to be made again
+    Connected,
+    Disconnected(String),
+    Message(Publish),
+    // The broker acknowledged the publish made with this tag (QoS 0: it was written out)
+    Delivered(u64),
+}
+
+// Client of the current connection attempt and the tags of its queued publishes, in queue order
+#[derive(Default)]
+struct Link {
+    client: Option<AsyncClient>,
+    tags: VecDeque<Option<u64>>,
+}
+
+// Broker connection driven by its own task, so a busy main loop never stalls the event loop
+// and publishing never waits for it
+pub struct Connection {
+    link: Arc<StdMutex<Link>>,
+    task: JoinHandle<()>,
+}
+
+impl Connection {
+    // Connect with `options`, reconnecting `reconnect_delay` after the connection is lost
+    pub fn spawn(options: MqttOptions, reconnect_delay: Duration) -> (Connection, mpsc::Receiver<Event>) {
+        let (events_tx, events) = mpsc::channel(CAPACITY);
+        let link = Arc::new(StdMutex::new(Link::default()));
+        let task = tokio::spawn(run(options, reconnect_delay, link.clone(), events_tx));
+        (Connection { link, task }, events)
+    }
+
+    pub fn publish(&self, topic: &str, qos: QoS, retain: bool, payload: Vec<u8>) -> Result<(), String> {
+        self.enqueue(None, topic, qos, retain, payload)
+    }
+
+    // Publish and report `Event::Delivered(tag)` once the broker has it
+    pub fn publish_tracked(&self, tag: u64, topic: &str, qos: QoS, payload: Vec<u8>) -> Result<(), String> {
+        self.enqueue(Some(tag), topic, qos, false, payload)
+    }
+
+    pub fn subscribe(&self, topic: &str, qos: QoS) -> Result<(), String> {
+        match self.link.lock().map_err(|e| e.to_string())?.client.as_ref() {
+            Some(client) => client.try_subscribe(topic, qos).map_err(|e| e.to_string()),
+            None => Err("MQTT not connected".to_string()),
+        }
+    }
+
+    fn enqueue(&self, tag: Option<u64>, topic: &str, qos: QoS, retain: bool, payload: Vec<u8>) -> Result<(), String> {
+        // The tag is queued under the same lock, so the task never sees the publish before it
+        let mut link = self.link.lock().map_err(|e| e.to_string())?;
+        let client = link.client.as_ref().ok_or("MQTT not connected")?;
+        client.try_publish(topic, qos, retain, payload).map_err(|e| e.to_string())?;
+        link.tags.push_back(tag);
+        Ok(())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run(
+    options: MqttOptions,
+    reconnect_delay: Duration,
+    link: Arc<StdMutex<Link>>,
+    events: mpsc::Sender<Event>,
+) {
+    loop {
+        // A fresh client per attempt, so nothing queued for a lost connection is sent later
+        let (client, mut eventloop) = AsyncClient::new(options.clone(), CAPACITY);
+        if let Ok(mut link) = link.lock() {
+            *link = Link { client: Some(client), tags: VecDeque::new() };
+        }
+        // Tags of tracked publishes waiting for their PubAck/PubComp
+        let mut unacked: HashMap<u16, u64> = HashMap::new();
+
+        let error = loop {
+            let event = match eventloop.poll().await {
+                Ok(rumqttc::Event::Incoming(Incoming::ConnAck(_))) => Event::Connected,
+                Ok(rumqttc::Event::Incoming(Incoming::Publish(publish))) => Event::Message(publish),
+                Ok(rumqttc::Event::Incoming(Incoming::PubAck(ack))) => match unacked.remove(&ack.pkid) {
+                    Some(tag) => Event::Delivered(tag),
+                    None => continue,
+                },
+                Ok(rumqttc::Event::Incoming(Incoming::PubComp(comp))) => match unacked.remove(&comp.pkid) {
+                    Some(tag) => Event::Delivered(tag),
+                    None => continue,
+                },
+                Ok(rumqttc::Event::Incoming(Incoming::Disconnect)) => break "disconnected by the broker".to_string(),
+                // Publishes go out in the order they were queued
+                Ok(rumqttc::Event::Outgoing(Outgoing::Publish(pkid))) => {
+                    let tag = link.lock().ok().and_then(|mut link| link.tags.pop_front()).flatten();
+                    match tag {
+                        Some(tag) if pkid == 0 => Event::Delivered(tag),
+                        Some(tag) => {
+                            unacked.insert(pkid, tag);
+                            continue;
+                        }
+                        None => continue,
+                    }
+                }
+                Ok(_) => continue,
+                Err(e) => break e.to_string(),
+            };
+            if events.send(event).await.is_err() {
+                return;
+            }
+        };
+
+        if let Ok(mut link) = link.lock() {
+            *link = Link::default();
+        }
+        if events.send(Event::Disconnected(error)).await.is_err() {
+            return;
+        }
+        tokio::time::sleep(reconnect_delay).await;
+    }
+}
@@ -0,0 +1,13 @@
+// MQTT pieces shared by the gateway daemons.
+//
+// `Broker` turns the UCI connection settings into client options,
+// `Connection` drives the broker connection on a task of its own and reports
+// which tracked publishes the broker acknowledged; `spool::Spool` keeps
+// uplinks on disk until such an acknowledgement arrives.
+
+mod broker;
+mod connection;
+pub mod spool;
+
+pub use broker::Broker;
+pub use connection::{Connection, Event};
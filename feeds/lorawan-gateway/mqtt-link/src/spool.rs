@@ -0,0 +1,527 @@
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::Connection;
+
+// Store-and-forward queue for uplinks produced while the broker is unreachable.
+//
+// Records are appended as JSON lines to numbered segment files in `<dir>/<name>/`, oldest
+// first, and never rewritten. A record handed out for delivery stays on disk until the
+// broker acknowledged it; segments are deleted once all of their records are delivered and
+// the position inside the oldest one is kept in a small `cursor` file. The queue is bounded
+// by size (whole oldest segments are dropped) and by age (expired records are skipped).
+
+// Replayed records waiting for their acknowledgement; the rest of the client queue is left to live traffic
+const REPLAY_WINDOW: usize = 5;
+// Segments per size limit, so dropping the oldest one loses a small part of the queue
+const SEGMENTS: u64 = 8;
+// How often the cursor is saved while a segment is being delivered
+const CURSOR_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    time: i64,
+    topic: String,
+    payload: String,
+    // Payload is hex-encoded because it is not valid UTF-8
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    hex: bool,
+}
+
+impl Record {
+    fn bytes(&self) -> Vec<u8> {
+        if !self.hex {
+            return self.payload.as_bytes().to_vec();
+        }
+        (0..self.payload.len() / 2)
+            .filter_map(|i| u8::from_str_radix(&self.payload[i * 2..i * 2 + 2], 16).ok())
+            .collect()
+    }
+}
+
+// A buffered uplink handed out for delivery; `id` is passed to `ack` once it arrived
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub id: u64,
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    segment: u64,
+    offset: u64,
+}
+
+#[derive(Debug)]
+struct Segment {
+    number: u64,
+    size: u64,
+    // Records not delivered yet
+    records: usize,
+}
+
+// A record handed out by `next`, in file order
+#[derive(Debug)]
+struct Flight {
+    id: u64,
+    start: Position,
+    end: Position,
+    delivered: bool,
+}
+
+pub struct Spool {
+    dir: PathBuf,
+    max_size: u64,
+    max_age: i64,
+    segment_size: u64,
+    // Oldest first; records are appended to the last one
+    segments: VecDeque<Segment>,
+    // First record not delivered yet
+    cursor: Position,
+    // First record not handed out yet
+    read: Position,
+    flights: VecDeque<Flight>,
+    next_id: u64,
+    cursor_saved: Instant,
+}
+
+impl Spool {
+    // `dir` is tmpfs (/tmp/...) or overlay storage when uplinks must survive a reboot
+    pub fn new(dir: &str, name: &str, max_size: u64, max_age: u64) -> std::io::Result<Spool> {
+        let path = PathBuf::from(dir).join(name);
+        std::fs::create_dir_all(&path)?;
+
+        let mut numbers = Vec::new();
+        for entry in std::fs::read_dir(&path)? {
+            let file = entry?.path();
+            if file.extension().is_some_and(|ext| ext == "seg") {
+                if let Some(number) = file.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok()) {
+                    numbers.push(number);
+                }
+            }
+        }
+        numbers.sort_unstable();
+
+        let cursor = read_cursor(&path)
+            .filter(|cursor| numbers.contains(&cursor.segment))
+            .unwrap_or(Position { segment: numbers.first().copied().unwrap_or(0), offset: 0 });
+
+        let mut segments = VecDeque::new();
+        for number in numbers {
+            let file = segment_path(&path, number);
+            // Left behind when the cursor was saved but the segment not yet deleted
+            if number < cursor.segment {
+                std::fs::remove_file(&file)?;
+                continue;
+            }
+            let start = if number == cursor.segment { cursor.offset } else { 0 };
+            segments.push_back(Segment {
+                number,
+                size: std::fs::metadata(&file)?.len(),
+                records: count_records(&file, start)?,
+            });
+        }
+        // Append to a new segment, never after a line a crash may have cut short
+        let head = segments.back().map_or(cursor.segment, |s| s.number + 1);
+        segments.push_back(Segment { number: head, size: 0, records: 0 });
+
+        Ok(Spool {
+            dir: path,
+            max_size,
+            max_age: max_age as i64,
+            segment_size: (max_size / SEGMENTS).max(1),
+            segments,
+            cursor,
+            read: cursor,
+            flights: VecDeque::new(),
+            // Unique across reopening, so a late acknowledgement for an earlier buffer matches nothing
+            next_id: chrono::Local::now().timestamp_micros() as u64,
+            cursor_saved: Instant::now(),
+        })
+    }
+
+    // Records not delivered yet, including those in flight
+    pub fn pending(&self) -> usize {
+        self.segments.iter().map(|s| s.records).sum()
+    }
+
+    pub fn push(&mut self, topic: &str, payload: &[u8]) -> std::io::Result<()> {
+        let (payload, hex) = match std::str::from_utf8(payload) {
+            Ok(text) => (text.to_string(), false),
+            Err(_) => (payload.iter().map(|b| format!("{:02X}", b)).collect(), true),
+        };
+        let record = Record {
+            time: chrono::Local::now().timestamp(),
+            topic: topic.to_string(),
+            payload,
+            hex,
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+
+        let head = self.head();
+        if head.size > 0 && head.size + line.len() as u64 > self.segment_size {
+            let number = head.number + 1;
+            self.segments.push_back(Segment { number, size: 0, records: 0 });
+        }
+        let head = self.segments.back_mut().expect("spool has a head segment");
+        let mut file = OpenOptions::new().create(true).append(true).open(segment_path(&self.dir, head.number))?;
+        file.write_all(line.as_bytes())?;
+        head.size += line.len() as u64;
+        head.records += 1;
+
+        // Over the limit: the oldest segments go, delivered or not
+        while self.segments.len() > 1 && self.segments.iter().map(|s| s.size).sum::<u64>() > self.max_size {
+            self.drop_oldest()?;
+        }
+        Ok(())
+    }
+
+    // Hand out up to `count` records following those already handed out, oldest first.
+    // Expired and unreadable records are skipped.
+    pub fn next(&mut self, count: usize) -> std::io::Result<Vec<Entry>> {
+        let oldest = chrono::Local::now().timestamp() - self.max_age;
+        let mut entries = Vec::new();
+
+        while entries.len() < count {
+            let Some(index) = self.segments.iter().position(|s| s.number == self.read.segment) else {
+                break;
+            };
+            let segment = &self.segments[index];
+            if self.read.offset >= segment.size {
+                match self.segments.get(index + 1) {
+                    Some(next) => {
+                        self.read = Position { segment: next.number, offset: 0 };
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+
+            let mut file = File::open(segment_path(&self.dir, segment.number))?;
+            file.seek(SeekFrom::Start(self.read.offset))?;
+            let mut reader = BufReader::new(file);
+            let mut line = Vec::new();
+            while entries.len() < count {
+                line.clear();
+                let read = reader.read_until(b'\n', &mut line)? as u64;
+                if read == 0 || line.last() != Some(&b'\n') {
+                    // A line cut short by a crash ends the segment
+                    if index + 1 < self.segments.len() {
+                        self.read.offset = segment.size;
+                    }
+                    break;
+                }
+
+                let start = self.read;
+                self.read.offset += read;
+                let record = serde_json::from_slice::<Record>(&line)
+                    .ok()
+                    .filter(|record| self.max_age == 0 || record.time >= oldest);
+                let id = self.next_id;
+                self.next_id += 1;
+                self.flights.push_back(Flight { id, start, end: self.read, delivered: record.is_none() });
+                if let Some(record) = record {
+                    entries.push(Entry { id, payload: record.bytes(), topic: record.topic });
+                }
+            }
+            if self.read.offset < segment.size && entries.len() < count {
+                break;
+            }
+        }
+
+        // Skipped records count as delivered
+        self.advance()?;
+        Ok(entries)
+    }
+
+    // The broker has the record handed out as `id`
+    pub fn ack(&mut self, id: u64) -> std::io::Result<()> {
+        if let Some(flight) = self.flights.iter_mut().find(|f| f.id == id) {
+            flight.delivered = true;
+        }
+        self.advance()
+    }
+
+    // `id` and everything handed out after it were not sent and will be handed out again
+    pub fn release(&mut self, id: u64) {
+        if let Some(index) = self.flights.iter().position(|f| f.id == id) {
+            self.read = self.flights[index].start;
+            self.flights.truncate(index);
+        }
+    }
+
+    // The connection was lost: records without an acknowledgement are handed out again
+    pub fn rewind(&mut self) {
+        self.flights.clear();
+        self.read = self.cursor;
+    }
+
+    // Publish buffered records in order, at least with QoS 1 so the broker acknowledges them.
+    // They stay buffered until `ack` is called for the `Event::Delivered` tag.
+    pub fn replay(&mut self, connection: &Connection, qos: QoS) -> std::io::Result<usize> {
+        let in_flight = self.flights.iter().filter(|f| !f.delivered).count();
+        if in_flight >= REPLAY_WINDOW {
+            return Ok(0);
+        }
+        let qos = if qos == QoS::AtMostOnce { QoS::AtLeastOnce } else { qos };
+
+        let mut sent = 0;
+        for entry in self.next(REPLAY_WINDOW - in_flight)? {
+            let id = entry.id;
+            if connection.publish_tracked(id, &entry.topic, qos, entry.payload).is_err() {
+                self.release(id);
+                break;
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    fn head(&self) -> &Segment {
+        self.segments.back().expect("spool has a head segment")
+    }
+
+    // Move the cursor over the delivered records in front and delete the segments left behind
+    fn advance(&mut self) -> std::io::Result<()> {
+        let mut moved = false;
+        while self.flights.front().is_some_and(|f| f.delivered) {
+            let flight = self.flights.pop_front().expect("front flight");
+            if let Some(segment) = self.segments.iter_mut().find(|s| s.number == flight.start.segment) {
+                segment.records = segment.records.saturating_sub(1);
+            }
+            self.cursor = flight.end;
+            moved = true;
+        }
+
+        let mut removed = false;
+        while self.segments.len() > 1 && self.segments[0].records == 0 && self.cursor.segment >= self.segments[0].number {
+            self.remove_oldest()?;
+            removed = true;
+        }
+        // Everything delivered: start over with an empty head segment
+        if self.pending() == 0 && self.flights.is_empty() && self.head().size > 0 {
+            let number = self.head().number;
+            std::fs::remove_file(segment_path(&self.dir, number))?;
+            self.segments.clear();
+            self.segments.push_back(Segment { number: number + 1, size: 0, records: 0 });
+            self.cursor = Position { segment: number + 1, offset: 0 };
+            self.read = self.cursor;
+            removed = true;
+        }
+
+        if removed || (moved && self.cursor_saved.elapsed() >= CURSOR_SAVE_INTERVAL) {
+            self.save_cursor()?;
+        }
+        Ok(())
+    }
+
+    // Drop the oldest segment because of the size limit, with whatever was handed out from it
+    fn drop_oldest(&mut self) -> std::io::Result<()> {
+        let number = self.segments[0].number;
+        self.flights.retain(|f| f.start.segment != number);
+        self.remove_oldest()?;
+        self.save_cursor()
+    }
+
+    fn remove_oldest(&mut self) -> std::io::Result<()> {
+        if let Some(segment) = self.segments.pop_front() {
+            match std::fs::remove_file(segment_path(&self.dir, segment.number)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        let first = Position { segment: self.segments[0].number, offset: 0 };
+        self.cursor = self.cursor.max(first);
+        self.read = self.read.max(self.cursor);
+        Ok(())
+    }
+
+    fn save_cursor(&mut self) -> std::io::Result<()> {
+        let tmp = self.dir.join("cursor.tmp");
+        std::fs::write(&tmp, format!("{} {}\n", self.cursor.segment, self.cursor.offset))?;
+        std::fs::rename(&tmp, self.dir.join("cursor"))?;
+        self.cursor_saved = Instant::now();
+        Ok(())
+    }
+}
+
+// A reloaded or reopened buffer continues where this one stopped
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = self.save_cursor();
+    }
+}
+
+fn segment_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:08}.seg", number))
+}
+
+fn read_cursor(dir: &Path) -> Option<Position> {
+    let text = std::fs::read_to_string(dir.join("cursor")).ok()?;
+    let (segment, offset) = text.trim().split_once(' ')?;
+    Some(Position { segment: segment.parse().ok()?, offset: offset.parse().ok()? })
+}
+
+// Complete lines from `offset` to the end of the segment
+fn count_records(file: &Path, offset: u64) -> std::io::Result<usize> {
+    let mut file = File::open(file)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut records = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 || line.last() != Some(&b'\n') {
+            return Ok(records);
+        }
+        records += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topics(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|e| e.topic.as_str()).collect()
+    }
+
+    #[test]
+    fn records_stay_until_acknowledged() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+        let mut spool = Spool::new(dir, "test", 1 << 20, 0).unwrap();
+        for topic in ["a", "b", "c"] {
+            spool.push(topic, topic.as_bytes()).unwrap();
+        }
+
+        let entries = spool.next(2).unwrap();
+        assert_eq!(topics(&entries), ["a", "b"]);
+        assert_eq!(entries[0].payload, b"a");
+        assert_eq!(spool.pending(), 3);
+
+        // Out of order: nothing before the unacknowledged "a" is released
+        spool.ack(entries[1].id).unwrap();
+        assert_eq!(spool.pending(), 3);
+        spool.ack(entries[0].id).unwrap();
+        assert_eq!(spool.pending(), 1);
+
+        let entries = spool.next(5).unwrap();
+        assert_eq!(topics(&entries), ["c"]);
+        spool.ack(entries[0].id).unwrap();
+        assert_eq!(spool.pending(), 0);
+        assert!(spool.next(5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rewind_and_release_hand_records_out_again() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+        let mut spool = Spool::new(dir, "test", 1 << 20, 0).unwrap();
+        for topic in ["a", "b", "c"] {
+            spool.push(topic, b"x").unwrap();
+        }
+
+        let entries = spool.next(3).unwrap();
+        spool.release(entries[1].id);
+        assert_eq!(topics(&spool.next(3).unwrap()), ["b", "c"]);
+
+        spool.rewind();
+        assert_eq!(topics(&spool.next(3).unwrap()), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn reopening_keeps_undelivered_records() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+        {
+            let mut spool = Spool::new(dir, "test", 1 << 20, 0).unwrap();
+            for topic in ["a", "b", "c"] {
+                spool.push(topic, b"x").unwrap();
+            }
+            let entries = spool.next(3).unwrap();
+            spool.ack(entries[0].id).unwrap();
+        }
+
+        let mut spool = Spool::new(dir, "test", 1 << 20, 0).unwrap();
+        assert_eq!(spool.pending(), 2);
+        spool.push("d", b"x").unwrap();
+        let entries = spool.next(5).unwrap();
+        assert_eq!(topics(&entries), ["b", "c", "d"]);
+        for entry in &entries {
+            spool.ack(entry.id).unwrap();
+        }
+        assert_eq!(spool.pending(), 0);
+    }
+
+    #[test]
+    fn drained_segments_are_deleted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+        let mut spool = Spool::new(dir, "test", 800, 0).unwrap();
+        for index in 0..20 {
+            spool.push(&format!("t{}", index), b"x").unwrap();
+        }
+        let files = |dir: &str| std::fs::read_dir(Path::new(dir).join("test")).unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "seg"))
+            .count();
+        assert!(files(dir) > 1);
+
+        for entry in spool.next(20).unwrap() {
+            spool.ack(entry.id).unwrap();
+        }
+        assert_eq!(files(dir), 0);
+    }
+
+    #[test]
+    fn size_limit_drops_oldest_segments() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+        let mut spool = Spool::new(dir, "test", 800, 0).unwrap();
+        for index in 0..100 {
+            spool.push(&format!("t{}", index), b"x").unwrap();
+        }
+        let entries = spool.next(100).unwrap();
+        assert!(entries.len() < 100);
+        assert_eq!(entries.last().unwrap().topic, "t99");
+        assert_eq!(entries.len(), spool.pending());
+        let disk: u64 = std::fs::read_dir(Path::new(dir).join("test")).unwrap()
+            .map(|e| e.unwrap().metadata().unwrap().len())
+            .sum();
+        assert!(disk <= 800 + 16);
+    }
+
+    #[test]
+    fn expired_records_are_skipped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+        std::fs::create_dir_all(Path::new(dir).join("test")).unwrap();
+        let old = chrono::Local::now().timestamp() - 100;
+        std::fs::write(
+            segment_path(&Path::new(dir).join("test"), 0),
+            format!("{{\"time\":{},\"topic\":\"old\",\"payload\":\"x\"}}\nnot json\n", old),
+        ).unwrap();
+
+        let mut spool = Spool::new(dir, "test", 1 << 20, 60).unwrap();
+        spool.push("new", b"x").unwrap();
+        assert_eq!(spool.pending(), 3);
+        assert_eq!(topics(&spool.next(5).unwrap()), ["new"]);
+        assert_eq!(spool.pending(), 1);
+    }
+
+    #[test]
+    fn binary_payloads_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+        let mut spool = Spool::new(dir, "test", 1 << 20, 0).unwrap();
+        spool.push("raw", &[0x00, 0xFF, 0x80, 0x0A]).unwrap();
+        assert_eq!(spool.next(1).unwrap()[0].payload, [0x00, 0xFF, 0x80, 0x0A]);
+    }
+}
@@ -0,0 +1,127 @@
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use std::io;
+use std::os::unix::io::{AsRawFd, BorrowedFd};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::sleep;
+use tokio_serial::SerialStream;
+
+// Linux `struct serial_rs485` (include/uapi/linux/serial.h)
+#[repr(C)]
+struct SerialRs485 {
+    flags: u32,
+    delay_rts_before_send: u32,
+    delay_rts_after_send: u32,
+    padding: [u32; 5],
+}
+
+const SER_RS485_ENABLED: u32 = 1 << 0;
+const SER_RS485_RTS_ON_SEND: u32 = 1 << 1;
+const SER_RS485_RTS_AFTER_SEND: u32 = 1 << 2;
+
+// Who switches a half-duplex transceiver between transmit and receive
+#[derive(Debug, Clone, PartialEq)]
+pub enum DirectionMode {
+    // The transceiver or adapter switches by itself
+    Auto,
+    // The UART driver drives RTS as driver enable (TIOCSRS485)
+    Rts { rts_on_send: bool },
+    // A GPIO line is driven around each transmit
+    Gpio { chip: String, line: u32, active_low: bool },
+}
+
+// UCI serial options `direction`, `rts_on_send`, `gpio_chip`, `gpio_line`,
+// `gpio_active_low`, `delay_before_send` and `delay_after_send`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectionConfig {
+    pub mode: DirectionMode,
+    pub delay_before: Duration,
+    pub delay_after: Duration,
+}
+
+// Transmit direction control for an opened port
+pub struct Direction {
+    line: Option<LineHandle>,
+    active: u8,
+    delay_before: Duration,
+    delay_after: Duration,
+}
+
+impl Direction {
+    pub fn setup(port: &SerialStream, config: &DirectionConfig) -> io::Result<Direction> {
+        let mut direction = Direction {
+            line: None,
+            active: 1,
+            delay_before: config.delay_before,
+            delay_after: config.delay_after,
+        };
+
+        match &config.mode {
+            DirectionMode::Auto => {}
+            DirectionMode::Rts { rts_on_send } => {
+                let rs485 = SerialRs485 {
+                    flags: SER_RS485_ENABLED
+                        | if *rts_on_send { SER_RS485_RTS_ON_SEND } else { SER_RS485_RTS_AFTER_SEND },
+                    delay_rts_before_send: config.delay_before.as_millis() as u32,
+                    delay_rts_after_send: config.delay_after.as_millis() as u32,
+                    padding: [0; 5],
+                };
+                // SAFETY: the fd is an open tty and `rs485` matches the kernel layout
+                if unsafe { libc::ioctl(port.as_raw_fd(), libc::TIOCSRS485, &rs485) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // The driver applies the delays itself
+                direction.delay_before = Duration::ZERO;
+                direction.delay_after = Duration::ZERO;
+            }
+            DirectionMode::Gpio { chip, line, active_low } => {
+                let idle = if *active_low { 1 } else { 0 };
+                let handle = Chip::new(chip)
+                    .and_then(|mut chip| chip.get_line(*line))
+                    .and_then(|line| line.request(LineRequestFlags::OUTPUT, idle, "rs485:de"))
+                    .map_err(io::Error::other)?;
+                direction.line = Some(handle);
+                direction.active = 1 - idle;
+            }
+        }
+        Ok(direction)
+    }
+
+    // Write with the transmitter enabled until the last bit has left the UART
+    pub async fn write(&self, port: &mut SerialStream, data: &[u8]) -> io::Result<()> {
+        let Some(line) = &self.line else {
+            return port.write_all(data).await;
+        };
+
+        line.set_value(self.active).map_err(io::Error::other)?;
+        if !self.delay_before.is_zero() {
+            sleep(self.delay_before).await;
+        }
+        let result = match port.write_all(data).await {
+            Ok(_) => drain(port).await,
+            Err(e) => Err(e),
+        };
+        if !self.delay_after.is_zero() {
+            sleep(self.delay_after).await;
+        }
+        // Always hand the bus back, even after a failed write
+        line.set_value(1 - self.active).map_err(io::Error::other)?;
+        result
+    }
+}
+
+// Wait until the output queue of the tty is transmitted. The blocking call runs on a
+// duplicate of the fd, so it never touches another file if the port is closed meanwhile
+async fn drain(port: &SerialStream) -> io::Result<()> {
+    // SAFETY: the port's fd is open while `port` is borrowed
+    let fd = unsafe { BorrowedFd::borrow_raw(port.as_raw_fd()) }.try_clone_to_owned()?;
+    tokio::task::spawn_blocking(move || {
+        if unsafe { libc::tcdrain(fd.as_raw_fd()) } < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    })
+    .await
+    .map_err(io::Error::other)?
+}
@@ -0,0 +1,44 @@
+// RS485 line handling shared by rs485-module and rs485-modbus: transmit
+// direction control and the Modbus RTU inter-frame silence.
+
+pub mod direction;
+
+use std::time::Duration;
+use tokio_serial::{DataBits, Parity, StopBits};
+
+// 3.5 character silence at the given line settings; fixed at 1750us above 19200 baud
+pub fn inter_frame_gap(baudrate: u32, data_bits: DataBits, parity: Parity, stop_bits: StopBits) -> Duration {
+    if baudrate > 19200 {
+        return Duration::from_micros(1750);
+    }
+    let data_bits = match data_bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    };
+    let parity_bits = if parity == Parity::None { 0 } else { 1 };
+    let stop_bits = if stop_bits == StopBits::Two { 2 } else { 1 };
+    let char_bits = 1 + data_bits + parity_bits + stop_bits;
+    Duration::from_micros(3_500_000 * char_bits / baudrate.max(1) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gap_follows_the_character_length() {
+        // 8N1 is 10 bits, 8E1 and 8N2 are 11
+        assert_eq!(inter_frame_gap(9600, DataBits::Eight, Parity::None, StopBits::One), Duration::from_micros(3645));
+        assert_eq!(inter_frame_gap(9600, DataBits::Eight, Parity::Even, StopBits::One), Duration::from_micros(4010));
+        assert_eq!(inter_frame_gap(9600, DataBits::Eight, Parity::None, StopBits::Two), Duration::from_micros(4010));
+        assert_eq!(inter_frame_gap(19200, DataBits::Seven, Parity::Even, StopBits::One), Duration::from_micros(1822));
+    }
+
+    #[test]
+    fn gap_is_fixed_above_19200_baud() {
+        assert_eq!(inter_frame_gap(38400, DataBits::Eight, Parity::None, StopBits::One), Duration::from_micros(1750));
+        assert_eq!(inter_frame_gap(115200, DataBits::Eight, Parity::Even, StopBits::Two), Duration::from_micros(1750));
+    }
+}
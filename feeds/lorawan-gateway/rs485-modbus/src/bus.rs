@@ -0,0 +1,187 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep, Instant};
+use tokio_serial::SerialStream;
+use rs485_line::direction::Direction;
+
+use crate::error::ModbusError;
+use crate::frame;
+use crate::{Logger, SerialConfig};
+
+// Attempts for a request whose response is corrupted or incomplete
+const FRAME_ATTEMPTS: usize = 3;
+
+// Work queued for the task that owns the serial port
+enum Job {
+    Request {
+        slave: u8,
+        pdu: Vec<u8>,
+        timeout: Duration,
+        reply: oneshot::Sender<Result<Vec<u8>, ModbusError>>,
+    },
+    Write {
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<(), ModbusError>>,
+    },
+    Reopen {
+        serial: SerialConfig,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+}
+
+// Handle to the RS485 bus; every user of the port goes through this queue
+#[derive(Clone)]
+pub struct Bus {
+    tx: mpsc::Sender<Job>,
+}
+
+impl Bus {
+    // Open the port and start the bus owner task
+    pub async fn spawn(serial: &SerialConfig, logger: Arc<Logger>) -> Result<Bus, String> {
+        let port = open(serial).await?;
+        let (tx, rx) = mpsc::channel(32);
+        tokio::spawn(run(port, rx, frame::inter_frame_gap(serial), logger));
+        Ok(Bus { tx })
+    }
+
+    // Close the port and open it again with new settings; queued work keeps its order
+    pub async fn reopen(&self, serial: SerialConfig) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Job::Reopen { serial, reply }).await.map_err(|e| e.to_string())?;
+        rx.await.map_err(|_| stopped().to_string())?
+    }
+
+    // Send a request PDU to a slave and return the response PDU (function code onward)
+    pub async fn request(&self, slave: u8, pdu: Vec<u8>, timeout: Duration) -> Result<Vec<u8>, ModbusError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Job::Request { slave, pdu, timeout, reply }).await?;
+        rx.await.map_err(|_| stopped())?
+    }
+
+    // Transparent write of raw bytes between Modbus frames
+    pub async fn write(&self, data: Vec<u8>) -> Result<(), ModbusError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Job::Write { data, reply }).await?;
+        rx.await.map_err(|_| stopped())?
+    }
+
+    async fn send(&self, job: Job) -> Result<(), ModbusError> {
+        self.tx.send(job).await.map_err(|_| stopped())
+    }
+}
+
+fn stopped() -> ModbusError {
+    ModbusError::Other("RS485 bus task stopped".to_string())
+}
+
+fn closed() -> ModbusError {
+    ModbusError::Other("RS485 port is not open".to_string())
+}
+
+async fn open(serial: &SerialConfig) -> Result<(SerialStream, Direction), String> {
+    let port = crate::setup_serial(serial).await.map_err(|e| e.to_string())?;
+    let direction = Direction::setup(&port, &serial.direction)
+        .map_err(|e| format!("direction control ({:?}): {}", serial.direction.mode, e))?;
+    Ok((port, direction))
+}
+
+async fn run(port: (SerialStream, Direction), mut rx: mpsc::Receiver<Job>, mut gap: Duration, logger: Arc<Logger>) {
+    // None after a reopen failed; jobs are answered with an error until the next reopen
+    let mut port = Some(port);
+    let mut last_frame = Instant::now();
+
+    while let Some(job) = rx.recv().await {
+        if let Job::Reopen { serial, reply } = job {
+            // The device is opened exclusively, so the old port is closed first
+            port = None;
+            let result = open(&serial).await.map(|opened| {
+                port = Some(opened);
+                gap = frame::inter_frame_gap(&serial);
+            });
+            let _ = reply.send(result);
+            last_frame = Instant::now();
+            continue;
+        }
+        let Some((port, direction)) = port.as_mut() else {
+            match job {
+                Job::Request { reply, .. } => {
+                    let _ = reply.send(Err(closed()));
+                }
+                Job::Write { reply, .. } => {
+                    let _ = reply.send(Err(closed()));
+                }
+                Job::Reopen { .. } => {}
+            }
+            continue;
+        };
+
+        // Turnaround: keep the line silent for T3.5 after the previous frame
+        let idle = last_frame.elapsed();
+        if idle < gap {
+            sleep(gap - idle).await;
+        }
+        discard_input(port).await;
+
+        match job {
+            Job::Request { slave, pdu, timeout, reply } => {
+                let result = transact(port, direction, slave, &pdu, timeout, gap, &logger).await;
+                let _ = reply.send(result);
+            }
+            Job::Write { data, reply } => {
+                let result = direction.write(port, &data).await.map_err(|e| ModbusError::from_io(&e));
+                let _ = reply.send(result);
+            }
+            Job::Reopen { .. } => {}
+        }
+        last_frame = Instant::now();
+    }
+}
+
+// Drop late or unsolicited bytes so they are not taken as the next response
+async fn discard_input(port: &mut SerialStream) {
+    let mut buf = [0u8; 256];
+    while let Ok(Ok(n)) = tokio::time::timeout(Duration::ZERO, port.read(&mut buf)).await {
+        if n == 0 {
+            break;
+        }
+    }
+}
+
+async fn transact(
+    port: &mut SerialStream,
+    direction: &Direction,
+    slave: u8,
+    pdu: &[u8],
+    timeout: Duration,
+    gap: Duration,
+    logger: &Arc<Logger>,
+) -> Result<Vec<u8>, ModbusError> {
+    let mut request = vec![slave];
+    request.extend_from_slice(pdu);
+    let request = frame::with_crc(request);
+
+    let mut attempt = 1;
+    loop {
+        direction.write(port, &request).await.map_err(|e| ModbusError::from_io(&e))?;
+
+        // Broadcasts are never answered
+        if slave == 0 {
+            return Ok(Vec::new());
+        }
+
+        let result = frame::read_frame(port, timeout, gap)
+            .await
+            .and_then(|response| frame::validate_response(&response, slave, pdu[0]).map(|pdu| pdu.to_vec()));
+        match result {
+            Err(e @ (ModbusError::Crc | ModbusError::Framing(_))) if attempt < FRAME_ATTEMPTS => {
+                logger.log(&format!("Bad response frame from slave {} ({}), retrying {}/{}", slave, e, attempt, FRAME_ATTEMPTS - 1));
+                attempt += 1;
+                sleep(gap).await;
+                discard_input(port).await;
+            }
+            result => return result,
+        }
+    }
+}
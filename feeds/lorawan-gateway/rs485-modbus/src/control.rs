@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use crate::bus::Bus;
+use crate::{run_transaction, Config, Link, Logger, ModbusUplink, ProtocolConfig};
+
+// Local control socket, one JSON request/response per line
+pub const SOCKET_PATH: &str = "/var/run/rs485-modbus.sock";
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Params,
+}
+
+// Transaction parameters; anything left out comes from the protocol section
+#[derive(Debug, Default, Deserialize)]
+struct Params {
+    // Entry whose result `last_result` returns; the latest of any entry when left out
+    name: Option<String>,
+    slave: Option<u8>,
+    function_code: Option<u8>,
+    address: Option<u16>,
+    count: Option<u16>,
+    values: Option<Vec<u16>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Reply {
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// State shared between the main loop and the control socket
+pub struct Shared {
+    pub bus: Bus,
+    pub config: StdMutex<Config>,
+    pub mqtt_state: StdMutex<String>,
+    // Latest result of any entry, and the latest of each entry by name
+    pub last_result: StdMutex<Option<Value>>,
+    pub results: StdMutex<HashMap<String, Value>>,
+    pub started: Instant,
+}
+
+impl Shared {
+    pub fn new(bus: Bus, config: Config) -> Self {
+        Shared {
+            bus,
+            config: StdMutex::new(config),
+            mqtt_state: StdMutex::new("not_connect".to_string()),
+            last_result: StdMutex::new(None),
+            results: StdMutex::new(HashMap::new()),
+            started: Instant::now(),
+        }
+    }
+
+    // Remember a transaction result for `last_result`; the legacy text form is kept for the web UI
+    pub fn set_last_result(&self, uplink: &ModbusUplink) -> Value {
+        let Ok(mut value) = serde_json::to_value(uplink) else {
+            return Value::Null;
+        };
+        value["data"] = Value::String(uplink.data.clone());
+        if let Ok(mut results) = self.results.lock() {
+            results.insert(uplink.name.clone(), value.clone());
+        }
+        if let Ok(mut last) = self.last_result.lock() {
+            *last = Some(value.clone());
+        }
+        value
+    }
+}
+
+// Serve control requests until the listener fails
+pub async fn run(listener: UnixListener, shared: Arc<Shared>, logger: Arc<Logger>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let shared = shared.clone();
+                let logger = logger.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, &shared, &logger).await {
+                        logger.log(&format!("Control connection error: {}", e));
+                    }
+                });
+            }
+            Err(e) => {
+                logger.log(&format!("Control socket accept failed: {}", e));
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+async fn handle_connection(stream: UnixStream, shared: &Shared, logger: &Arc<Logger>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let id = request.id.clone();
+                match dispatch(request, shared, logger).await {
+                    Ok(result) => Reply { id, result: Some(result), error: None },
+                    Err(e) => Reply { id, result: None, error: Some(e) },
+                }
+            }
+            Err(e) => Reply { id: Value::Null, result: None, error: Some(format!("Invalid request: {}", e)) },
+        };
+        let mut json = serde_json::to_string(&reply).unwrap_or_default();
+        json.push('\n');
+        writer.write_all(json.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn dispatch(request: Request, shared: &Shared, logger: &Arc<Logger>) -> Result<Value, String> {
+    let config = shared.config.lock().map_err(|e| e.to_string())?.clone();
+
+    match request.method.as_str() {
+        "read" | "write" => {
+            let protocol = transaction_protocol(&request.method, &request.params, &config.protocol)?;
+            let uplink = run_transaction(Link::Bus(&shared.bus), &protocol, &config, "control").await;
+            logger.log(&format!("Control {} slave {} FC{:02}: {}", request.method, protocol.device_address,
+                protocol.function_code, if uplink.status == "ok" { uplink.data.clone() } else { uplink.status.clone() }));
+            Ok(shared.set_last_result(&uplink))
+        }
+        "status" => Ok(json!({
+            "uptime": shared.started.elapsed().as_secs(),
+            "serial": config.serial.device,
+            "baudrate": config.serial.baudrate,
+            "mqtt": shared.mqtt_state.lock().map_err(|e| e.to_string())?.clone(),
+            "polls": config.polls.iter().map(|p| p.name.clone()).collect::<Vec<_>>(),
+            "tcp_server": config.tcp_server.enabled,
+        })),
+        "last_result" => match &request.params.name {
+            Some(name) => Ok(shared.results.lock().map_err(|e| e.to_string())?.get(name).cloned().unwrap_or(Value::Null)),
+            None => Ok(shared.last_result.lock().map_err(|e| e.to_string())?.clone().unwrap_or(Value::Null)),
+        },
+        other => Err(format!("Unknown method: {}", other)),
+    }
+}
+
+// Merge request parameters over the protocol section
+fn transaction_protocol(method: &str, params: &Params, base: &ProtocolConfig) -> Result<ProtocolConfig, String> {
+    let mut protocol = base.clone();
+    if let Some(slave) = params.slave {
+        protocol.device_address = slave;
+    }
+    if let Some(function_code) = params.function_code {
+        protocol.function_code = function_code;
+    }
+    if let Some(address) = params.address {
+        protocol.register_address = address;
+    }
+    if let Some(count) = params.count {
+        protocol.data_length = count;
+    }
+    if let Some(values) = &params.values {
+        protocol.write_value = values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",");
+        protocol.standard_mode = true;
+        if params.count.is_none() {
+            protocol.data_length = values.len().max(1) as u16;
+        }
+    }
+
+    let is_read = (1..=4).contains(&protocol.function_code);
+    let is_write = matches!(protocol.function_code, 5 | 6 | 15 | 16);
+    match method {
+        "read" if !is_read => Err(format!("read requires function code 1-4, got {}", protocol.function_code)),
+        "write" if !is_write => Err(format!("write requires function code 5, 6, 15 or 16, got {}", protocol.function_code)),
+        _ => Ok(protocol),
+    }
+}
+
+// Client side of the socket: `rs485-modbus call <method> [params-json]`
+pub async fn call(method: &str, params: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let params: Value = match params {
+        Some(params) => serde_json::from_str(params)?,
+        None => json!({}),
+    };
+    let request = json!({ "id": 1, "method": method, "params": params });
+
+    let stream = UnixStream::connect(SOCKET_PATH)
+        .await
+        .map_err(|e| format!("rs485-modbus daemon not running ({}: {})", SOCKET_PATH, e))?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(format!("{}\n", request).as_bytes()).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    match lines.next_line().await? {
+        Some(reply) => {
+            println!("{}", reply);
+            Ok(())
+        }
+        None => Err("No reply from rs485-modbus".into()),
+    }
+}
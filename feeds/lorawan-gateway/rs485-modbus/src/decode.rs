@@ -0,0 +1,353 @@
+use serde::Serialize;
+
+use crate::report::PublishPolicy;
+
+// Register data types supported by point decoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataType {
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+    Str,
+    Bitfield,
+}
+
+impl DataType {
+    pub fn parse(value: &str) -> Option<DataType> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "u16" | "uint16" => Some(DataType::U16),
+            "i16" | "int16" => Some(DataType::I16),
+            "u32" | "uint32" => Some(DataType::U32),
+            "i32" | "int32" => Some(DataType::I32),
+            "f32" | "float32" | "float" => Some(DataType::F32),
+            "f64" | "float64" | "double" => Some(DataType::F64),
+            "string" | "str" => Some(DataType::Str),
+            "bitfield" | "bits" => Some(DataType::Bitfield),
+            _ => None,
+        }
+    }
+
+    // Number of 16-bit registers one value occupies
+    pub fn registers(&self, length: u16) -> usize {
+        match self {
+            DataType::U16 | DataType::I16 | DataType::Bitfield => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+            DataType::F64 => 4,
+            DataType::Str => length.max(1) as usize,
+        }
+    }
+}
+
+// Byte order of a value, "A" being its most significant byte
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteOrder {
+    Abcd,
+    Cdab,
+    Badc,
+    Dcba,
+}
+
+impl ByteOrder {
+    pub fn parse(value: &str) -> Option<ByteOrder> {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "ABCD" => Some(ByteOrder::Abcd),
+            "CDAB" => Some(ByteOrder::Cdab),
+            "BADC" => Some(ByteOrder::Badc),
+            "DCBA" => Some(ByteOrder::Dcba),
+            _ => None,
+        }
+    }
+
+    // Registers are sent least significant word first
+    fn word_swap(&self) -> bool {
+        matches!(self, ByteOrder::Cdab | ByteOrder::Dcba)
+    }
+
+    // Each register carries its low byte first
+    fn byte_swap(&self) -> bool {
+        matches!(self, ByteOrder::Badc | ByteOrder::Dcba)
+    }
+}
+
+// One named value inside a poll block (UCI `config point` sections)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointConfig {
+    pub name: String,
+    pub poll: String,
+    pub address: u16,
+    pub data_type: DataType,
+    pub order: ByteOrder,
+    pub length: u16,
+    pub bit: Option<u8>,
+    pub scale: f64,
+    pub offset: f64,
+    pub unit: String,
+    pub publish: PublishPolicy,
+    // Publish at least this often (seconds) even without change; 0 disables
+    pub max_silence: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum PointValue {
+    Number(f64),
+    Text(String),
+    Bits(Vec<bool>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedPoint {
+    pub name: String,
+    pub value: PointValue,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub unit: String,
+}
+
+// Arrange the bytes of a multi-register value in ABCD order
+fn ordered_bytes(registers: &[u16], order: ByteOrder) -> Vec<u8> {
+    let mut words: Vec<u16> = registers.to_vec();
+    if order.word_swap() {
+        words.reverse();
+    }
+    words
+        .iter()
+        .flat_map(|word| {
+            let bytes = word.to_be_bytes();
+            if order.byte_swap() {
+                [bytes[1], bytes[0]]
+            } else {
+                bytes
+            }
+        })
+        .collect()
+}
+
+// Apply scale and offset; divide by 1/scale when it is integral to keep 0.1-style factors exact
+fn engineering_value(raw: f64, scale: f64, offset: f64) -> f64 {
+    let divisor = 1.0 / scale;
+    let scaled = if scale.abs() < 1.0 && (divisor - divisor.round()).abs() < 1e-9 {
+        raw / divisor.round()
+    } else {
+        raw * scale
+    };
+    scaled + offset
+}
+
+// Decode one point from the registers it covers (exactly `data_type.registers()` words)
+pub fn decode_registers(point: &PointConfig, registers: &[u16]) -> Result<PointValue, String> {
+    let needed = point.data_type.registers(point.length);
+    if registers.len() < needed {
+        return Err(format!("{}: needs {} registers, got {}", point.name, needed, registers.len()));
+    }
+    let bytes = ordered_bytes(&registers[..needed], point.order);
+
+    let raw = match point.data_type {
+        DataType::U16 => u16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+        DataType::I16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+        DataType::U32 => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        DataType::I32 => i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        DataType::F32 => {
+            // Go through the shortest decimal form so 230.1f32 does not become 230.100006...
+            let value = f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            if !value.is_finite() {
+                return Err(format!("{}: invalid float value", point.name));
+            }
+            value.to_string().parse::<f64>().unwrap_or(value as f64)
+        }
+        DataType::F64 => {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(&bytes[..8]);
+            let value = f64::from_be_bytes(buf);
+            if !value.is_finite() {
+                return Err(format!("{}: invalid float value", point.name));
+            }
+            value
+        }
+        DataType::Str => {
+            let text = String::from_utf8_lossy(&bytes);
+            return Ok(PointValue::Text(
+                text.trim_end_matches(|c: char| c == '\0' || c.is_whitespace()).to_string(),
+            ));
+        }
+        DataType::Bitfield => {
+            let word = u16::from_be_bytes([bytes[0], bytes[1]]);
+            return Ok(match point.bit {
+                Some(bit) if bit < 16 => PointValue::Number(((word >> bit) & 1) as f64),
+                Some(bit) => return Err(format!("{}: bit {} out of range", point.name, bit)),
+                None => PointValue::Bits((0..16).map(|bit| (word >> bit) & 1 == 1).collect()),
+            });
+        }
+    };
+
+    Ok(PointValue::Number(engineering_value(raw, point.scale, point.offset)))
+}
+
+// Decode every point that falls inside a register block starting at `start`
+pub fn decode_block(points: &[PointConfig], start: u16, registers: &[u16]) -> (Vec<DecodedPoint>, Vec<String>) {
+    let mut decoded = Vec::new();
+    let mut errors = Vec::new();
+
+    for point in points {
+        let index = match point.address.checked_sub(start) {
+            Some(index) if (index as usize) < registers.len() => index as usize,
+            _ => {
+                errors.push(format!("{}: address {} outside polled block", point.name, point.address));
+                continue;
+            }
+        };
+        match decode_registers(point, &registers[index..]) {
+            Ok(value) => decoded.push(DecodedPoint {
+                name: point.name.clone(),
+                value,
+                unit: point.unit.clone(),
+            }),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (decoded, errors)
+}
+
+// Coil/discrete input blocks: every point maps to a single bit
+pub fn decode_bits(points: &[PointConfig], start: u16, bits: &[bool]) -> (Vec<DecodedPoint>, Vec<String>) {
+    let mut decoded = Vec::new();
+    let mut errors = Vec::new();
+
+    for point in points {
+        match point.address.checked_sub(start).and_then(|index| bits.get(index as usize)) {
+            Some(bit) => decoded.push(DecodedPoint {
+                name: point.name.clone(),
+                value: PointValue::Number(if *bit { 1.0 } else { 0.0 }),
+                unit: point.unit.clone(),
+            }),
+            None => errors.push(format!("{}: address {} outside polled block", point.name, point.address)),
+        }
+    }
+
+    (decoded, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(data_type: DataType, order: ByteOrder) -> PointConfig {
+        PointConfig {
+            name: "p".to_string(),
+            poll: "block".to_string(),
+            address: 0,
+            data_type,
+            order,
+            length: 0,
+            bit: None,
+            scale: 1.0,
+            offset: 0.0,
+            unit: String::new(),
+            publish: PublishPolicy::Always,
+            max_silence: 0,
+        }
+    }
+
+    fn number(point: &PointConfig, registers: &[u16]) -> f64 {
+        match decode_registers(point, registers) {
+            Ok(PointValue::Number(value)) => value,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn float32_in_every_byte_order() {
+        // 230.1 is 0x4366199A
+        let cases = [
+            (ByteOrder::Abcd, [0x4366, 0x199A]),
+            (ByteOrder::Cdab, [0x199A, 0x4366]),
+            (ByteOrder::Badc, [0x6643, 0x9A19]),
+            (ByteOrder::Dcba, [0x9A19, 0x6643]),
+        ];
+        for (order, registers) in cases {
+            assert_eq!(number(&point(DataType::F32, order), &registers), 230.1, "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn integers_in_every_byte_order() {
+        // 0x12345678
+        let cases = [
+            (ByteOrder::Abcd, [0x1234, 0x5678]),
+            (ByteOrder::Cdab, [0x5678, 0x1234]),
+            (ByteOrder::Badc, [0x3412, 0x7856]),
+            (ByteOrder::Dcba, [0x7856, 0x3412]),
+        ];
+        for (order, registers) in cases {
+            assert_eq!(number(&point(DataType::U32, order), &registers), 305419896.0, "{:?}", order);
+        }
+        assert_eq!(number(&point(DataType::U16, ByteOrder::Badc), &[0x3412]), 0x1234 as f64);
+    }
+
+    #[test]
+    fn signed_values() {
+        assert_eq!(number(&point(DataType::I16, ByteOrder::Abcd), &[0xFF38]), -200.0);
+        assert_eq!(number(&point(DataType::U16, ByteOrder::Abcd), &[0xFF38]), 65336.0);
+        assert_eq!(number(&point(DataType::I32, ByteOrder::Abcd), &[0xFFFF, 0xFFFE]), -2.0);
+        assert_eq!(number(&point(DataType::I32, ByteOrder::Cdab), &[0xFFFE, 0xFFFF]), -2.0);
+    }
+
+    #[test]
+    fn float64_and_invalid_floats() {
+        // 1.5 is 0x3FF8000000000000
+        assert_eq!(number(&point(DataType::F64, ByteOrder::Abcd), &[0x3FF8, 0, 0, 0]), 1.5);
+        assert_eq!(number(&point(DataType::F64, ByteOrder::Cdab), &[0, 0, 0, 0x3FF8]), 1.5);
+        // NaN
+        assert!(decode_registers(&point(DataType::F32, ByteOrder::Abcd), &[0x7FC0, 0]).is_err());
+    }
+
+    #[test]
+    fn scale_and_offset() {
+        let mut voltage = point(DataType::U16, ByteOrder::Abcd);
+        voltage.scale = 0.1;
+        assert_eq!(number(&voltage, &[2301]), 230.1);
+
+        let mut temperature = point(DataType::I16, ByteOrder::Abcd);
+        temperature.scale = 0.01;
+        temperature.offset = -40.0;
+        assert_eq!(number(&temperature, &[6250]), 22.5);
+
+        let mut energy = point(DataType::U32, ByteOrder::Abcd);
+        energy.scale = 2.0;
+        energy.offset = 1.0;
+        assert_eq!(number(&energy, &[0x0001, 0x0000]), 131073.0);
+    }
+
+    #[test]
+    fn short_registers_strings_and_bits() {
+        assert!(decode_registers(&point(DataType::U32, ByteOrder::Abcd), &[0x1234]).is_err());
+
+        let mut text = point(DataType::Str, ByteOrder::Abcd);
+        text.length = 3;
+        assert_eq!(
+            decode_registers(&text, &[0x4142, 0x4344, 0x4500]),
+            Ok(PointValue::Text("ABCDE".to_string()))
+        );
+
+        let mut bit = point(DataType::Bitfield, ByteOrder::Abcd);
+        bit.bit = Some(3);
+        assert_eq!(decode_registers(&bit, &[0x0008]), Ok(PointValue::Number(1.0)));
+        bit.bit = Some(16);
+        assert!(decode_registers(&bit, &[0x0008]).is_err());
+    }
+
+    #[test]
+    fn block_offsets() {
+        let mut second = point(DataType::U16, ByteOrder::Abcd);
+        second.address = 101;
+        let mut outside = point(DataType::U16, ByteOrder::Abcd);
+        outside.address = 99;
+        let (decoded, errors) = decode_block(&[second, outside], 100, &[1, 2]);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].value, PointValue::Number(2.0));
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+use tokio_modbus::prelude::*;
+
+// Why a Modbus transaction failed, as reported in uplinks and control replies
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModbusError {
+    Exception(u8),
+    Crc,
+    Timeout,
+    Framing(String),
+    Other(String),
+}
+
+impl ModbusError {
+    pub fn status(&self) -> &'static str {
+        match self {
+            ModbusError::Exception(_) => "exception",
+            ModbusError::Crc => "crc_error",
+            ModbusError::Timeout => "timeout",
+            ModbusError::Framing(_) => "framing_error",
+            ModbusError::Other(_) => "error",
+        }
+    }
+
+    pub fn exception_code(&self) -> Option<u8> {
+        match self {
+            ModbusError::Exception(code) => Some(*code),
+            _ => None,
+        }
+    }
+
+    // Map an error returned by a transaction onto its class
+    pub fn classify(e: &(dyn std::error::Error + Send + Sync + 'static)) -> ModbusError {
+        if let Some(e) = e.downcast_ref::<ModbusError>() {
+            return e.clone();
+        }
+        if let Some(code) = e.downcast_ref::<Exception>() {
+            return ModbusError::Exception(u8::from(*code));
+        }
+        if let Some(e) = e.downcast_ref::<tokio_modbus::Error>() {
+            // Protocol errors are responses that do not match the request
+            return match e {
+                tokio_modbus::Error::Transport(io) => ModbusError::from_io(io),
+                _ => ModbusError::Framing(e.to_string()),
+            };
+        }
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            return ModbusError::from_io(io);
+        }
+        ModbusError::Other(e.to_string())
+    }
+
+    // The RTU codec reports bad checksums and truncated frames as InvalidData
+    pub fn from_io(e: &std::io::Error) -> ModbusError {
+        match e.kind() {
+            std::io::ErrorKind::TimedOut => ModbusError::Timeout,
+            std::io::ErrorKind::InvalidData if e.to_string().to_ascii_lowercase().contains("crc") => ModbusError::Crc,
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => ModbusError::Framing(e.to_string()),
+            _ => ModbusError::Other(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ModbusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModbusError::Exception(code) => write!(f, "Modbus exception {:02X}: {}", code, exception_name(*code)),
+            ModbusError::Crc => write!(f, "CRC error in response"),
+            ModbusError::Timeout => write!(f, "No response from slave"),
+            ModbusError::Framing(message) => write!(f, "Framing error: {}", message),
+            ModbusError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ModbusError {}
+
+pub fn exception_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "Illegal function",
+        0x02 => "Illegal data address",
+        0x03 => "Illegal data value",
+        0x04 => "Server device failure",
+        0x05 => "Acknowledge",
+        0x06 => "Server device busy",
+        0x07 => "Negative acknowledge",
+        0x08 => "Memory parity error",
+        0x0A => "Gateway path unavailable",
+        0x0B => "Gateway target device failed to respond",
+        _ => "Unknown exception",
+    }
+}
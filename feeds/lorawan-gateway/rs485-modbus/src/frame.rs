@@ -0,0 +1,180 @@
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::ModbusError;
+use crate::SerialConfig;
+
+// Largest RTU frame allowed by the Modbus serial line spec
+const MAX_FRAME_LEN: usize = 256;
+
+// CRC-16 Modbus calculation
+pub fn crc16_modbus(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            if crc & 0x0001 != 0 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+// Append the CRC (low byte first) to an address + PDU frame
+pub fn with_crc(mut frame: Vec<u8>) -> Vec<u8> {
+    let crc = crc16_modbus(&frame);
+    frame.push((crc & 0xFF) as u8);
+    frame.push((crc >> 8) as u8);
+    frame
+}
+
+// Length of the PDU that follows a Modbus TCP (MBAP) header: transaction id, protocol id,
+// length and unit id; None for a header that is not Modbus or out of range
+pub fn mbap_pdu_length(header: &[u8; 7]) -> Option<usize> {
+    let protocol_id = u16::from_be_bytes([header[2], header[3]]);
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    (protocol_id == 0 && (2..=254).contains(&length)).then(|| length - 1)
+}
+
+// MBAP response carrying `pdu` for the request with this header
+pub fn mbap_response(header: &[u8; 7], pdu: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(7 + pdu.len());
+    frame.extend_from_slice(&header[..4]);
+    frame.extend_from_slice(&((pdu.len() + 1) as u16).to_be_bytes());
+    frame.push(header[6]);
+    frame.extend_from_slice(pdu);
+    frame
+}
+
+// Silence that delimits RTU frames: 3.5 characters at the port's line settings, but never
+// less than the configured gap, as USB adapters and converters deliver bytes in bursts
+pub fn inter_frame_gap(serial: &SerialConfig) -> Duration {
+    rs485_line::inter_frame_gap(serial.baudrate, serial.databit, serial.checkbit, serial.stopbit).max(serial.frame_gap)
+}
+
+// Collect one frame: wait up to `timeout` for the first byte, then read until the line is silent for `gap`
+pub async fn read_frame<R: AsyncRead + Unpin>(port: &mut R, timeout: Duration, gap: Duration) -> Result<Vec<u8>, ModbusError> {
+    let mut frame = Vec::new();
+    let mut buf = [0u8; MAX_FRAME_LEN];
+
+    loop {
+        let wait = if frame.is_empty() { timeout } else { gap };
+        match tokio::time::timeout(wait, port.read(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => {
+                frame.extend_from_slice(&buf[..n]);
+                if frame.len() > MAX_FRAME_LEN {
+                    return Err(ModbusError::Framing(format!("frame exceeds {} bytes", MAX_FRAME_LEN)));
+                }
+            }
+            Ok(Err(e)) if frame.is_empty() => return Err(ModbusError::from_io(&e)),
+            _ if frame.is_empty() => return Err(ModbusError::Timeout),
+            _ => return Ok(frame),
+        }
+    }
+}
+
+// Check a response against its request and return the PDU (function code onward, CRC stripped)
+pub fn validate_response(frame: &[u8], slave: u8, function_code: u8) -> Result<&[u8], ModbusError> {
+    let len = frame.len();
+    if len < 5 {
+        return Err(ModbusError::Framing(format!("response too short: {:02X?}", frame)));
+    }
+    let crc = crc16_modbus(&frame[..len - 2]);
+    if frame[len - 2..] != [(crc & 0xFF) as u8, (crc >> 8) as u8] {
+        return Err(ModbusError::Crc);
+    }
+    if frame[0] != slave {
+        return Err(ModbusError::Framing(format!("response from slave {}, expected {}", frame[0], slave)));
+    }
+    if frame[1] == function_code | 0x80 {
+        return Err(ModbusError::Exception(frame[2]));
+    }
+    if frame[1] != function_code {
+        return Err(ModbusError::Framing(format!("response function code {:02}, expected {:02}", frame[1], function_code)));
+    }
+
+    // Reads carry a byte count, writes echo address and value/quantity
+    let expected = match function_code {
+        1..=4 => 5 + frame[2] as usize,
+        _ => 8,
+    };
+    if len != expected {
+        return Err(ModbusError::Framing(format!("expected {} bytes, got {}", expected, len)));
+    }
+    Ok(&frame[1..len - 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc() {
+        // Read 10 holding registers from slave 1, and write 3 to register 1
+        assert_eq!(with_crc(vec![0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD]);
+        assert_eq!(with_crc(vec![0x01, 0x06, 0x00, 0x01, 0x00, 0x03]), [0x01, 0x06, 0x00, 0x01, 0x00, 0x03, 0x98, 0x0B]);
+        assert_eq!(crc16_modbus(&[]), 0xFFFF);
+    }
+
+    #[test]
+    fn read_response() {
+        let frame = with_crc(vec![0x01, 0x03, 0x04, 0x00, 0x0A, 0x01, 0x02]);
+        assert_eq!(validate_response(&frame, 1, 3), Ok(&[0x03, 0x04, 0x00, 0x0A, 0x01, 0x02][..]));
+
+        // Byte count disagreeing with the frame length
+        let frame = with_crc(vec![0x01, 0x03, 0x04, 0x00, 0x0A]);
+        assert!(matches!(validate_response(&frame, 1, 3), Err(ModbusError::Framing(_))));
+    }
+
+    #[test]
+    fn write_response() {
+        let frame = with_crc(vec![0x11, 0x10, 0x00, 0x01, 0x00, 0x02]);
+        assert_eq!(validate_response(&frame, 0x11, 0x10), Ok(&[0x10, 0x00, 0x01, 0x00, 0x02][..]));
+    }
+
+    #[test]
+    fn exception_response() {
+        let frame = with_crc(vec![0x01, 0x83, 0x02]);
+        assert_eq!(validate_response(&frame, 1, 3), Err(ModbusError::Exception(2)));
+        // The exception of another function is a mismatch
+        assert!(matches!(validate_response(&frame, 1, 4), Err(ModbusError::Framing(_))));
+    }
+
+    #[test]
+    fn bad_frames() {
+        let mut frame = with_crc(vec![0x01, 0x03, 0x02, 0x00, 0x0A]);
+        assert!(matches!(validate_response(&frame, 2, 3), Err(ModbusError::Framing(_))));
+        frame[4] ^= 0x01;
+        assert_eq!(validate_response(&frame, 1, 3), Err(ModbusError::Crc));
+        assert!(matches!(validate_response(&[0x01, 0x03, 0x00], 1, 3), Err(ModbusError::Framing(_))));
+    }
+
+    #[test]
+    fn mbap_to_rtu_and_back() {
+        // Transaction 0x1234, unit 1: read 2 holding registers from address 0x006B
+        let header = [0x12, 0x34, 0x00, 0x00, 0x00, 0x06, 0x01];
+        let request = [0x03, 0x00, 0x6B, 0x00, 0x02];
+        assert_eq!(mbap_pdu_length(&header), Some(request.len()));
+
+        let mut rtu = vec![header[6]];
+        rtu.extend_from_slice(&request);
+        assert_eq!(with_crc(rtu)[..6], [0x01, 0x03, 0x00, 0x6B, 0x00, 0x02]);
+
+        let reply = with_crc(vec![0x01, 0x03, 0x04, 0x02, 0x2B, 0x00, 0x64]);
+        let pdu = validate_response(&reply, 1, 3).unwrap();
+        assert_eq!(
+            mbap_response(&header, pdu),
+            [0x12, 0x34, 0x00, 0x00, 0x00, 0x07, 0x01, 0x03, 0x04, 0x02, 0x2B, 0x00, 0x64]
+        );
+    }
+
+    #[test]
+    fn invalid_mbap_headers() {
+        assert_eq!(mbap_pdu_length(&[0, 1, 0x00, 0x01, 0x00, 0x06, 0x01]), None);
+        assert_eq!(mbap_pdu_length(&[0, 1, 0x00, 0x00, 0x00, 0x01, 0x01]), None);
+        assert_eq!(mbap_pdu_length(&[0, 1, 0x00, 0x00, 0x01, 0x00, 0x01]), None);
+    }
+}
@@ -1,23 +1,33 @@
-use chrono::Local;
-use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS, Transport};
-use rumqttc::tokio_rustls::rustls::ClientConfig as RustlsClientConfig;
-use rustls_pemfile::{certs, pkcs8_private_keys};
+use chrono::{Local, SecondsFormat};
+use rumqttc::QoS;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::process::Command;
+use std::os::unix::fs::PermissionsExt;
 use std::sync::{Arc, Mutex as StdMutex};
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::time::sleep;
 use tokio_serial::{DataBits, Parity, StopBits, SerialPortBuilderExt};
 use tokio_modbus::prelude::*;
-use std::path::Path;
+use mqtt_link::spool;
+use rs485_line::direction;
 
-// Global constants for file paths
-const trigger_read_path: &str = "/tmp/rs485/modbus_read";
-const trigger_write_path: &str = "/tmp/rs485/modbus_write";
-const result_path: &str = "/tmp/rs485/modbus_result";
+mod bus;
+mod control;
+mod decode;
+mod error;
+mod frame;
+mod pdu;
+mod report;
+mod schedule;
+mod tcp_server;
+use decode::{ByteOrder, DataType, DecodedPoint, PointConfig};
+use error::ModbusError;
+
+// Minimum RTU frame gap in ms (UCI serial option `frame_gap`), as libmodbus and mbpoll allow for
+const DEFAULT_FRAME_GAP: u64 = 3;
 
 // Configuration Structures
 #[derive(Debug, Clone, PartialEq)]
@@ -25,27 +35,23 @@ struct Config {
     mqtt: MqttConfig,
     serial: SerialConfig,
     protocol: ProtocolConfig,
+    polls: Vec<PollConfig>,
+    schedules: Vec<ScheduleConfig>,
+    tcp_server: TcpServerConfig,
+    buffer: BufferConfig,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 struct MqttConfig {
     enabled: bool,
-    transport: String,
-    host: String,
-    port: u16,
-    username: Option<String>,
-    password: Option<String>,
-    client_id: String,
-    keepalive: u64,
+    broker: mqtt_link::Broker,
     uplink_topic: String,
     downlink_topic: String,
+    response_topic: String,
     qos_level: QoS,
     reconnect_delay: u64,
-    auth_mode: String,
-    ca_cert: Option<String>,
-    client_cert: Option<String>,
-    client_key: Option<String>,
     token: Option<String>,
+    gateway_id: String,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -57,6 +63,9 @@ struct SerialConfig {
     checkbit: Parity,
     flowcontrol: tokio_serial::FlowControl,
     timeout: Duration,
+    // Shortest silence that ends an RTU frame; T3.5 applies when longer
+    frame_gap: Duration,
+    direction: direction::DirectionConfig,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -70,12 +79,204 @@ struct ProtocolConfig {
     work_mode: String,
     poll_interval: u64,
     timeout: u64,
+    payload_format: String,
+}
+
+// Modbus TCP server in front of the RTU bus
+#[derive(Debug, Clone, PartialEq)]
+struct TcpServerConfig {
+    enabled: bool,
+    bind: String,
+    port: u16,
+}
+
+// Store-and-forward buffer for uplinks while the broker is unreachable
+#[derive(Debug, Clone, PartialEq)]
+struct BufferConfig {
+    enabled: bool,
+    path: String,
+    max_size: u64,
+    max_age: u64,
+}
+
+// One entry of the poll table (UCI `config poll` sections)
+#[derive(Debug, Clone, PartialEq)]
+struct PollConfig {
+    name: String,
+    transport: String,
+    host: String,
+    port: u16,
+    slave_id: u8,
+    function_code: u8,
+    register_address: u16,
+    count: u16,
+    interval: u64,
+    timeout: u64,
+    topic: String,
+    points: Vec<PointConfig>,
+}
+
+impl PollConfig {
+    // Protocol settings used by read_modbus_data for this entry
+    fn protocol(&self, base: &ProtocolConfig) -> ProtocolConfig {
+        ProtocolConfig {
+            device_address: self.slave_id,
+            function_code: self.function_code,
+            register_address: self.register_address,
+            data_length: self.count,
+            timeout: self.timeout,
+            ..base.clone()
+        }
+    }
+}
+
+// Scheduled write (UCI `config schedule` sections), run every `interval` seconds or on `cron`
+#[derive(Debug, Clone, PartialEq)]
+struct ScheduleConfig {
+    name: String,
+    slave_id: u8,
+    function_code: u8,
+    address: u16,
+    values: Vec<u16>,
+    interval: u64,
+    cron: Option<schedule::Cron>,
+    timeout: u64,
+    topic: String,
+}
+
+impl ScheduleConfig {
+    fn protocol(&self, base: &ProtocolConfig) -> ProtocolConfig {
+        ProtocolConfig {
+            device_address: self.slave_id,
+            function_code: self.function_code,
+            register_address: self.address,
+            data_length: self.values.len() as u16,
+            write_value: self.values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","),
+            standard_mode: true,
+            timeout: self.timeout,
+            ..base.clone()
+        }
+    }
+}
+
+// Values returned by one Modbus transaction
+#[derive(Debug, Clone, PartialEq)]
+enum ModbusData {
+    Coils(Vec<bool>),
+    Registers(Vec<u16>),
+    // Single coil write, confirmed by the slave
+    Coil(bool),
+    // Single coil write in hex data mode, the value echoed by the slave (0xFF00 or 0x0000)
+    CoilEcho(u16),
+    CoilCount(u16),
+    RegisterCount(u16),
+}
+
+impl std::fmt::Display for ModbusData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModbusData::Coils(values) => write!(f, "Coils: [{}]",
+                values.iter().map(|v| if *v { "1" } else { "0" }).collect::<Vec<_>>().join(", ")),
+            ModbusData::Registers(values) => write!(f, "Registers: [{}]",
+                values.iter().map(|v| format!("0x{:04X}", *v)).collect::<Vec<_>>().join(", ")),
+            // Single coil writes keep the legacy strings: true/false, or the echoed value
+            ModbusData::Coil(value) => write!(f, "Coils: [{}]", value),
+            ModbusData::CoilEcho(value) => write!(f, "Coils: [0x{:04X}]", value),
+            ModbusData::CoilCount(count) => write!(f, "Coils: [count={}]", count),
+            ModbusData::RegisterCount(count) => write!(f, "Registers: [count={}]", count),
+        }
+    }
+}
+
+impl ModbusData {
+    // Numeric form used by the structured uplink (coils as 0/1)
+    fn values(&self) -> Vec<u16> {
+        match self {
+            ModbusData::Coils(values) => values.iter().map(|v| *v as u16).collect(),
+            ModbusData::Registers(values) => values.clone(),
+            ModbusData::Coil(value) => vec![*value as u16],
+            ModbusData::CoilEcho(value) => vec![(*value != 0) as u16],
+            ModbusData::CoilCount(count) | ModbusData::RegisterCount(count) => vec![*count],
+        }
+    }
 }
 
 // MQTT Message Structures
 #[derive(Debug, Serialize)]
 struct UplinkMessage {
     data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    points: Option<Vec<DecodedPoint>>,
+}
+
+// Structured uplink (protocol.payload_format = json)
+#[derive(Debug, Serialize)]
+struct ModbusUplink {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<serde_json::Value>,
+    gateway_id: String,
+    name: String,
+    slave_id: u8,
+    function_code: u8,
+    address: u16,
+    values: Vec<u16>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    points: Vec<DecodedPoint>,
+    timestamp: String,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exception_code: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip)]
+    data: String,
+}
+
+impl ModbusUplink {
+    fn new(gateway_id: &str, name: &str, protocol: &ProtocolConfig) -> Self {
+        ModbusUplink {
+            id: None,
+            gateway_id: gateway_id.to_string(),
+            name: name.to_string(),
+            slave_id: protocol.device_address,
+            function_code: protocol.function_code,
+            address: protocol.register_address,
+            values: Vec::new(),
+            points: Vec::new(),
+            timestamp: Local::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            status: "ok".to_string(),
+            exception_code: None,
+            error: None,
+            data: String::new(),
+        }
+    }
+
+    fn with_data(mut self, data: &ModbusData) -> Self {
+        self.values = data.values();
+        self.data = data.to_string();
+        self
+    }
+
+    // Record a classified transaction failure
+    fn with_failure(mut self, error: &ModbusError) -> Self {
+        self.status = error.status().to_string();
+        self.exception_code = error.exception_code();
+        self.error = Some(error.to_string());
+        self
+    }
+
+    // Serialize in the configured format; the legacy format only reports successful reads
+    fn to_payload(&self, payload_format: &str) -> Option<String> {
+        if payload_format == "legacy" {
+            if self.status != "ok" {
+                return None;
+            }
+            let points = if self.points.is_empty() { None } else { Some(self.points.clone()) };
+            serde_json::to_string(&UplinkMessage { data: self.data.clone(), points }).ok()
+        } else {
+            serde_json::to_string(self).ok()
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -83,6 +284,20 @@ struct DownlinkMessage {
     data: String,
 }
 
+// Modbus transaction requested over MQTT; `id` is echoed on the response topic
+#[derive(Debug, Deserialize)]
+struct ModbusCommand {
+    #[serde(default)]
+    id: serde_json::Value,
+    slave: u8,
+    function_code: u8,
+    address: u16,
+    #[serde(default)]
+    count: Option<u16>,
+    #[serde(default)]
+    values: Vec<u16>,
+}
+
 // Logger Structure
 struct Logger {
     file: StdMutex<Option<File>>,
@@ -121,96 +336,106 @@ impl Logger {
 
 // Load configuration from UCI
 fn load_config_from_uci() -> Result<Config, Box<dyn std::error::Error + Send + Sync>> {
-    let uci_get = |config: &str, section: &str, option: &str| -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let output = Command::new("uci")
-            .args(&["get", &format!("{}.{}.{}", config, section, option)])
-            .output()?;
-        if output.status.success() {
-            Ok(String::from_utf8(output.stdout)?.trim().to_string())
-        } else {
-            Err(format!("uci get failed: {}.{}.{}", config, section, option).into())
-        }
+    let uci = uci::load("rs485-module")?;
+    let uci_get = |section: &str, option: &str| -> Result<String, String> {
+        uci.get(section, option)
+            .map(|value| value.trim().to_string())
+            .ok_or_else(|| format!("rs485-module.{}.{} is not set", section, option))
     };
 
     // MQTT config
-    let mqtt_enabled = uci_get("rs485-module", "mqtt", "enabled")
+    let broker = mqtt_link::Broker::load(&uci, "mqtt", "rs485_modbus");
+    let mqtt_enabled = uci_get("mqtt", "enabled")
         .ok()
         .and_then(|s| s.parse::<u8>().ok())
         .unwrap_or(0)
         == 1;
-    let host = uci_get("rs485-module", "mqtt", "host").unwrap_or_default();
-    let port = uci_get("rs485-module", "mqtt", "port")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(1883);
-    let username = uci_get("rs485-module", "mqtt", "username").ok();
-    let password = uci_get("rs485-module", "mqtt", "password").ok();
-    let client_id = uci_get("rs485-module", "mqtt", "client_id").unwrap_or_else(|_| "rs485_modbus".to_string());
-    let keepalive = uci_get("rs485-module", "mqtt", "keepalive")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(30);
-    let uplink_topic = uci_get("rs485-module", "mqtt", "uplink_topic").unwrap_or_else(|_| "rs485/uplink".to_string());
-    let downlink_topic = uci_get("rs485-module", "mqtt", "downlink_topic").unwrap_or_else(|_| "rs485/downlink".to_string());
-    let qos_level = uci_get("rs485-module", "mqtt", "qos")
+    let uplink_topic = uci_get("mqtt", "uplink_topic").unwrap_or_else(|_| "rs485/uplink".to_string());
+    let downlink_topic = uci_get("mqtt", "downlink_topic").unwrap_or_else(|_| "rs485/downlink".to_string());
+    let response_topic = uci_get("mqtt", "response_topic").unwrap_or_else(|_| "rs485/response".to_string());
+    let qos_level = uci_get("mqtt", "qos")
         .ok()
         .and_then(|s| s.parse::<u8>().ok())
         .unwrap_or(0);
-    let reconnect_delay = uci_get("rs485-module", "mqtt", "reconnect_delay")
+    let reconnect_delay = uci_get("mqtt", "reconnect_delay")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(30);
-    let transport = uci_get("rs485-module", "mqtt", "transport").unwrap_or_else(|_| "tcp".to_string());
-    let auth_mode = uci_get("rs485-module", "mqtt", "auth_mode").unwrap_or_else(|_| "none".to_string());
-    let ca_cert = uci_get("rs485-module", "mqtt", "ca_cert").ok();
-    let client_cert = uci_get("rs485-module", "mqtt", "client_cert").ok();
-    let client_key = uci_get("rs485-module", "mqtt", "client_key").ok();
-    let token = uci_get("rs485-module", "mqtt", "token").ok();
+    let token = uci_get("mqtt", "token").ok();
+    let gateway_id = uci_get("mqtt", "gateway_id")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/deviceinfo/eui").ok().map(|s| s.trim().to_string()))
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| broker.client_id.clone());
 
     let mqtt_config = MqttConfig {
         enabled: mqtt_enabled,
-        transport,
-        host,
-        port,
-        username,
-        password,
-        client_id,
-        keepalive,
+        broker,
         uplink_topic,
         downlink_topic,
+        response_topic,
         qos_level: match qos_level {
             1 => QoS::AtLeastOnce,
             2 => QoS::ExactlyOnce,
             _ => QoS::AtMostOnce,
         },
         reconnect_delay,
-        auth_mode,
-        ca_cert,
-        client_cert,
-        client_key,
         token,
+        gateway_id,
     };
 
     // Serial config
     let device = format!(
         "/dev/{}",
-        uci_get("rs485-module", "serial", "device").unwrap_or_else(|_| "RS485-1".to_string())
+        uci_get("serial", "device").unwrap_or_else(|_| "RS485-1".to_string())
     );
-    let baudrate = uci_get("rs485-module", "serial", "baudrate")
+    let baudrate = uci_get("serial", "baudrate")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(9600);
-    let databit = uci_get("rs485-module", "serial", "databit")
+    let databit = uci_get("serial", "databit")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(8);
-    let stopbit = uci_get("rs485-module", "serial", "stopbit").unwrap_or_else(|_| "1".to_string());
-    let checkbit = uci_get("rs485-module", "serial", "checkbit").unwrap_or_else(|_| "none".to_string());
-    let flowcontrol = uci_get("rs485-module", "serial", "flowcontrol").unwrap_or_else(|_| "none".to_string());
-    let timeout = uci_get("rs485-module", "serial", "timeout")
+    let stopbit = uci_get("serial", "stopbit").unwrap_or_else(|_| "1".to_string());
+    let checkbit = uci_get("serial", "checkbit").unwrap_or_else(|_| "none".to_string());
+    let flowcontrol = uci_get("serial", "flowcontrol").unwrap_or_else(|_| "none".to_string());
+    let timeout = uci_get("serial", "timeout")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(1000);
+    let frame_gap = uci_get("serial", "frame_gap")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_FRAME_GAP);
+
+    // Half-duplex direction control (delays in ms)
+    let delay = |option: &str| -> Duration {
+        Duration::from_millis(
+            uci_get("serial", option)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+        )
+    };
+    let direction_config = direction::DirectionConfig {
+        mode: match uci_get("serial", "direction").unwrap_or_default().as_str() {
+            "rts" => direction::DirectionMode::Rts {
+                rts_on_send: uci_get("serial", "rts_on_send").map(|s| s != "0").unwrap_or(true),
+            },
+            "gpio" => match uci_get("serial", "gpio_line").ok().and_then(|s| s.parse().ok()) {
+                Some(line) => direction::DirectionMode::Gpio {
+                    chip: uci_get("serial", "gpio_chip").unwrap_or_else(|_| "/dev/gpiochip0".to_string()),
+                    line,
+                    active_low: uci_get("serial", "gpio_active_low").map(|s| s == "1").unwrap_or(false),
+                },
+                None => direction::DirectionMode::Auto,
+            },
+            _ => direction::DirectionMode::Auto,
+        },
+        delay_before: delay("delay_before_send"),
+        delay_after: delay("delay_after_send"),
+    };
 
     let serial_config = SerialConfig {
         device,
@@ -236,47 +461,53 @@ fn load_config_from_uci() -> Result<Config, Box<dyn std::error::Error + Send + S
             _ => tokio_serial::FlowControl::None,
         },
         timeout: Duration::from_millis(timeout),
+        frame_gap: Duration::from_millis(frame_gap),
+        direction: direction_config,
     };
 
     // Protocol config
-    let device_address = uci_get("rs485-module", "protocol", "device_address")
+    let device_address = uci_get("protocol", "device_address")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(1);
-    let function_code = uci_get("rs485-module", "protocol", "function_code")
+    let function_code = uci_get("protocol", "function_code")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(3);
-    let register_address = uci_get("rs485-module", "protocol", "register_address")
+    let register_address = uci_get("protocol", "register_address")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(40001);
-    let data_length = uci_get("rs485-module", "protocol", "data_length")
+    let data_length = uci_get("protocol", "data_length")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(10);
 
-    let write_value = uci_get("rs485-module", "protocol", "write_value")
+    let write_value = uci_get("protocol", "write_value")
         .unwrap_or_else(|_| "0".to_string());
     
-    let standard_mode = uci_get("rs485-module", "protocol", "standard_mode")
+    let standard_mode = uci_get("protocol", "standard_mode")
         .ok()
         .and_then(|s| s.parse::<u8>().ok())
         .unwrap_or(1) == 1;
 
-    let work_mode = uci_get("rs485-module", "protocol", "work_mode")
+    let work_mode = uci_get("protocol", "work_mode")
         .unwrap_or_else(|_| "once".to_string());
     
-    let poll_interval = uci_get("rs485-module", "protocol", "poll_interval")
+    let poll_interval = uci_get("protocol", "poll_interval")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(10);
     
-    let timeout = uci_get("rs485-module", "protocol", "timeout")
+    let timeout = uci_get("protocol", "timeout")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(10);
 
+    // Existing installs without the option keep the plain {"data":"..."} payload
+    let payload_format = uci_get("protocol", "payload_format")
+        .unwrap_or_else(|_| "legacy".to_string());
+
     let protocol_config = ProtocolConfig {
         device_address,
         function_code,
@@ -287,169 +518,248 @@ fn load_config_from_uci() -> Result<Config, Box<dyn std::error::Error + Send + S
         work_mode,
         poll_interval,
         timeout,
+        payload_format,
     };
 
-    Ok(Config {
-        mqtt: mqtt_config,
-        serial: serial_config,
-        protocol: protocol_config,
-    })
-}
+    // Poll table: iterate `@poll[N]` until the section index runs out
+    let mut polls = Vec::new();
+    let mut index = 0;
+    while uci.has_section(&format!("@poll[{}]", index)) {
+        let section = format!("@poll[{}]", index);
+        index += 1;
 
-// Setup MQTT client
-fn setup_mqtt_client(
-    config: &MqttConfig,
-) -> Result<(AsyncClient, rumqttc::EventLoop), Box<dyn std::error::Error + Send + Sync>> {
-    let mut mqttoptions = MqttOptions::new(&config.client_id, &config.host, config.port);
-    mqttoptions.set_keep_alive(Duration::from_secs(config.keepalive));
-    
-    if let (Some(username), Some(password)) = (&config.username, &config.password) {
-        mqttoptions.set_credentials(username, password);
+        let enabled = uci_get(&section, "enabled")
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(1) == 1;
+        let function_code = uci_get(&section, "function_code")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(3);
+        if !enabled || !(1..=4).contains(&function_code) {
+            continue;
+        }
+
+        polls.push(PollConfig {
+            name: uci_get(&section, "name").unwrap_or_else(|_| format!("poll{}", index - 1)),
+            transport: uci_get(&section, "transport").unwrap_or_else(|_| "rtu".to_string()),
+            host: uci_get(&section, "host").unwrap_or_default(),
+            port: uci_get(&section, "port")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(502),
+            slave_id: uci_get(&section, "slave_id")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1),
+            function_code,
+            register_address: uci_get(&section, "register_address")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            count: uci_get(&section, "count")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1),
+            interval: uci_get(&section, "interval")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(protocol_config.poll_interval),
+            timeout: uci_get(&section, "timeout")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(protocol_config.timeout),
+            topic: uci_get(&section, "topic").unwrap_or_else(|_| mqtt_config.uplink_topic.clone()),
+            points: Vec::new(),
+        });
     }
 
-    match config.transport.as_str() {
-        "ssl" | "tls" => {
-            let mut root_cert_store = rumqttc::tokio_rustls::rustls::RootCertStore::empty();
-            
-            match config.auth_mode.as_str() {
-                "tls-server" => {
-                    if let Some(ca_cert) = &config.ca_cert {
-                        let ca_cert_bytes = ca_cert.as_bytes();
-                        let mut cursor = std::io::Cursor::new(ca_cert_bytes);
-                        for cert in certs(&mut cursor) {
-                            root_cert_store.add(cert?)?;
-                        }
-                    } else {
-                        root_cert_store.extend(
-                            webpki_roots::TLS_SERVER_ROOTS.iter().cloned()
-                        );
-                    }
-                    
-                    let tls_config = RustlsClientConfig::builder()
-                        .with_root_certificates(root_cert_store)
-                        .with_no_client_auth();
-                    
-                    mqttoptions.set_transport(Transport::tls_with_config(tls_config.into()));
-                }
-                "mutual-tls" => {
-                    if let Some(ca_cert) = &config.ca_cert {
-                        let ca_cert_bytes = ca_cert.as_bytes();
-                        let mut cursor = std::io::Cursor::new(ca_cert_bytes);
-                        for cert in certs(&mut cursor) {
-                            root_cert_store.add(cert?)?;
-                        }
-                    } else {
-                        root_cert_store.extend(
-                            webpki_roots::TLS_SERVER_ROOTS.iter().cloned()
-                        );
-                    }
-                    
-                    if let (Some(client_cert_pem), Some(client_key_pem)) = (&config.client_cert, &config.client_key) {
-                        let cert_bytes = client_cert_pem.as_bytes();
-                        let key_bytes = client_key_pem.as_bytes();
-                        
-                        let mut cert_cursor = std::io::Cursor::new(cert_bytes);
-                        let certs: Vec<_> = certs(&mut cert_cursor).collect::<Result<_, _>>()?;
-                        
-                        let mut key_cursor = std::io::Cursor::new(key_bytes);
-                        let mut keys = pkcs8_private_keys(&mut key_cursor).collect::<Result<Vec<_>, _>>()?;
-                        
-                        if keys.is_empty() {
-                            return Err("No private key found".into());
-                        }
-                        
-                        let tls_config = RustlsClientConfig::builder()
-                            .with_root_certificates(root_cert_store)
-                            .with_client_auth_cert(certs, keys.remove(0).into())?;
-                        
-                        mqttoptions.set_transport(Transport::tls_with_config(tls_config.into()));
-                    } else {
-                        return Err("Mutual TLS requires both client certificate and private key".into());
-                    }
-                }
-                _ => {
-                    root_cert_store.extend(
-                        webpki_roots::TLS_SERVER_ROOTS.iter().cloned()
-                    );
-                    
-                    let tls_config = RustlsClientConfig::builder()
-                        .with_root_certificates(root_cert_store)
-                        .with_no_client_auth();
-                    
-                    mqttoptions.set_transport(Transport::tls_with_config(tls_config.into()));
-                }
-            }
-        }
-        "ws" => {
-            let ws_url = format!("ws://{}:{}/mqtt", config.host, config.port);
-            mqttoptions = MqttOptions::new(&config.client_id, &ws_url, config.port);
-            mqttoptions.set_keep_alive(Duration::from_secs(config.keepalive));
-            if let Some(username) = &config.username {
-                mqttoptions.set_credentials(username, config.password.as_deref().unwrap_or(""));
-            }
-            mqttoptions.set_transport(Transport::Ws);
+    // Legacy single-block setup: periodic mode of the protocol section becomes one poll entry
+    if polls.is_empty()
+        && protocol_config.work_mode == "periodic"
+        && (1..=4).contains(&protocol_config.function_code)
+    {
+        polls.push(PollConfig {
+            name: "protocol".to_string(),
+            transport: "rtu".to_string(),
+            host: String::new(),
+            port: 502,
+            slave_id: protocol_config.device_address,
+            function_code: protocol_config.function_code,
+            register_address: protocol_config.register_address,
+            count: protocol_config.data_length,
+            interval: protocol_config.poll_interval,
+            timeout: protocol_config.timeout,
+            topic: mqtt_config.uplink_topic.clone(),
+            points: Vec::new(),
+        });
+    }
+
+    // Entries are tracked by name and a zero interval would read the bus back to back
+    let mut names = HashSet::new();
+    for poll in &polls {
+        if poll.interval == 0 {
+            return Err(format!("Poll entry {}: interval must be at least 1 second", poll.name).into());
         }
-        "wss" => {
-            let wss_url = format!("wss://{}:{}/mqtt", config.host, config.port);
-            mqttoptions = MqttOptions::new(&config.client_id, &wss_url, config.port);
-            mqttoptions.set_keep_alive(Duration::from_secs(config.keepalive));
-            if let Some(username) = &config.username {
-                mqttoptions.set_credentials(username, config.password.as_deref().unwrap_or(""));
-            }
-            
-            let mut root_cert_store = rumqttc::tokio_rustls::rustls::RootCertStore::empty();
-            
-            if let Some(ca_cert) = &config.ca_cert {
-                let ca_cert_bytes = ca_cert.as_bytes();
-                let mut cursor = std::io::Cursor::new(ca_cert_bytes);
-                for cert in certs(&mut cursor) {
-                    root_cert_store.add(cert?)?;
-                }
-            } else {
-                root_cert_store.extend(
-                    webpki_roots::TLS_SERVER_ROOTS.iter().cloned()
-                );
-            }
-            
-            let tls_config = match config.auth_mode.as_str() {
-                "mutual-tls" => {
-                    if let (Some(client_cert_pem), Some(client_key_pem)) = (&config.client_cert, &config.client_key) {
-                        let cert_bytes = client_cert_pem.as_bytes();
-                        let key_bytes = client_key_pem.as_bytes();
-                        
-                        let mut cert_cursor = std::io::Cursor::new(cert_bytes);
-                        let certs: Vec<_> = certs(&mut cert_cursor).collect::<Result<_, _>>()?;
-                        
-                        let mut key_cursor = std::io::Cursor::new(key_bytes);
-                        let mut keys = pkcs8_private_keys(&mut key_cursor).collect::<Result<Vec<_>, _>>()?;
-                        
-                        if keys.is_empty() {
-                            return Err("No private key found".into());
-                        }
-                        
-                        RustlsClientConfig::builder()
-                            .with_root_certificates(root_cert_store)
-                            .with_client_auth_cert(certs, keys.remove(0).into())?
-                    } else {
-                        return Err("Mutual TLS requires both client certificate and private key".into());
-                    }
-                }
-                _ => {
-                    RustlsClientConfig::builder()
-                        .with_root_certificates(root_cert_store)
-                        .with_no_client_auth()
-                }
-            };
-            
-            mqttoptions.set_transport(Transport::wss_with_config(tls_config.into()));
+        if !names.insert(poll.name.as_str()) {
+            return Err(format!("Duplicate poll entry name: {}", poll.name).into());
         }
-        "tcp" | _ => {
-            mqttoptions.set_transport(Transport::Tcp);
+    }
+
+    // Typed points, attached to the poll entry named by their `poll` option
+    let mut index = 0;
+    while uci.has_section(&format!("@point[{}]", index)) {
+        let section = format!("@point[{}]", index);
+        index += 1;
+
+        let poll_name = uci_get(&section, "poll").unwrap_or_default();
+        let poll = match polls.iter_mut().find(|p| p.name == poll_name) {
+            Some(poll) => poll,
+            None => continue,
+        };
+
+        poll.points.push(PointConfig {
+            name: uci_get(&section, "name").unwrap_or_else(|_| format!("point{}", index - 1)),
+            poll: poll_name,
+            address: uci_get(&section, "address")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(poll.register_address),
+            data_type: uci_get(&section, "type")
+                .ok()
+                .and_then(|s| DataType::parse(&s))
+                .unwrap_or(DataType::U16),
+            order: uci_get(&section, "order")
+                .ok()
+                .and_then(|s| ByteOrder::parse(&s))
+                .unwrap_or(ByteOrder::Abcd),
+            length: uci_get(&section, "length")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1),
+            bit: uci_get(&section, "bit")
+                .ok()
+                .and_then(|s| s.parse().ok()),
+            scale: uci_get(&section, "scale")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1.0),
+            offset: uci_get(&section, "offset")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.0),
+            unit: uci_get(&section, "unit").unwrap_or_default(),
+            publish: report::PublishPolicy::parse(
+                &uci_get(&section, "publish").unwrap_or_default(),
+                uci_get(&section, "deadband")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0.0),
+            ),
+            max_silence: uci_get(&section, "max_silence")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+        });
+    }
+
+    // Scheduled writes; entries without values or a valid interval/cron are skipped
+    let mut schedules = Vec::new();
+    let mut index = 0;
+    while uci.has_section(&format!("@schedule[{}]", index)) {
+        let section = format!("@schedule[{}]", index);
+        index += 1;
+
+        let enabled = uci_get(&section, "enabled")
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(1) == 1;
+        let function_code = uci_get(&section, "function_code")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(6);
+        let values: Vec<u16> = uci_get(&section, "values")
+            .unwrap_or_default()
+            .split([',', ' '])
+            .filter_map(|s| s.trim().parse().ok())
+            .collect();
+        let interval = uci_get(&section, "interval")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        let cron = uci_get(&section, "cron")
+            .ok()
+            .and_then(|s| schedule::Cron::parse(&s));
+        if !enabled || !matches!(function_code, 5 | 6 | 15 | 16) || values.is_empty() || (interval == 0 && cron.is_none()) {
+            continue;
         }
+
+        schedules.push(ScheduleConfig {
+            name: uci_get(&section, "name").unwrap_or_else(|_| format!("schedule{}", index - 1)),
+            slave_id: uci_get(&section, "slave_id")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1),
+            function_code,
+            address: uci_get(&section, "address")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            values,
+            interval,
+            cron,
+            timeout: uci_get(&section, "timeout")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(protocol_config.timeout),
+            topic: uci_get(&section, "topic").unwrap_or_else(|_| mqtt_config.response_topic.clone()),
+        });
     }
-    
-    let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
-    Ok((client, eventloop))
+
+    let mut names = HashSet::new();
+    if let Some(entry) = schedules.iter().find(|entry| !names.insert(entry.name.as_str())) {
+        return Err(format!("Duplicate schedule entry name: {}", entry.name).into());
+    }
+
+    // Modbus TCP server config
+    let tcp_server_config = TcpServerConfig {
+        enabled: uci_get("tcp_server", "enabled")
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(0) == 1,
+        bind: uci_get("tcp_server", "bind").unwrap_or_else(|_| "0.0.0.0".to_string()),
+        port: uci_get("tcp_server", "port")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(502),
+    };
+
+    // Store-and-forward buffer config (max_size in KiB, max_age in seconds)
+    let buffer_config = BufferConfig {
+        enabled: uci_get("buffer", "enabled")
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(0) == 1,
+        path: uci_get("buffer", "path").unwrap_or_else(|_| "/tmp/rs485/spool".to_string()),
+        max_size: uci_get("buffer", "max_size")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1024),
+        max_age: uci_get("buffer", "max_age")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(86400),
+    };
+
+    Ok(Config {
+        mqtt: mqtt_config,
+        serial: serial_config,
+        protocol: protocol_config,
+        polls,
+        schedules,
+        tcp_server: tcp_server_config,
+        buffer: buffer_config,
+    })
 }
 
 // Setup serial port
@@ -467,233 +777,126 @@ async fn setup_serial(
     Ok(port)
 }
 
-// CRC-16 Modbus calculation
-fn crc16_modbus(data: &[u8]) -> u16 {
-    let mut crc: u16 = 0xFFFF;
-    for &byte in data {
-        crc ^= byte as u16;
-        for _ in 0..8 {
-            if crc & 0x0001 != 0 {
-                crc = (crc >> 1) ^ 0xA001;
-            } else {
-                crc >>= 1;
-            }
+fn log_serial(serial: &SerialConfig, logger: &Logger) {
+    logger.log(&format!(
+        "Opening serial port: {} @ {} baud, {:?} data bits, {:?} stop bits, {:?} parity, {:?} flow control, {:?} timeout",
+        serial.device, serial.baudrate, serial.databit, serial.stopbit, serial.checkbit, serial.flowcontrol, serial.timeout
+    ));
+}
+
+// Start the Modbus TCP server if enabled; the handle stops it on reload
+async fn start_tcp_server(config: &Config, bus: &bus::Bus, logger: &Arc<Logger>) -> Option<tokio::task::JoinHandle<()>> {
+    if !config.tcp_server.enabled {
+        return None;
+    }
+    let bind_addr = format!("{}:{}", config.tcp_server.bind, config.tcp_server.port);
+    match tokio::net::TcpListener::bind(&bind_addr).await {
+        Ok(listener) => {
+            logger.log(&format!("Modbus TCP server listening on {}", bind_addr));
+            let gateway = tcp_server::Gateway {
+                bus: bus.clone(),
+                default_slave: config.protocol.device_address,
+                timeout: Duration::from_millis(config.protocol.timeout * 100),
+                logger: logger.clone(),
+            };
+            Some(tokio::spawn(tcp_server::run(listener, gateway)))
+        }
+        Err(e) => {
+            logger.log(&format!("Modbus TCP server bind {} failed: {}", bind_addr, e));
+            None
         }
     }
-    crc
+}
+
+fn open_spool(config: &BufferConfig, logger: &Logger) -> Option<spool::Spool> {
+    if !config.enabled {
+        return None;
+    }
+    match spool::Spool::new(&config.path, "rs485-modbus", config.max_size * 1024, config.max_age) {
+        Ok(spool) => {
+            logger.log(&format!("Uplink buffer in {} ({} pending)", config.path, spool.pending()));
+            Some(spool)
+        }
+        Err(e) => {
+            logger.log(&format!("Uplink buffer {} unavailable: {}", config.path, e));
+            None
+        }
+    }
+}
+
+// Where a transaction is sent: the local RS485 bus or a Modbus TCP device
+enum Link<'a> {
+    Bus(&'a bus::Bus),
+    Tcp(&'a mut client::Context),
 }
 
 // Read Modbus data
 async fn read_modbus_data(
-    ctx: &mut client::Context,
+    link: Link<'_>,
     config: &ProtocolConfig,
-    serial_config: &SerialConfig,
-    logger: &Arc<Logger>,
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    ctx.set_slave(Slave(config.device_address));
-    let addr = config.register_address as u16;
-    
-    // For write operations (FC05/06/15/16), check if non-standard mode is enabled
-    if matches!(config.function_code, 5 | 6 | 15 | 16) && !config.standard_mode {
-        // Non-standard mode: parse space-separated hex data (e.g., "00 5A")
-        let write_val_str = config.write_value.trim();
-        let parts: Vec<&str> = write_val_str.split_whitespace().collect();
-        let mut data_bytes = Vec::new();
-        
-        for part in &parts {
-            match u8::from_str_radix(part, 16) {
-                Ok(byte) => data_bytes.push(byte),
-                Err(_) => return Err(format!("Invalid hex value: {}", part).into()),
-            }
-        }
-        
-        if data_bytes.is_empty() {
-            return Err("No data bytes provided in non-standard mode".into());
-        }
-        
-        // Build frame based on function code
-        let mut frame = vec![config.device_address, config.function_code];
-        
-        match config.function_code {
-            5 => {
-                // FC05: Write Single Coil
-                frame.push((addr >> 8) as u8);
-                frame.push((addr & 0xFF) as u8);
-                frame.extend_from_slice(&data_bytes);
-            }
-            6 => {
-                // FC06: Write Single Register
-                frame.push((addr >> 8) as u8);
-                frame.push((addr & 0xFF) as u8);
-                frame.extend_from_slice(&data_bytes);
-            }
-            15 => {
-                // FC15: Write Multiple Coils
-                let quantity = config.data_length as u16;
-                let byte_count = data_bytes.len() as u8;
-                frame.push((addr >> 8) as u8);
-                frame.push((addr & 0xFF) as u8);
-                frame.push((quantity >> 8) as u8);
-                frame.push((quantity & 0xFF) as u8);
-                frame.push(byte_count);
-                frame.extend_from_slice(&data_bytes);
-            }
-            16 => {
-                // FC16: Write Multiple Registers
-                let quantity = (data_bytes.len() / 2) as u16;
-                let byte_count = data_bytes.len() as u8;
-                frame.push((addr >> 8) as u8);
-                frame.push((addr & 0xFF) as u8);
-                frame.push((quantity >> 8) as u8);
-                frame.push((quantity & 0xFF) as u8);
-                frame.push(byte_count);
-                frame.extend_from_slice(&data_bytes);
-            }
-            _ => {
-                return Err(format!("Hex data mode not supported for FC{:02}", config.function_code).into());
-            }
-        }
-        
-        // Calculate and append CRC
-        let crc = crc16_modbus(&frame);
-        frame.push((crc & 0xFF) as u8);
-        frame.push((crc >> 8) as u8);
-        
-        let mut port = tokio_serial::new(&serial_config.device, serial_config.baudrate)
-            .data_bits(serial_config.databit)
-            .stop_bits(serial_config.stopbit)
-            .parity(serial_config.checkbit)
-            .flow_control(serial_config.flowcontrol)
-            .timeout(serial_config.timeout)
-            .open_native_async()
-            .map_err(|e| format!("Failed to open port: {}", e))?;
-        
-        AsyncWriteExt::write_all(&mut port, &frame).await?;
-        
-        // Read response
-        let mut response_buf = vec![0u8; 256];
-        match AsyncReadExt::read(&mut port, &mut response_buf).await {
-            Ok(n) if n > 0 => {
-                let response = &response_buf[..n];
-                // logger.log(&format!("Response received: {:02X?}", response));
-                drop(port);
-                
-                // Parse response according to function code (same format as standard frames)
-                match config.function_code {
-                    5 => {
-                        // FC05: Write Single Coil response
-                        if n >= 6 {
-                            let value = ((response[4] as u16) << 8) | (response[5] as u16);
-                            return Ok(format!("Coils: [0x{:04X}]", value));
-                        }
-                    }
-                    6 => {
-                        // FC06: Write Single Register response
-                        if n >= 6 {
-                            let value = ((response[4] as u16) << 8) | (response[5] as u16);
-                            return Ok(format!("Registers: [0x{:04X}]", value));
-                        }
-                    }
-                    15 => {
-                        // FC15: Write Multiple Coils response
-                        if n >= 6 {
-                            let count = ((response[4] as u16) << 8) | (response[5] as u16);
-                            return Ok(format!("Coils: [count={}]", count));
-                        }
-                    }
-                    16 => {
-                        // FC16: Write Multiple Registers response
-                        if n >= 6 {
-                            let count = ((response[4] as u16) << 8) | (response[5] as u16);
-                            return Ok(format!("Registers: [count={}]", count));
-                        }
-                    }
-                    _ => {
-                        return Ok(format!("Response: {:02X?}", response));
-                    }
-                }
-            }
-            Ok(_) => {
-                logger.log("No response data received");
-                drop(port);
-                return Err("No response data received".into());
-            }
-            Err(e) => {
-                logger.log(&format!("Response read error: {}", e));
-                drop(port);
-                return Err(format!("Response read error: {}", e).into());
-            }
+) -> Result<ModbusData, Box<dyn std::error::Error + Send + Sync>> {
+    let ctx = match link {
+        Link::Bus(bus) => {
+            let request = pdu::request(config)?;
+            let timeout = Duration::from_millis(config.timeout * 100);
+            let response = bus.request(config.device_address, request, timeout).await?;
+            return Ok(pdu::response(config, &response)?);
         }
+        Link::Tcp(ctx) => ctx,
+    };
+
+    if matches!(config.function_code, 5 | 6 | 15 | 16) && !config.standard_mode {
+        return Err("Hex data mode is only available on the RS485 bus".into());
     }
+    ctx.set_slave(Slave(config.device_address));
+    let addr = config.register_address;
 
-    // Standard Modbus operations (for read operations or standard mode write operations)
+    // Standard Modbus operations over Modbus TCP
     match config.function_code {
         3 => {
-            let data = ctx.read_holding_registers(addr, config.data_length as u16).await??;
-            Ok(format!("Registers: [{}]", 
-                data.iter().map(|v| format!("0x{:04X}", *v)).collect::<Vec<_>>().join(", ")))
+            let data = ctx.read_holding_registers(addr, config.data_length).await??;
+            Ok(ModbusData::Registers(data))
         }
         4 => {
-            let data = ctx.read_input_registers(addr, config.data_length as u16).await??;
-            Ok(format!("Registers: [{}]", 
-                data.iter().map(|v| format!("0x{:04X}", *v)).collect::<Vec<_>>().join(", ")))
+            let data = ctx.read_input_registers(addr, config.data_length).await??;
+            Ok(ModbusData::Registers(data))
         }
         1 => {
-            let data = ctx.read_coils(addr, config.data_length as u16).await??;
-            Ok(format!("Coils: [{}]", 
-                data.iter().map(|v| if *v { "1" } else { "0" }).collect::<Vec<_>>().join(", ")))
+            let data = ctx.read_coils(addr, config.data_length).await??;
+            Ok(ModbusData::Coils(data))
         }
         2 => {
-            let data = ctx.read_discrete_inputs(addr, config.data_length as u16).await??;
-            Ok(format!("Coils: [{}]", 
-                data.iter().map(|v| if *v { "1" } else { "0" }).collect::<Vec<_>>().join(", ")))
+            let data = ctx.read_discrete_inputs(addr, config.data_length).await??;
+            Ok(ModbusData::Coils(data))
         }
         5 => {
             // Write Single Coil
-            let value = config.write_value.trim().parse::<u16>().unwrap_or(0) != 0;
+            let value = pdu::write_coils(config).first().copied().unwrap_or(false);
             ctx.write_single_coil(addr, value).await??;
-            Ok(format!("Coils: [{}]", value))
+            Ok(ModbusData::Coil(value))
         }
         6 => {
             // Write Single Register
-            let value = if config.write_value.trim().starts_with("0x") || config.write_value.trim().starts_with("0X") {
-                u16::from_str_radix(&config.write_value.trim()[2..], 16).unwrap_or(0)
-            } else {
-                config.write_value.trim().parse::<u16>().unwrap_or(0)
-            };
+            let value = pdu::write_registers(config).first().copied().unwrap_or(0);
             ctx.write_single_register(addr, value).await??;
-            Ok(format!("Registers: [0x{:04X}]", value))
+            Ok(ModbusData::Registers(vec![value]))
         }
         15 => {
-            let values: Vec<bool> = config.write_value.trim().split(',')
-                .filter_map(|s| s.trim().parse::<u16>().ok())
-                .map(|v| v != 0)
-                .collect();
+            let values = pdu::write_coils(config);
             if values.is_empty() {
                 return Err("No valid values provided for Write Multiple Coils".into());
             }
             ctx.write_multiple_coils(addr, &values).await??;
-            Ok(format!("Coils: [{}]", 
-                values.iter().map(|v| if *v { "1" } else { "0" }).collect::<Vec<_>>().join(", ")))
+            Ok(ModbusData::Coils(values))
         }
         16 => {
             // Write Multiple Registers
-            let values: Vec<u16> = config.write_value.trim().split(',')
-                .filter_map(|s| {
-                    let s = s.trim();
-                    if s.starts_with("0x") || s.starts_with("0X") {
-                        u16::from_str_radix(&s[2..], 16).ok()
-                    } else {
-                        s.parse::<u16>().ok()
-                    }
-                })
-                .collect();
+            let values = pdu::write_registers(config);
             if values.is_empty() {
                 return Err("No valid values provided for Write Multiple Registers".into());
             }
             ctx.write_multiple_registers(addr, &values).await??;
-            Ok(format!("Registers: [{}]", 
-                values.iter().map(|v| format!("0x{:04X}", *v)).collect::<Vec<_>>().join(", ")))
+            Ok(ModbusData::Registers(values))
         }
         _ => {
             Err(format!("Unsupported function code: {}", config.function_code).into())
@@ -701,12 +904,196 @@ async fn read_modbus_data(
     }
 }
 
+// Run an MQTT command as one Modbus transaction and build its response
+async fn run_command(
+    bus: &bus::Bus,
+    command: &ModbusCommand,
+    config: &Config,
+) -> ModbusUplink {
+    let protocol = ProtocolConfig {
+        device_address: command.slave,
+        function_code: command.function_code,
+        register_address: command.address,
+        data_length: command.count.unwrap_or(command.values.len().max(1) as u16),
+        write_value: command.values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","),
+        standard_mode: true,
+        ..config.protocol.clone()
+    };
+    let mut response = if !matches!(command.function_code, 1 | 2 | 3 | 4 | 5 | 6 | 15 | 16) {
+        ModbusUplink::new(&config.mqtt.gateway_id, "command", &protocol)
+            .with_failure(&ModbusError::Other(format!("Unsupported function code: {}", command.function_code)))
+    } else if matches!(command.function_code, 5 | 6 | 15 | 16) && command.values.is_empty() {
+        ModbusUplink::new(&config.mqtt.gateway_id, "command", &protocol)
+            .with_failure(&ModbusError::Other("Write command requires values".to_string()))
+    } else {
+        run_transaction(Link::Bus(bus), &protocol, config, "command").await
+    };
+    response.id = Some(command.id.clone());
+    response
+}
+
+// Answer a downlink command on the response topic
+fn publish_response(connection: Option<&mqtt_link::Connection>, config: &MqttConfig, json: String, logger: &Logger) {
+    let Some(connection) = connection else {
+        return;
+    };
+    match connection.publish(&config.response_topic, config.qos_level, false, json.clone().into_bytes()) {
+        Ok(_) => logger.log(&format!("Published response to {}: {}", config.response_topic, json)),
+        Err(e) => logger.log(&format!("MQTT publish failed: {}", e)),
+    }
+}
+
+// Run one Modbus transaction and classify the outcome
+async fn run_transaction(
+    link: Link<'_>,
+    protocol: &ProtocolConfig,
+    config: &Config,
+    name: &str,
+) -> ModbusUplink {
+    let response = ModbusUplink::new(&config.mqtt.gateway_id, name, protocol);
+    let result = match link {
+        // The bus task applies the timeout per attempt, after the request leaves the queue
+        Link::Bus(_) => read_modbus_data(link, protocol).await,
+        Link::Tcp(_) => {
+            let timeout_duration = Duration::from_millis(protocol.timeout * 100);
+            match tokio::time::timeout(timeout_duration, read_modbus_data(link, protocol)).await {
+                Ok(result) => result,
+                Err(_) => return response.with_failure(&ModbusError::Timeout),
+            }
+        }
+    };
+    match result {
+        Ok(values) => response.with_data(&values),
+        Err(e) => response.with_failure(&ModbusError::classify(e.as_ref())),
+    }
+}
+
+// Open a Modbus TCP connection for poll entries with transport 'tcp'
+async fn connect_tcp(
+    host: &str,
+    port: u16,
+    slave_id: u8,
+    timeout: Duration,
+) -> Result<client::Context, Box<dyn std::error::Error + Send + Sync>> {
+    let connect = async {
+        let addr = tokio::net::lookup_host((host, port))
+            .await?
+            .next()
+            .ok_or_else(|| format!("Cannot resolve {}", host))?;
+        let ctx = tcp::connect_slave(addr, Slave(slave_id)).await?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(ctx)
+    };
+    match tokio::time::timeout(timeout, connect).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("Connect to {}:{} timed out", host, port).into()),
+    }
+}
+
+// Uplink destination: the broker while connected, otherwise the store-and-forward spool
+struct Outbox<'a> {
+    client: Option<&'a mqtt_link::Connection>,
+    spool: Option<&'a mut spool::Spool>,
+    qos: QoS,
+}
+
+impl Outbox<'_> {
+    // Whether an uplink goes anywhere at all
+    fn active(&self) -> bool {
+        self.client.is_some() || self.spool.is_some()
+    }
+
+    // Whether the uplink was sent or buffered
+    async fn publish(&mut self, topic: &str, payload: &str, logger: &Arc<Logger>) -> bool {
+        if let Some(client) = self.client {
+            match client.publish(topic, self.qos, false, payload.as_bytes().to_vec()) {
+                Ok(_) => {
+                    logger.log(&format!("Published to MQTT {}: {}", topic, payload));
+                    return true;
+                }
+                Err(e) => logger.log(&format!("MQTT publish failed: {}", e)),
+            }
+        }
+        if let Some(spool) = self.spool.as_deref_mut() {
+            match spool.push(topic, payload.as_bytes()) {
+                Ok(_) => {
+                    logger.log(&format!("Buffered uplink for {} ({} pending)", topic, spool.pending()));
+                    return true;
+                }
+                Err(e) => logger.log(&format!("Failed to buffer uplink: {}", e)),
+            }
+        }
+        false
+    }
+}
+
+// Read one poll table entry and publish the result to its uplink topic
+async fn run_poll(
+    link: Link<'_>,
+    poll: &PollConfig,
+    config: &Config,
+    outbox: &mut Outbox<'_>,
+    reporter: &mut report::Reporter,
+    logger: &Arc<Logger>,
+) -> ModbusUplink {
+    let protocol = poll.protocol(&config.protocol);
+    let mut uplink = run_transaction(link, &protocol, config, &poll.name).await;
+
+    if uplink.status == "ok" {
+        logger.log(&format!("[{}] Modbus data received: {}", poll.name, uplink.data));
+
+        // Decode configured points into engineering values
+        let (decoded, errors) = match protocol.function_code {
+            1 | 2 => {
+                let bits: Vec<bool> = uplink.values.iter().map(|v| *v != 0).collect();
+                decode::decode_bits(&poll.points, poll.register_address, &bits)
+            }
+            _ => decode::decode_block(&poll.points, poll.register_address, &uplink.values),
+        };
+        for e in errors {
+            logger.log(&format!("[{}] Point decode failed: {}", poll.name, e));
+        }
+        uplink.points = decoded;
+    } else {
+        logger.log(&format!("[{}] Modbus read failed ({}): {}", poll.name, uplink.status,
+            uplink.error.as_deref().unwrap_or("")));
+    }
+
+    if outbox.active() {
+        // Report by exception: only points whose publish policy fires go out
+        let all_points = std::mem::take(&mut uplink.points);
+        uplink.points = reporter.select(&poll.name, &poll.points, &all_points);
+        if uplink.status == "ok" && !all_points.is_empty() && uplink.points.is_empty() {
+            logger.log(&format!("[{}] No point changed, uplink suppressed", poll.name));
+        } else if let Some(json) = uplink.to_payload(&config.protocol.payload_format) {
+            if outbox.publish(&poll.topic, &json, logger).await {
+                reporter.commit(&poll.name, &uplink.points);
+            }
+        } else {
+            logger.log(&format!("[{}] Read failure not published: payload_format \"legacy\" only carries data", poll.name));
+        }
+        uplink.points = all_points;
+    }
+
+    uplink
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Control client mode: rs485-modbus call <method> [params-json]
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() >= 3 && args[1] == "call" {
+        return control::call(&args[2], args.get(3).map(|s| s.as_str())).await;
+    }
+
     let logger = Arc::new(Logger::new());
     logger.init()?;
     logger.log("RS485-Modbus Bridge starting...");
 
+    // SIGHUP (sent by `/etc/init.d/rs485-modbus reload`) reloads the configuration; installed
+    // before anything else so an early reload does not terminate the process
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut reload = false;
+
     // Load initial configuration from UCI
     let mut config = match load_config_from_uci() {
         Ok(cfg) => cfg,
@@ -717,407 +1104,305 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     };
 
     // Initialize Modbus context
-    let port = setup_serial(&config.serial).await?;
-    logger.log(&format!(
-        "Opening serial port: {} @ {} baud, {:?} data bits, {:?} stop bits, {:?} parity, {:?} flow control, {:?} timeout",
-        config.serial.device, config.serial.baudrate, config.serial.databit, config.serial.stopbit, config.serial.checkbit, config.serial.flowcontrol, config.serial.timeout
-    ));
-    let mut modbus_ctx = rtu::attach(port);
+    log_serial(&config.serial, &logger);
+    // The bus task owns the port; polls, commands, the TCP server and downlinks queue through it
+    let bus = match bus::Bus::spawn(&config.serial, logger.clone()).await {
+        Ok(bus) => bus,
+        Err(e) => {
+            logger.log(&format!("Failed opening serial port {}: {}", config.serial.device, e));
+            return Err(e.into());
+        }
+    };
     logger.log("Success opening serial port");
+
+    // Start Modbus TCP server
+    let mut tcp_server = start_tcp_server(&config, &bus, &logger).await;
     
-    let mut mqtt_client: Option<AsyncClient> = None;                // MQTT client
-    let mut mqtt_eventloop: Option<rumqttc::EventLoop> = None;      // MQTT event loop
-    let mut mqtt_state = "not_connect";                             // MQTT connection state   
-    let mut last_periodic_read = tokio::time::Instant::now();       // Last periodic read timestamp
+    // Start local control socket (read/write/status/last_result)
+    let shared = Arc::new(control::Shared::new(bus.clone(), config.clone()));
+    let _ = std::fs::remove_file(control::SOCKET_PATH);
+    // Requests on the socket can write to the bus, so only root (rpcd included) may connect
+    let control_socket = tokio::net::UnixListener::bind(control::SOCKET_PATH).and_then(|listener| {
+        std::fs::set_permissions(control::SOCKET_PATH, std::fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    });
+    match control_socket {
+        Ok(listener) => {
+            logger.log(&format!("Control socket listening on {}", control::SOCKET_PATH));
+            tokio::spawn(control::run(listener, shared.clone(), logger.clone()));
+        }
+        Err(e) => logger.log(&format!("Control socket bind {} failed: {}", control::SOCKET_PATH, e)),
+    }
+
+    // Start store-and-forward buffer
+    let mut spool = open_spool(&config.buffer, &logger);
+
+    let mut mqtt: Option<(mqtt_link::Connection, tokio::sync::mpsc::Receiver<mqtt_link::Event>)> = None; // MQTT connection task
+    let mut mqtt_state = "not_connect";                             // MQTT connection state
+    let mut mqtt_connected = false;                                 // ConnAck received on the current connection
+    let mut last_poll: HashMap<String, tokio::time::Instant> = HashMap::new();  // Last read per poll entry
+    let mut tcp_contexts: HashMap<String, client::Context> = HashMap::new();     // Modbus TCP connections by host:port
+    let mut last_write: HashMap<String, tokio::time::Instant> = HashMap::new(); // Last interval write per schedule
+    let mut last_cron: HashMap<String, i64> = HashMap::new();                   // Minute of the last cron write per schedule
+    let mut reporter = report::Reporter::default();                              // Last published value per point
 
     loop {
-        // Load configuration
-        config = match load_config_from_uci() {
-            Ok(cfg) => cfg,
-            Err(e) => {
-                logger.log(&format!("Failed to load config: {}", e));
-                return Err(e);
-            }
-        };
+        // Apply a reloaded configuration to the parts it changed
+        if reload {
+            reload = false;
+            match load_config_from_uci() {
+                Ok(new_config) => {
+                    logger.log("Configuration reloaded");
 
-        if config.mqtt.enabled {
-            if mqtt_state == "not_connect" {
-                logger.log("MQTT enabled, connecting...");
-                match setup_mqtt_client(&config.mqtt) {
-                    Ok((client, el)) => {
-                        mqtt_client = Some(client);
-                        mqtt_eventloop = Some(el);
-                        logger.log(&format!("Connected to {}:{}", config.mqtt.host, config.mqtt.port));
-                        mqtt_state = "success_connect";
-                    }
-                    Err(e) => {
-                        logger.log(&format!("Connection failed: {}", e));
-                        mqtt_state = "failed_connect";
-                    }
-                }
-            } 
-            else if mqtt_state == "success_connect" {
-                // Handle work mode based logic
-                if config.protocol.work_mode == "once" {
-                        // Check for modbus_read trigger file first (higher priority)
-                    if Path::new(trigger_read_path).exists() {
-                        // logger.log("Modbus read trigger detected");
-                        
-                        // Add 3 second timeout for Modbus read
-                        let read_future = read_modbus_data(&mut modbus_ctx, &config.protocol, &config.serial, &logger);
-                        let timeout_future = tokio::time::sleep(Duration::from_millis(config.protocol.timeout * 100));
-                        
-                        let modbus_result = tokio::select! {
-                            result = read_future => Some(result),
-                            _ = timeout_future => {
-                                logger.log(&format!("Modbus read timeout after {}ms", config.protocol.timeout * 100));
-                                None
-                            }
-                        };
-                        
-                        match modbus_result {
-                            Some(Ok(data)) => {
-                                // logger.log(&format!("Modbus data: {}", data));
-                                match std::fs::write(result_path, &data) {
-                                    Ok(_) => logger.log(&format!("Modbus data received: {}", data)),
-                                    Err(e) => logger.log(&format!("Failed to write result file: {}", e)),
-                                }
-                                
-                                // Publish to MQTT if enabled
-                                if config.mqtt.enabled {
-                                    if let Some(ref client) = mqtt_client {
-                                        let uplink_msg = UplinkMessage { data: data.clone() };
-                                        if let Ok(json) = serde_json::to_string(&uplink_msg) {
-                                            match client.publish(&config.mqtt.uplink_topic, config.mqtt.qos_level, false, json.as_bytes()).await {
-                                                Ok(_) => logger.log(&format!("Published to MQTT: {}", json)),
-                                                Err(e) => logger.log(&format!("MQTT publish failed: {}", e)),
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            Some(Err(e)) => {
-                                logger.log(&format!("Modbus read failed: {}", e));
-                                let error_msg = format!("Error: {}", e);
-                                match std::fs::write(result_path, &error_msg) {
-                                    Ok(_) => logger.log("Error result written"),
-                                    Err(e) => logger.log(&format!("Failed to write error: {}", e)),
-                                }
-                            }
-                            None => {
-                                // Timeout occurred
-                                let error_msg = "Error: Modbus read timeout";
-                                match std::fs::write(result_path, &error_msg) {
-                                    Ok(_) => logger.log("Error result written"),
-                                    Err(e) => logger.log(&format!("Failed to write error: {}", e)),
-                                }
-                            }
+                    if config.serial != new_config.serial {
+                        log_serial(&new_config.serial, &logger);
+                        match bus.reopen(new_config.serial.clone()).await {
+                            Ok(_) => logger.log("Success opening serial port"),
+                            Err(e) => logger.log(&format!("Failed opening serial port {}: {}", new_config.serial.device, e)),
                         }
-                    
-                        let _ = std::fs::remove_file(trigger_read_path);
                     }
-                } 
-                else if config.protocol.work_mode == "periodic" && matches!(config.protocol.function_code, 1 | 2 | 3 | 4) {
-                    // Periodic mode: Read at intervals
-                    let elapsed = last_periodic_read.elapsed();
-                    if elapsed >= Duration::from_secs(config.protocol.poll_interval) {
-                        last_periodic_read = tokio::time::Instant::now();
-                        
-                        let read_future = read_modbus_data(&mut modbus_ctx, &config.protocol, &config.serial, &logger);
-                        let timeout_duration = Duration::from_millis(config.protocol.timeout * 100);
-                        
-                        let modbus_result = tokio::select! {
-                            result = read_future => Some(result),
-                            _ = tokio::time::sleep(timeout_duration) => {
-                                logger.log(&format!("Modbus read timeout after {}ms", config.protocol.timeout * 100));
-                                None
-                            }
-                        };
-                        
-                        match modbus_result {
-                            Some(Ok(data)) => {
-                                // logger.log(&format!("Modbus data: {}", data));
-                                match std::fs::write(result_path, &data) {
-                                    Ok(_) => logger.log(&format!("Modbus data received: {}", data)),
-                                    Err(e) => logger.log(&format!("Failed to write result file: {}", e)),
-                                }
-                                
-                                // Publish to MQTT if enabled
-                                if config.mqtt.enabled {
-                                    if let Some(ref client) = mqtt_client {
-                                        let uplink_msg = UplinkMessage { data: data.clone() };
-                                        if let Ok(json) = serde_json::to_string(&uplink_msg) {
-                                            match client.publish(&config.mqtt.uplink_topic, config.mqtt.qos_level, false, json.as_bytes()).await {
-                                                Ok(_) => logger.log(&format!("Published to MQTT: {}", json)),
-                                                Err(e) => logger.log(&format!("MQTT publish failed: {}", e)),
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            Some(Err(e)) => {
-                                logger.log(&format!("Modbus read failed: {}", e));
-                                let error_msg = format!("Error: {}", e);
-                                match std::fs::write(result_path, &error_msg) {
-                                    Ok(_) => logger.log("Error result written"),
-                                    Err(e) => logger.log(&format!("Failed to write error: {}", e)),
-                                }
-                            }
-                            None => {
-                                // Timeout occurred
-                                let error_msg = "Error: Modbus read timeout";
-                                match std::fs::write(result_path, &error_msg) {
-                                    Ok(_) => logger.log("Error result written"),
-                                    Err(e) => logger.log(&format!("Failed to write error: {}", e)),
-                                }
-                            }
+
+                    // The gateway takes its default slave and timeout from the protocol section
+                    if config.tcp_server != new_config.tcp_server || config.protocol != new_config.protocol {
+                        if let Some(task) = tcp_server.take() {
+                            task.abort();
+                            let _ = task.await;
                         }
+                        tcp_server = start_tcp_server(&new_config, &bus, &logger).await;
                     }
-                }
-                
-                // Then handle MQTT events with timeout
-                tokio::select! {
-                    mqtt_result = async {
-                        if let Some(ref mut el) = mqtt_eventloop {
-                            el.poll().await
-                        } else {
-                            std::future::pending().await
-                        }
-                    } => {
-                        match mqtt_result {
-                            Ok(Event::Incoming(incoming)) => {
-                                match incoming {
-                                    // Handle connection acknowledgment
-                                    Incoming::ConnAck(_) => {
-                                        // Subscribe to downlink topic
-                                        if let Some(ref client) = mqtt_client {
-                                            match client.subscribe(&config.mqtt.downlink_topic, config.mqtt.qos_level).await {
-                                                Ok(_) => {
-                                                    logger.log(&format!("Subscribed [MQTT->RS485] to topic: {}", config.mqtt.downlink_topic));
-                                                }
-                                                Err(e) => {
-                                                    logger.log(&format!("Failed to topic: {}", e));
-                                                }
-                                            }
-                                        }
-                                        logger.log(&format!("Published [RS485->MQTT] to topic: {}", config.mqtt.uplink_topic));
-                                    }
-                                    // Handle incoming publish messages
-                                    Incoming::Publish(p) => {
-                                        let payload = String::from_utf8_lossy(&p.payload);
-                                        logger.log(&format!("MQTT received: {}", payload));
-                                        
-                                        if let Ok(msg) = serde_json::from_str::<DownlinkMessage>(&payload) {
-                                            let data = msg.data.as_bytes();
-                                                
-                                            match tokio_serial::new(&config.serial.device, config.serial.baudrate)
-                                                .data_bits(config.serial.databit)
-                                                .stop_bits(config.serial.stopbit)
-                                                .parity(config.serial.checkbit)
-                                                .flow_control(config.serial.flowcontrol)
-                                                .timeout(config.serial.timeout)
-                                                .open_native_async()
-                                            {
-                                                Ok(mut port) => {
-                                                    match AsyncWriteExt::write_all(&mut port, &data).await {
-                                                        Ok(_) => logger.log(&format!("Forwarded to RS485: {}", msg.data)),
-                                                        Err(e) => logger.log(&format!("RS485 write failed: {}", e)),
-                                                    }
-                                                }
-                                                Err(e) => logger.log(&format!("Failed to open serial port: {}", e)),
-                                            }
-                                        }
-                                    }
-                                    // Handle subscription acknowledgment
-                                    Incoming::SubAck(_) => {
-                                        // logger.log("Subscription acknowledged");
-                                    }
-                                    // Handle disconnection
-                                    Incoming::Disconnect => {
-                                        logger.log("MQTT disconnected");
-                                        mqtt_state = "failed_connect";
-                                    }
-                                    // Handle other incoming events
-                                    _ => {
-                                        // logger.log(&format!("MQTT event: {:?}", other));
-                                    }
-                                }
-                            }
-                            Ok(Event::Outgoing(_)) => {
-                                // Outgoing events are normal
-                            }
-                            Err(e) => {
-                                logger.log(&format!("MQTT error: {}", e));
-                                mqtt_state = "failed_connect";
-                            }
+
+                    if config.mqtt != new_config.mqtt {
+                        if mqtt.is_some() {
+                            logger.log("MQTT settings changed, reconnecting");
                         }
+                        mqtt = None;
+                        mqtt_connected = false;
+                        mqtt_state = "not_connect";
                     }
-                    _ = tokio::time::sleep(Duration::from_millis(1000)) => {
-                        // Timeout to ensure trigger file checked regularly
+
+                    if config.buffer != new_config.buffer {
+                        spool = open_spool(&new_config.buffer, &logger);
                     }
-                }
-            } 
-            else if mqtt_state == "failed_connect" {
-                tokio::select! {
-                    // Reconnect timer
-                    _ = tokio::time::sleep(Duration::from_secs(config.mqtt.reconnect_delay)) => {
-                        logger.log("Retrying connection...");
-                        match setup_mqtt_client(&config.mqtt) {
-                            Ok((client, el)) => {
-                                mqtt_client = Some(client);
-                                mqtt_eventloop = Some(el);
-
-                                logger.log(&format!("Success connecting to {}:{}", config.mqtt.host, config.mqtt.port));
-                                mqtt_state = "success_connect";
-                            }
-                            Err(e) => {
-                                mqtt_client = None;
-                                mqtt_eventloop = None;
 
-                                logger.log(&format!("Reconnect failed: {}", e));
-                                mqtt_state = "failed_connect";
-                            }
-                        }
+                    config = new_config;
+                    if let Ok(mut current) = shared.config.lock() {
+                        *current = config.clone();
                     }
                 }
+                Err(e) => {
+                    logger.log(&format!("Failed to reload config, keeping the current one: {}", e));
+                }
             }
-        } 
-        else {
-            // MQTT disabled, only check trigger file
-            if config.protocol.work_mode == "once" {
-                if Path::new(trigger_read_path).exists() {
-                    // logger.log("Modbus read trigger detected");
-                    
-                    // Add 3 second timeout for Modbus read
-                    let read_future = read_modbus_data(&mut modbus_ctx, &config.protocol, &config.serial, &logger);
-                    let timeout_future = tokio::time::sleep(Duration::from_millis(config.protocol.timeout * 100));
-                    
-                    let modbus_result = tokio::select! {
-                        result = read_future => Some(result),
-                        _ = timeout_future => {
-                            logger.log(&format!("Modbus read timeout after {}ms", config.protocol.timeout * 100));
-                            None
-                        }
-                    };
-
-                    match modbus_result {
-                        Some(Ok(data)) => {
-                            // logger.log(&format!("Modbus data: {}", data));
-                            match std::fs::write(result_path, &data) {
-                                Ok(_) => logger.log(&format!("Modbus data received: {}", data)),
-                                Err(e) => logger.log(&format!("Failed to write result file: {}", e)),
-                            }
-                        }
-                        Some(Err(e)) => {
-                            logger.log(&format!("Modbus read failed: {}", e));
-                            let error_msg = format!("Error: {}", e);
-                            match std::fs::write(result_path, &error_msg) {
-                                Ok(_) => logger.log("Error result written"),
-                                Err(e) => logger.log(&format!("Failed to write error: {}", e)),
-                            }
+        }
+
+        if !config.mqtt.enabled {
+            if mqtt.take().is_some() {
+                logger.log("MQTT disabled");
+            }
+            mqtt_connected = false;
+            mqtt_state = "not_connect";
+        } else if mqtt.is_none() && mqtt_state == "not_connect" {
+            // Reconnects are made by the connection task; invalid settings wait for a reload
+            logger.log("MQTT enabled, connecting...");
+            match config.mqtt.broker.options() {
+                Ok(options) => {
+                    logger.log(&format!("Connecting to {}:{}", config.mqtt.broker.host, config.mqtt.broker.port));
+                    mqtt = Some(mqtt_link::Connection::spawn(options, Duration::from_secs(config.mqtt.reconnect_delay)));
+                }
+                Err(e) => {
+                    logger.log(&format!("Connection failed: {}", e));
+                    mqtt_state = "failed_connect";
+                }
+            }
+        }
+        if let Ok(mut state) = shared.mqtt_state.lock() {
+            *state = mqtt_state.to_string();
+        }
+
+        // Uplinks go to the broker once it acknowledged the connection, to the buffer otherwise
+        let online = mqtt.is_some() && (mqtt_connected || spool.is_none());
+        let mut outbox = Outbox {
+            client: if online { mqtt.as_ref().map(|(connection, _)| connection) } else { None },
+            spool: if config.mqtt.enabled { spool.as_mut() } else { None },
+            qos: config.mqtt.qos_level,
+        };
+
+        // Poll table: read every entry whose interval has elapsed
+        for poll in &config.polls {
+            let due = match last_poll.get(&poll.name) {
+                Some(last) => last.elapsed() >= Duration::from_secs(poll.interval),
+                None => true,
+            };
+            if !due {
+                continue;
+            }
+            last_poll.insert(poll.name.clone(), tokio::time::Instant::now());
+
+            if poll.transport == "tcp" {
+                // Connections are kept open and re-established after a failed read
+                let key = format!("{}:{}", poll.host, poll.port);
+                if !tcp_contexts.contains_key(&key) {
+                    let timeout = Duration::from_millis(poll.timeout * 100);
+                    match connect_tcp(&poll.host, poll.port, poll.slave_id, timeout).await {
+                        Ok(ctx) => {
+                            logger.log(&format!("[{}] Connected to Modbus TCP device {}", poll.name, key));
+                            tcp_contexts.insert(key.clone(), ctx);
                         }
-                        None => {
-                            // Timeout occurred
-                            let error_msg = "Error: Modbus read timeout";
-                            match std::fs::write(result_path, &error_msg) {
-                                Ok(_) => logger.log("Error result written"),
-                                Err(e) => logger.log(&format!("Failed to write error: {}", e)),
-                            }
+                        Err(e) => {
+                            logger.log(&format!("[{}] Modbus TCP connect failed: {}", poll.name, e));
+                            continue;
                         }
                     }
-                        
-                    let _ = std::fs::remove_file(trigger_read_path);
                 }
-            } 
-            else if config.protocol.work_mode == "periodic" && matches!(config.protocol.function_code, 1 | 2 | 3 | 4) {
-                // Periodic mode: Read at intervals
-                let elapsed = last_periodic_read.elapsed();
-                if elapsed >= Duration::from_secs(config.protocol.poll_interval) {
-                    last_periodic_read = tokio::time::Instant::now();
-                    
-                    let read_future = read_modbus_data(&mut modbus_ctx, &config.protocol, &config.serial, &logger);
-                    let timeout_duration = Duration::from_millis(config.protocol.timeout * 100);
-                    
-                    let modbus_result = tokio::select! {
-                        result = read_future => Some(result),
-                        _ = tokio::time::sleep(timeout_duration) => {
-                            logger.log(&format!("Modbus read timeout after {}ms", config.protocol.timeout * 100));
-                            None
-                        }
-                    };
-                    
-                    match modbus_result {
-                        Some(Ok(data)) => {
-                            // logger.log(&format!("Modbus data: {}", data));
-                            match std::fs::write(result_path, &data) {
-                                Ok(_) => logger.log(&format!("Modbus data received: {}", data)),
-                                Err(e) => logger.log(&format!("Failed to write result file: {}", e)),
-                            }
-                        }
-                        Some(Err(e)) => {
-                            logger.log(&format!("Modbus read failed: {}", e));
-                            let error_msg = format!("Error: {}", e);
-                            match std::fs::write(result_path, &error_msg) {
-                                Ok(_) => logger.log("Error result written"),
-                                Err(e) => logger.log(&format!("Failed to write error: {}", e)),
-                            }
-                        }
-                        None => {
-                            // Timeout occurred
-                            let error_msg = "Error: Modbus read timeout";
-                            match std::fs::write(result_path, &error_msg) {
-                                Ok(_) => logger.log("Error result written"),
-                                Err(e) => logger.log(&format!("Failed to write error: {}", e)),
-                            }
-                        }
+                if let Some(ctx) = tcp_contexts.get_mut(&key) {
+                    let uplink = run_poll(Link::Tcp(ctx), poll, &config, &mut outbox, &mut reporter, &logger).await;
+                    shared.set_last_result(&uplink);
+                    // An exception reply means the connection itself is fine
+                    if !matches!(uplink.status.as_str(), "ok" | "exception") {
+                        tcp_contexts.remove(&key);
                     }
                 }
-            }
-            
-            if mqtt_state != "not_connect" {
-                mqtt_client = None;
-                mqtt_eventloop = None;
-                logger.log("MQTT disabled");
-                mqtt_state = "not_connect";
+            } else {
+                let uplink = run_poll(Link::Bus(&bus), poll, &config, &mut outbox, &mut reporter, &logger).await;
+                shared.set_last_result(&uplink);
             }
         }
 
-        // Check for modbus_write trigger file (works regardless of MQTT state)
-        if Path::new(trigger_write_path).exists() {
-            let write_future = read_modbus_data(&mut modbus_ctx, &config.protocol, &config.serial, &logger);
-            let timeout_future = tokio::time::sleep(Duration::from_secs(3));
-            
-            let modbus_result = tokio::select! {
-                result = write_future => Some(result),
-                _ = timeout_future => {
-                    logger.log("Modbus write operation timeout after 3 seconds");
-                    None
+        // Scheduled writes run whatever the MQTT state so watchdog registers keep being refreshed;
+        // reconnect delays are waited out by the connection task, never by this loop
+        let now = Local::now();
+        for entry in &config.schedules {
+            let due = match &entry.cron {
+                Some(cron) => {
+                    let minute = now.timestamp() / 60;
+                    cron.matches(&now) && last_cron.insert(entry.name.clone(), minute) != Some(minute)
                 }
+                None => match last_write.get(&entry.name) {
+                    Some(last) => last.elapsed() >= Duration::from_secs(entry.interval),
+                    None => true,
+                },
             };
-            
-            match modbus_result {
-                Some(Ok(data)) => {
-                    logger.log(&format!("Modbus write successful: {}", data));
-                    if let Err(e) = std::fs::write(result_path, &data) {
-                        logger.log(&format!("Failed to write result file: {}", e));
+            if !due {
+                continue;
+            }
+            last_write.insert(entry.name.clone(), tokio::time::Instant::now());
+
+            let uplink = run_transaction(Link::Bus(&bus), &entry.protocol(&config.protocol), &config, &entry.name).await;
+            match &uplink.error {
+                None => logger.log(&format!("[{}] Scheduled write done: {}", entry.name, uplink.data)),
+                Some(e) => logger.log(&format!("[{}] Scheduled write failed ({}): {}", entry.name, uplink.status, e)),
+            }
+            shared.set_last_result(&uplink);
+
+            if let Some(json) = uplink.to_payload(&config.protocol.payload_format) {
+                outbox.publish(&entry.topic, &json, &logger).await;
+            } else {
+                logger.log(&format!("[{}] Write failure not published: payload_format \"legacy\" only carries data", entry.name));
+            }
+        }
+
+        // Replay buffered uplinks in order, a few at a time so live uplinks and downlinks keep flowing
+        if let (true, Some(spool), Some((connection, _))) = (mqtt_connected, spool.as_mut(), &mqtt) {
+            if spool.pending() > 0 {
+                match spool.replay(connection, config.mqtt.qos_level) {
+                    Ok(sent) if sent > 0 => logger.log(&format!("Replayed {} buffered uplinks ({} pending)", sent, spool.pending())),
+                    Ok(_) => {}
+                    Err(e) => logger.log(&format!("Uplink buffer replay failed: {}", e)),
+                }
+            }
+        }
+
+        // Wait for the broker, a reload or the next poll round
+        let event = tokio::select! {
+            Some(event) = async {
+                match mqtt.as_mut() {
+                    Some((_, events)) => events.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => Some(event),
+            _ = sleep(Duration::from_millis(100)) => None,
+            _ = hangup.recv() => {
+                reload = true;
+                None
+            }
+        };
+
+        match event {
+            Some(mqtt_link::Event::Connected) => {
+                mqtt_connected = true;
+                mqtt_state = "success_connect";
+                // Subscribe to downlink topic
+                if let Some((connection, _)) = &mqtt {
+                    match connection.subscribe(&config.mqtt.downlink_topic, config.mqtt.qos_level) {
+                        Ok(_) => {
+                            logger.log(&format!("Subscribed [MQTT->RS485] to topic: {}", config.mqtt.downlink_topic));
+                        }
+                        Err(e) => {
+                            logger.log(&format!("Failed to topic: {}", e));
+                        }
                     }
                 }
-                Some(Err(e)) => {
-                    let error_msg = format!("Error: Modbus write failed - {}", e);
-                    logger.log(&error_msg);
-                    let _ = std::fs::write(result_path, error_msg);
+                logger.log(&format!("Published [RS485->MQTT] to topic: {}", config.mqtt.uplink_topic));
+            }
+            Some(mqtt_link::Event::Message(p)) => {
+                let payload = String::from_utf8_lossy(&p.payload);
+                logger.log(&format!("MQTT received: {}", payload));
+
+                let connection = mqtt.as_ref().map(|(connection, _)| connection);
+                match serde_json::from_str::<ModbusCommand>(&payload) {
+                    Ok(command) => {
+                        let response = run_command(&bus, &command, &config).await;
+                        shared.set_last_result(&response);
+                        if let Ok(json) = serde_json::to_string(&response) {
+                            publish_response(connection, &config.mqtt, json, &logger);
+                        }
+                    }
+                    Err(command_error) => match serde_json::from_str::<DownlinkMessage>(&payload) {
+                        Ok(msg) => match bus.write(msg.data.clone().into_bytes()).await {
+                            Ok(_) => logger.log(&format!("Forwarded to RS485: {}", msg.data)),
+                            Err(e) => logger.log(&format!("RS485 write failed: {}", e)),
+                        },
+                        Err(_) => {
+                            logger.log(&format!("Invalid downlink, neither a Modbus command nor {{\"data\":...}}: {}", command_error));
+                            // A request that carries an id still gets an answer
+                            let id = serde_json::from_str::<serde_json::Value>(&payload)
+                                .ok()
+                                .and_then(|value| value.get("id").cloned())
+                                .filter(|id| !id.is_null());
+                            if let Some(id) = id {
+                                let response = serde_json::json!({
+                                    "id": id,
+                                    "gateway_id": config.mqtt.gateway_id,
+                                    "name": "command",
+                                    "timestamp": Local::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                                    "status": "error",
+                                    "error": format!("Invalid command: {}", command_error),
+                                });
+                                publish_response(connection, &config.mqtt, response.to_string(), &logger);
+                            }
+                        }
+                    },
                 }
-                None => {
-                    let error_msg = "Error: Modbus write timeout";
-                    logger.log(error_msg);
-                    let _ = std::fs::write(result_path, error_msg);
+            }
+            // A replayed uplink reached the broker and leaves the buffer
+            Some(mqtt_link::Event::Delivered(id)) => {
+                if let Some(spool) = spool.as_mut() {
+                    if let Err(e) = spool.ack(id) {
+                        logger.log(&format!("Uplink buffer update failed: {}", e));
+                    }
                 }
             }
-            
-            // Remove trigger file
-            let _ = std::fs::remove_file(trigger_write_path);
+            // The connection task retries after reconnect_delay
+            Some(mqtt_link::Event::Disconnected(e)) => {
+                logger.log(&format!("MQTT error: {}", e));
+                mqtt_connected = false;
+                mqtt_state = "failed_connect";
+                // Unacknowledged replays are sent again on the next connection
+                if let Some(spool) = spool.as_mut() {
+                    spool.rewind();
+                }
+            }
+            None => {}
         }
-
-        sleep(Duration::from_millis(100)).await;
     }
 }
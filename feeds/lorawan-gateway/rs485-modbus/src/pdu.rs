@@ -0,0 +1,242 @@
+use crate::error::ModbusError;
+use crate::{ModbusData, ProtocolConfig};
+
+// Parse "0x"-prefixed hex or decimal register values
+fn parse_u16(value: &str) -> Option<u16> {
+    let value = value.trim();
+    if value.starts_with("0x") || value.starts_with("0X") {
+        u16::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse::<u16>().ok()
+    }
+}
+
+// Comma-separated coil values for FC05/FC15
+pub fn write_coils(config: &ProtocolConfig) -> Vec<bool> {
+    config.write_value.trim().split(',')
+        .filter_map(|s| s.trim().parse::<u16>().ok())
+        .map(|v| v != 0)
+        .collect()
+}
+
+// Comma-separated register values for FC06/FC16
+pub fn write_registers(config: &ProtocolConfig) -> Vec<u16> {
+    config.write_value.trim().split(',').filter_map(parse_u16).collect()
+}
+
+// Space-separated hex bytes used by non-standard mode (e.g. "00 5A")
+fn hex_bytes(config: &ProtocolConfig) -> Result<Vec<u8>, ModbusError> {
+    let mut data_bytes = Vec::new();
+    for part in config.write_value.split_whitespace() {
+        match u8::from_str_radix(part, 16) {
+            Ok(byte) => data_bytes.push(byte),
+            Err(_) => return Err(ModbusError::Other(format!("Invalid hex value: {}", part))),
+        }
+    }
+    if data_bytes.is_empty() {
+        return Err(ModbusError::Other("No data bytes provided in non-standard mode".to_string()));
+    }
+    Ok(data_bytes)
+}
+
+// Build the request PDU (function code onward) for one transaction
+pub fn request(config: &ProtocolConfig) -> Result<Vec<u8>, ModbusError> {
+    let addr = config.register_address;
+    let mut pdu = vec![config.function_code];
+    pdu.extend_from_slice(&addr.to_be_bytes());
+
+    // Non-standard mode: the configured hex bytes follow the address as-is
+    if matches!(config.function_code, 5 | 6 | 15 | 16) && !config.standard_mode {
+        let data_bytes = hex_bytes(config)?;
+        match config.function_code {
+            15 | 16 => {
+                let quantity = if config.function_code == 15 {
+                    config.data_length
+                } else {
+                    (data_bytes.len() / 2) as u16
+                };
+                pdu.extend_from_slice(&quantity.to_be_bytes());
+                pdu.push(data_bytes.len() as u8);
+            }
+            _ => {}
+        }
+        pdu.extend_from_slice(&data_bytes);
+        return Ok(pdu);
+    }
+
+    match config.function_code {
+        1..=4 => pdu.extend_from_slice(&config.data_length.to_be_bytes()),
+        5 => {
+            let value = config.write_value.trim().parse::<u16>().unwrap_or(0) != 0;
+            pdu.extend_from_slice(if value { &[0xFF, 0x00] } else { &[0x00, 0x00] });
+        }
+        6 => pdu.extend_from_slice(&parse_u16(&config.write_value).unwrap_or(0).to_be_bytes()),
+        15 => {
+            let values = write_coils(config);
+            if values.is_empty() {
+                return Err(ModbusError::Other("No valid values provided for Write Multiple Coils".to_string()));
+            }
+            let mut bytes = vec![0u8; values.len().div_ceil(8)];
+            for (i, value) in values.iter().enumerate() {
+                if *value {
+                    bytes[i / 8] |= 1 << (i % 8);
+                }
+            }
+            pdu.extend_from_slice(&(values.len() as u16).to_be_bytes());
+            pdu.push(bytes.len() as u8);
+            pdu.extend_from_slice(&bytes);
+        }
+        16 => {
+            let values = write_registers(config);
+            if values.is_empty() {
+                return Err(ModbusError::Other("No valid values provided for Write Multiple Registers".to_string()));
+            }
+            pdu.extend_from_slice(&(values.len() as u16).to_be_bytes());
+            pdu.push((values.len() * 2) as u8);
+            for value in values {
+                pdu.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        _ => return Err(ModbusError::Other(format!("Unsupported function code: {}", config.function_code))),
+    }
+    Ok(pdu)
+}
+
+// Decode a validated response PDU; an empty PDU is an unanswered broadcast
+pub fn response(config: &ProtocolConfig, pdu: &[u8]) -> Result<ModbusData, ModbusError> {
+    if pdu.is_empty() {
+        return match config.function_code {
+            5 => Ok(ModbusData::Coil(write_coils(config).first().copied().unwrap_or(false))),
+            15 => Ok(ModbusData::Coils(write_coils(config))),
+            6 | 16 => Ok(ModbusData::Registers(write_registers(config))),
+            _ => Err(ModbusError::Other("Broadcast reads get no response".to_string())),
+        };
+    }
+
+    let count = config.data_length as usize;
+    match config.function_code {
+        1 | 2 => {
+            let bytes = &pdu[2..];
+            if bytes.len() != count.div_ceil(8) {
+                return Err(ModbusError::Framing(format!("{} data bytes for {} coils", bytes.len(), count)));
+            }
+            Ok(ModbusData::Coils((0..count).map(|i| bytes[i / 8] & (1 << (i % 8)) != 0).collect()))
+        }
+        3 | 4 => {
+            let bytes = &pdu[2..];
+            if bytes.len() != count * 2 {
+                return Err(ModbusError::Framing(format!("{} data bytes for {} registers", bytes.len(), count)));
+            }
+            Ok(ModbusData::Registers(bytes.chunks(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect()))
+        }
+        _ => {
+            // Writes echo the address followed by the value or quantity
+            let value = u16::from_be_bytes([pdu[3], pdu[4]]);
+            Ok(match config.function_code {
+                5 if config.standard_mode => ModbusData::Coil(value != 0),
+                5 => ModbusData::CoilEcho(value),
+                6 => ModbusData::Registers(vec![value]),
+                15 if config.standard_mode => ModbusData::Coils(write_coils(config)),
+                16 if config.standard_mode => ModbusData::Registers(write_registers(config)),
+                15 => ModbusData::CoilCount(value),
+                _ => ModbusData::RegisterCount(value),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(function_code: u8, data_length: u16, write_value: &str) -> ProtocolConfig {
+        ProtocolConfig {
+            device_address: 1,
+            function_code,
+            register_address: 0x0010,
+            data_length,
+            write_value: write_value.to_string(),
+            standard_mode: true,
+            work_mode: String::new(),
+            poll_interval: 0,
+            timeout: 10,
+            payload_format: String::new(),
+        }
+    }
+
+    #[test]
+    fn read_requests() {
+        assert_eq!(request(&config(3, 10, "")).unwrap(), [0x03, 0x00, 0x10, 0x00, 0x0A]);
+        assert_eq!(request(&config(1, 20, "")).unwrap(), [0x01, 0x00, 0x10, 0x00, 0x14]);
+    }
+
+    #[test]
+    fn write_requests() {
+        assert_eq!(request(&config(5, 1, "1")).unwrap(), [0x05, 0x00, 0x10, 0xFF, 0x00]);
+        assert_eq!(request(&config(6, 1, "0x1234")).unwrap(), [0x06, 0x00, 0x10, 0x12, 0x34]);
+        // Ten coils packed least significant bit first
+        assert_eq!(
+            request(&config(15, 10, "1,0,1,1,0,0,1,1,1,0")).unwrap(),
+            [0x0F, 0x00, 0x10, 0x00, 0x0A, 0x02, 0xCD, 0x01]
+        );
+        assert_eq!(
+            request(&config(16, 2, "10, 0xFFFF")).unwrap(),
+            [0x10, 0x00, 0x10, 0x00, 0x02, 0x04, 0x00, 0x0A, 0xFF, 0xFF]
+        );
+        assert!(request(&config(16, 2, "")).is_err());
+        assert!(request(&config(7, 1, "")).is_err());
+    }
+
+    #[test]
+    fn non_standard_write() {
+        let mut raw = config(16, 1, "00 5A 01 02");
+        raw.standard_mode = false;
+        assert_eq!(request(&raw).unwrap(), [0x10, 0x00, 0x10, 0x00, 0x02, 0x04, 0x00, 0x5A, 0x01, 0x02]);
+        raw.write_value = "zz".to_string();
+        assert!(request(&raw).is_err());
+    }
+
+    #[test]
+    fn read_responses() {
+        assert_eq!(
+            response(&config(3, 2, ""), &[0x03, 0x04, 0x00, 0x0A, 0xFF, 0xFF]),
+            Ok(ModbusData::Registers(vec![10, 0xFFFF]))
+        );
+        assert_eq!(
+            response(&config(2, 3, ""), &[0x02, 0x01, 0x05]),
+            Ok(ModbusData::Coils(vec![true, false, true]))
+        );
+        // Fewer registers than requested
+        assert!(matches!(response(&config(3, 2, ""), &[0x03, 0x02, 0x00, 0x0A]), Err(ModbusError::Framing(_))));
+    }
+
+    #[test]
+    fn write_responses() {
+        assert_eq!(response(&config(5, 1, "1"), &[0x05, 0x00, 0x10, 0xFF, 0x00]), Ok(ModbusData::Coil(true)));
+        assert_eq!(
+            response(&config(16, 2, "1,2"), &[0x10, 0x00, 0x10, 0x00, 0x02]),
+            Ok(ModbusData::Registers(vec![1, 2]))
+        );
+        let mut raw = config(16, 2, "00 01 00 02");
+        raw.standard_mode = false;
+        assert_eq!(response(&raw, &[0x10, 0x00, 0x10, 0x00, 0x02]), Ok(ModbusData::RegisterCount(2)));
+        let mut raw = config(5, 1, "FF 00");
+        raw.standard_mode = false;
+        assert_eq!(response(&raw, &[0x05, 0x00, 0x10, 0xFF, 0x00]), Ok(ModbusData::CoilEcho(0xFF00)));
+    }
+
+    #[test]
+    fn legacy_data_strings() {
+        assert_eq!(ModbusData::Coils(vec![true, false]).to_string(), "Coils: [1, 0]");
+        assert_eq!(ModbusData::Coil(true).to_string(), "Coils: [true]");
+        assert_eq!(ModbusData::CoilEcho(0xFF00).to_string(), "Coils: [0xFF00]");
+        assert_eq!(ModbusData::Registers(vec![10]).to_string(), "Registers: [0x000A]");
+        assert_eq!(ModbusData::CoilCount(3).to_string(), "Coils: [count=3]");
+    }
+
+    #[test]
+    fn broadcast_responses() {
+        assert_eq!(response(&config(6, 1, "7"), &[]), Ok(ModbusData::Registers(vec![7])));
+        assert!(response(&config(3, 1, ""), &[]).is_err());
+    }
+}
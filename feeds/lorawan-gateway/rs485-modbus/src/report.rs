@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::decode::{DecodedPoint, PointConfig, PointValue};
+
+// When a point is included in an uplink (UCI point options `publish` and `deadband`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PublishPolicy {
+    Always,
+    OnChange,
+    Deadband(f64),
+    DeadbandPercent(f64),
+}
+
+impl PublishPolicy {
+    pub fn parse(value: &str, deadband: f64) -> PublishPolicy {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "change" | "on_change" => PublishPolicy::OnChange,
+            "deadband" => PublishPolicy::Deadband(deadband.abs()),
+            "percent" | "deadband_percent" => PublishPolicy::DeadbandPercent(deadband.abs()),
+            _ => PublishPolicy::Always,
+        }
+    }
+
+    fn exceeded(&self, last: &PointValue, value: &PointValue) -> bool {
+        match (self, last, value) {
+            (PublishPolicy::Always, _, _) => true,
+            (PublishPolicy::Deadband(band), PointValue::Number(last), PointValue::Number(value)) => (value - last).abs() > *band,
+            (PublishPolicy::DeadbandPercent(percent), PointValue::Number(last), PointValue::Number(value)) => {
+                // Relative to the last published value; any change counts when that was zero
+                if *last == 0.0 {
+                    value != last
+                } else {
+                    (value - last).abs() > last.abs() * percent / 100.0
+                }
+            }
+            _ => last != value,
+        }
+    }
+}
+
+// Last published value of every point, keyed by poll and point name
+#[derive(Default)]
+pub struct Reporter {
+    published: HashMap<String, (PointValue, Instant)>,
+}
+
+impl Reporter {
+    // Keep the points that their policy says to publish
+    pub fn select(&self, poll: &str, configs: &[PointConfig], points: &[DecodedPoint]) -> Vec<DecodedPoint> {
+        points
+            .iter()
+            .filter(|point| {
+                let config = configs.iter().find(|c| c.name == point.name);
+                match (config, self.published.get(&format!("{}/{}", poll, point.name))) {
+                    (Some(config), Some((last, at))) => {
+                        config.publish.exceeded(last, &point.value)
+                            || (config.max_silence > 0 && at.elapsed() >= Duration::from_secs(config.max_silence))
+                    }
+                    _ => true,
+                }
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Remember selected points as published, once the uplink was sent or buffered; points of a
+    // lost uplink are selected again on the next poll
+    pub fn commit(&mut self, poll: &str, points: &[DecodedPoint]) {
+        for point in points {
+            self.published.insert(format!("{}/{}", poll, point.name), (point.value.clone(), Instant::now()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{ByteOrder, DataType};
+
+    fn config(publish: PublishPolicy, max_silence: u64) -> PointConfig {
+        PointConfig {
+            name: "voltage".to_string(),
+            poll: "meter".to_string(),
+            address: 0,
+            data_type: DataType::U16,
+            order: ByteOrder::Abcd,
+            length: 0,
+            bit: None,
+            scale: 1.0,
+            offset: 0.0,
+            unit: "V".to_string(),
+            publish,
+            max_silence,
+        }
+    }
+
+    // Whether a reading of `value` is published
+    fn report(reporter: &mut Reporter, config: &PointConfig, value: f64) -> bool {
+        let point = DecodedPoint {
+            name: config.name.clone(),
+            value: PointValue::Number(value),
+            unit: config.unit.clone(),
+        };
+        let selected = reporter.select("meter", std::slice::from_ref(config), &[point]);
+        reporter.commit("meter", &selected);
+        !selected.is_empty()
+    }
+
+    #[test]
+    fn deadband() {
+        let config = config(PublishPolicy::Deadband(0.5), 0);
+        let mut reporter = Reporter::default();
+        // First reading is always published
+        assert!(report(&mut reporter, &config, 230.0));
+        assert!(!report(&mut reporter, &config, 230.3));
+        assert!(!report(&mut reporter, &config, 229.5));
+        assert!(report(&mut reporter, &config, 230.6));
+        // Measured from the last published value, not the last reading
+        assert!(!report(&mut reporter, &config, 230.2));
+        assert!(report(&mut reporter, &config, 230.0));
+    }
+
+    #[test]
+    fn deadband_percent() {
+        let config = config(PublishPolicy::DeadbandPercent(1.0), 0);
+        let mut reporter = Reporter::default();
+        assert!(report(&mut reporter, &config, 200.0));
+        assert!(!report(&mut reporter, &config, 202.0));
+        assert!(report(&mut reporter, &config, 202.1));
+
+        // Any change counts after a zero
+        assert!(report(&mut reporter, &config, 0.0));
+        assert!(report(&mut reporter, &config, 0.01));
+    }
+
+    #[test]
+    fn on_change() {
+        let config = config(PublishPolicy::OnChange, 0);
+        let mut reporter = Reporter::default();
+        assert!(report(&mut reporter, &config, 1.0));
+        assert!(!report(&mut reporter, &config, 1.0));
+        assert!(report(&mut reporter, &config, 2.0));
+    }
+
+    #[test]
+    fn max_silence_forces_publish() {
+        let config = config(PublishPolicy::Deadband(0.5), 60);
+        let mut reporter = Reporter::default();
+        assert!(report(&mut reporter, &config, 230.0));
+        assert!(!report(&mut reporter, &config, 230.1));
+
+        // Last published a minute ago
+        if let Some((_, at)) = reporter.published.get_mut("meter/voltage") {
+            *at -= Duration::from_secs(60);
+        }
+        assert!(report(&mut reporter, &config, 230.1));
+        // The forced publish restarts the interval
+        assert!(!report(&mut reporter, &config, 230.1));
+    }
+
+    #[test]
+    fn uncommitted_points_are_selected_again() {
+        let config = config(PublishPolicy::OnChange, 0);
+        let mut reporter = Reporter::default();
+        assert!(report(&mut reporter, &config, 1.0));
+
+        // The uplink carrying 2.0 was lost
+        let point = DecodedPoint { name: config.name.clone(), value: PointValue::Number(2.0), unit: config.unit.clone() };
+        assert_eq!(reporter.select("meter", std::slice::from_ref(&config), std::slice::from_ref(&point)).len(), 1);
+        assert!(report(&mut reporter, &config, 2.0));
+        assert!(!report(&mut reporter, &config, 2.0));
+    }
+}
@@ -0,0 +1,178 @@
+use chrono::{DateTime, Datelike, TimeZone, Timelike};
+
+// Five-field cron expression: minute hour day-of-month month day-of-week
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    // Restricted day fields match when either one does, as in crontab(5); a field
+    // starting with `*` (also `*/2`) counts as unrestricted
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Option<Cron> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return None;
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 mean Sunday
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        weekdays.truncate(7);
+
+        Some(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    // Checked in the zone of `now`; the daemon passes local time
+    pub fn matches<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> bool {
+        let day = self.days[now.day() as usize];
+        let weekday = self.weekdays[now.weekday().num_days_from_sunday() as usize];
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        self.minutes[now.minute() as usize]
+            && self.hours[now.hour() as usize]
+            && self.months[now.month() as usize]
+            && day_matches
+    }
+}
+
+// One field: `*`, `*/n`, `a`, `a-b`, `a-b/n` and comma-separated lists of those
+fn parse_field(field: &str, min: u32, max: u32) -> Option<Vec<bool>> {
+    let mut set = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (a.parse().ok()?, b.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            // `5/15` runs from 5 to the end of the range
+            (value, if step > 1 { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set[value as usize] = true;
+        }
+    }
+    Some(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    // UTC, so the results do not depend on the host time zone and its DST gaps
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert_eq!(Cron::parse("* * * *"), None);
+        assert_eq!(Cron::parse("* * * * * *"), None);
+        assert_eq!(Cron::parse("60 * * * *"), None);
+        assert_eq!(Cron::parse("* 24 * * *"), None);
+        assert_eq!(Cron::parse("* * 0 * *"), None);
+        assert_eq!(Cron::parse("* * * 13 *"), None);
+        assert_eq!(Cron::parse("* * * * 8"), None);
+        assert_eq!(Cron::parse("10-5 * * * *"), None);
+        assert_eq!(Cron::parse("*/0 * * * *"), None);
+        assert_eq!(Cron::parse("a * * * *"), None);
+    }
+
+    #[test]
+    fn parses_ranges_steps_and_lists() {
+        assert_eq!(parse_field("*", 0, 5).unwrap(), vec![true; 6]);
+        assert_eq!(parse_field("1-3", 0, 5).unwrap(), vec![false, true, true, true, false, false]);
+        assert_eq!(parse_field("*/2", 0, 5).unwrap(), vec![true, false, true, false, true, false]);
+        assert_eq!(parse_field("1-5/2", 0, 5).unwrap(), vec![false, true, false, true, false, true]);
+        assert_eq!(parse_field("3/2", 0, 7).unwrap(), vec![false, false, false, true, false, true, false, true]);
+        assert_eq!(parse_field("0,4", 0, 5).unwrap(), vec![true, false, false, false, true, false]);
+        assert_eq!(parse_field("4", 0, 5).unwrap(), vec![false, false, false, false, true, false]);
+    }
+
+    #[test]
+    fn every_fifteen_minutes_during_working_hours() {
+        let cron = Cron::parse("*/15 8-17 * * *").unwrap();
+        assert!(cron.matches(&at(2026, 10, 15, 8, 0)));
+        assert!(cron.matches(&at(2026, 10, 15, 17, 45)));
+        assert!(!cron.matches(&at(2026, 10, 15, 8, 20)));
+        assert!(!cron.matches(&at(2026, 10, 15, 18, 0)));
+        assert!(!cron.matches(&at(2026, 10, 15, 7, 45)));
+    }
+
+    #[test]
+    fn sunday_is_zero_or_seven() {
+        // 2026-10-18 is a Sunday, 2026-10-19 a Monday
+        for expr in ["0 12 * * 0", "0 12 * * 7"] {
+            let cron = Cron::parse(expr).unwrap();
+            assert!(cron.matches(&at(2026, 10, 18, 12, 0)), "{}", expr);
+            assert!(!cron.matches(&at(2026, 10, 19, 12, 0)), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // The 15th or any Monday
+        let cron = Cron::parse("0 0 15 * 1").unwrap();
+        assert!(cron.matches(&at(2026, 10, 15, 0, 0)));  // Thursday the 15th
+        assert!(cron.matches(&at(2026, 10, 19, 0, 0)));  // Monday the 19th
+        assert!(!cron.matches(&at(2026, 10, 18, 0, 0))); // Sunday the 18th
+    }
+
+    #[test]
+    fn star_step_day_field_is_unrestricted() {
+        // Odd days that are Mondays, not odd days or Mondays
+        let cron = Cron::parse("0 0 */2 * 1").unwrap();
+        assert!(cron.matches(&at(2026, 10, 19, 0, 0)));  // Monday the 19th
+        assert!(!cron.matches(&at(2026, 10, 12, 0, 0))); // Monday the 12th
+        assert!(!cron.matches(&at(2026, 10, 15, 0, 0))); // Thursday the 15th
+
+        // The 15th when it is a Sunday, Tuesday, Thursday or Saturday
+        let cron = Cron::parse("0 0 15 * */2").unwrap();
+        assert!(cron.matches(&at(2026, 10, 15, 0, 0)));  // Thursday the 15th
+        assert!(!cron.matches(&at(2026, 10, 17, 0, 0))); // Saturday the 17th
+    }
+
+    #[test]
+    fn single_restricted_day_field_must_match() {
+        let days = Cron::parse("0 0 15 * *").unwrap();
+        assert!(days.matches(&at(2026, 10, 15, 0, 0)));
+        assert!(!days.matches(&at(2026, 10, 19, 0, 0)));
+
+        let weekdays = Cron::parse("0 0 * * 1-5").unwrap();
+        assert!(weekdays.matches(&at(2026, 10, 19, 0, 0)));
+        assert!(!weekdays.matches(&at(2026, 10, 18, 0, 0)));
+    }
+
+    #[test]
+    fn month_field_limits_the_year() {
+        let cron = Cron::parse("30 6 1 1,7 *").unwrap();
+        assert!(cron.matches(&at(2026, 7, 1, 6, 30)));
+        assert!(!cron.matches(&at(2026, 6, 1, 6, 30)));
+    }
+}
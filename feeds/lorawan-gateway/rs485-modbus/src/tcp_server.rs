@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::bus::Bus;
+use crate::error::ModbusError;
+use crate::frame;
+use crate::Logger;
+
+// Modbus exception codes sent back to the TCP master
+const EXC_ILLEGAL_FUNCTION: u8 = 0x01;
+const EXC_ILLEGAL_DATA_VALUE: u8 = 0x03;
+const EXC_GATEWAY_TARGET_FAILED: u8 = 0x0B;
+
+// Gateway settings shared by every TCP connection
+#[derive(Clone)]
+pub struct Gateway {
+    pub bus: Bus,
+    pub default_slave: u8,
+    pub timeout: Duration,
+    pub logger: Arc<Logger>,
+}
+
+// Accept Modbus TCP masters and forward their requests to the RTU bus
+pub async fn run(listener: TcpListener, gateway: Gateway) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                gateway.logger.log(&format!("Modbus TCP client connected: {}", peer));
+                let gateway = gateway.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, &gateway).await {
+                        gateway.logger.log(&format!("Modbus TCP client {} error: {}", peer, e));
+                    }
+                    gateway.logger.log(&format!("Modbus TCP client disconnected: {}", peer));
+                });
+            }
+            Err(e) => {
+                gateway.logger.log(&format!("Modbus TCP accept failed: {}", e));
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+// Serve MBAP frames from one master until it disconnects
+async fn handle_connection(mut stream: TcpStream, gateway: &Gateway) -> std::io::Result<()> {
+    loop {
+        // MBAP header: transaction id, protocol id, length, unit id
+        let mut header = [0u8; 7];
+        match stream.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let Some(length) = frame::mbap_pdu_length(&header) else {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid MBAP header"));
+        };
+
+        let mut pdu = vec![0u8; length];
+        stream.read_exact(&mut pdu).await?;
+
+        // Unit 255 addresses the gateway itself and goes to the configured slave;
+        // unit 0 is a broadcast and is passed through as one
+        let unit_id = header[6];
+        let slave = if unit_id == 0xFF { gateway.default_slave } else { unit_id };
+        let response = forward(gateway, slave, &pdu).await;
+        stream.write_all(&frame::mbap_response(&header, &response)).await?;
+    }
+}
+
+// Execute one request PDU on the RTU bus and build the response PDU
+async fn forward(gateway: &Gateway, slave: u8, pdu: &[u8]) -> Vec<u8> {
+    let function_code = pdu[0];
+    let word = |index: usize| -> Option<u16> {
+        Some(u16::from_be_bytes([*pdu.get(index)?, *pdu.get(index + 1)?]))
+    };
+    let value = match (word(1), word(3)) {
+        (Some(_), Some(value)) => value,
+        _ if matches!(function_code, 1 | 2 | 3 | 4 | 5 | 6 | 15 | 16) => {
+            return exception(function_code, EXC_ILLEGAL_DATA_VALUE)
+        }
+        _ => return exception(function_code, EXC_ILLEGAL_FUNCTION),
+    };
+
+    // Reject malformed requests here rather than putting them on the bus
+    let valid = match function_code {
+        1 | 2 => pdu.len() == 5 && (1..=2000).contains(&value),
+        3 | 4 => pdu.len() == 5 && (1..=125).contains(&value),
+        5 => pdu.len() == 5 && (value == 0xFF00 || value == 0x0000),
+        6 => pdu.len() == 5,
+        // The byte count has to be exactly what the quantity needs
+        15 => pdu.len() > 6 && value > 0 && pdu[5] as usize == (value as usize).div_ceil(8) && pdu.len() == 6 + pdu[5] as usize,
+        16 => pdu.len() > 6 && pdu[5] as usize == pdu.len() - 6 && value > 0 && pdu.len() - 6 == value as usize * 2,
+        _ => return exception(function_code, EXC_ILLEGAL_FUNCTION),
+    };
+    if !valid {
+        return exception(function_code, EXC_ILLEGAL_DATA_VALUE);
+    }
+    // Only writes can be broadcast
+    if slave == 0 && matches!(function_code, 1..=4) {
+        return exception(function_code, EXC_ILLEGAL_FUNCTION);
+    }
+
+    match gateway.bus.request(slave, pdu.to_vec(), gateway.timeout).await {
+        // A broadcast gets no reply from the bus; echo the request like a write response
+        Ok(response) if response.is_empty() => pdu[..5].to_vec(),
+        Ok(response) => response,
+        Err(ModbusError::Exception(code)) => exception(function_code, code),
+        Err(e) => {
+            gateway.logger.log(&format!("Modbus TCP forward to slave {} failed: {}", slave, e));
+            exception(function_code, EXC_GATEWAY_TARGET_FAILED)
+        }
+    }
+}
+
+fn exception(function_code: u8, code: u8) -> Vec<u8> {
+    vec![function_code | 0x80, code]
+}
@@ -0,0 +1,128 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+// How serial bytes are carried in MQTT payloads (UCI mqtt option `encoding`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    // Text in the JSON `data` field, trimmed (legacy behaviour)
+    Utf8,
+    // Hex digits in the JSON `data` field, e.g. "0103000A0001"
+    Hex,
+    // Base64 in the JSON `data` field
+    Base64,
+    // The MQTT payload is the serial data itself, no JSON
+    Raw,
+}
+
+impl Encoding {
+    pub fn parse(value: &str) -> Option<Encoding> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" | "text" => Some(Encoding::Utf8),
+            "hex" => Some(Encoding::Hex),
+            "base64" => Some(Encoding::Base64),
+            "raw" => Some(Encoding::Raw),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf8",
+            Encoding::Hex => "hex",
+            Encoding::Base64 => "base64",
+            Encoding::Raw => "raw",
+        }
+    }
+
+    // Text for the JSON `data` field
+    pub fn encode(&self, data: &[u8]) -> String {
+        match self {
+            Encoding::Hex => data.iter().map(|b| format!("{:02X}", b)).collect(),
+            Encoding::Base64 => STANDARD.encode(data),
+            Encoding::Utf8 => String::from_utf8_lossy(data).trim().to_string(),
+            Encoding::Raw => String::from_utf8_lossy(data).into_owned(),
+        }
+    }
+
+    // Bytes to write to the serial port from the JSON `data` field
+    pub fn decode(&self, data: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Hex => {
+                let digits: Vec<u8> = data.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+                let pairs = digits.chunks_exact(2);
+                if !pairs.remainder().is_empty() {
+                    return Err(format!("odd number of hex digits in {:?}", data));
+                }
+                // from_str_radix alone would also take a sign, e.g. "+1"
+                pairs
+                    .map(|pair| {
+                        std::str::from_utf8(pair)
+                            .ok()
+                            .filter(|s| s.bytes().all(|b| b.is_ascii_hexdigit()))
+                            .and_then(|s| u8::from_str_radix(s, 16).ok())
+                            .ok_or_else(|| format!("invalid hex digits in {:?}", data))
+                    })
+                    .collect()
+            }
+            Encoding::Base64 => STANDARD.decode(data.trim()).map_err(|e| format!("invalid base64: {}", e)),
+            Encoding::Utf8 | Encoding::Raw => Ok(data.as_bytes().to_vec()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trip() {
+        let data = [0x01, 0x03, 0x00, 0x0A, 0xFF];
+        assert_eq!(Encoding::Hex.encode(&data), "0103000AFF");
+        assert_eq!(Encoding::Hex.decode("0103000AFF").unwrap(), data);
+        assert_eq!(Encoding::Hex.decode("0103000aff").unwrap(), data);
+        assert_eq!(Encoding::Hex.encode(&[]), "");
+    }
+
+    #[test]
+    fn hex_input() {
+        assert_eq!(Encoding::Hex.decode(" 01 03\n0A\t0B ").unwrap(), [0x01, 0x03, 0x0A, 0x0B]);
+        assert!(Encoding::Hex.decode("010").unwrap_err().contains("odd number"));
+        assert!(Encoding::Hex.decode("01zz").unwrap_err().contains("invalid hex"));
+        assert!(Encoding::Hex.decode("+1").is_err());
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        let data = [0x00, 0xFF, 0x80, 0x0A];
+        let text = Encoding::Base64.encode(&data);
+        assert_eq!(text, "AP+ACg==");
+        assert_eq!(Encoding::Base64.decode(&text).unwrap(), data);
+        assert_eq!(Encoding::Base64.decode(" AP+ACg==\n").unwrap(), data);
+        assert!(Encoding::Base64.decode("AP+ACg=").unwrap_err().starts_with("invalid base64"));
+        assert!(Encoding::Base64.decode("not base64!").is_err());
+    }
+
+    #[test]
+    fn parse() {
+        for name in ["utf8", "UTF-8", "text", " utf8 "] {
+            assert_eq!(Encoding::parse(name), Some(Encoding::Utf8));
+        }
+        assert_eq!(Encoding::parse("HEX"), Some(Encoding::Hex));
+        assert_eq!(Encoding::parse("base64"), Some(Encoding::Base64));
+        assert_eq!(Encoding::parse("raw"), Some(Encoding::Raw));
+        assert_eq!(Encoding::parse("ascii"), None);
+        for encoding in [Encoding::Utf8, Encoding::Hex, Encoding::Base64, Encoding::Raw] {
+            assert_eq!(Encoding::parse(encoding.name()), Some(encoding));
+        }
+    }
+
+    #[test]
+    fn only_utf8_is_trimmed() {
+        assert_eq!(Encoding::Utf8.encode(b" ok\r\n"), "ok");
+        assert_eq!(Encoding::Raw.encode(b" ok\r\n"), " ok\r\n");
+        assert_eq!(Encoding::Hex.encode(b" \n"), "200A");
+        assert_eq!(Encoding::Base64.decode(&Encoding::Base64.encode(b" \n")).unwrap(), b" \n");
+        // Downlinks are written as given
+        assert_eq!(Encoding::Utf8.decode(" ok\r\n").unwrap(), b" ok\r\n");
+    }
+}
@@ -0,0 +1,268 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::Logger;
+
+// How received bytes are cut into uplink frames (UCI `config framing`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum FramingMode {
+    // Every serial read is one frame (legacy behaviour)
+    None,
+    // A frame ends when the line is silent for the given time
+    Idle(Duration),
+    // Every frame has the same number of bytes
+    Fixed(usize),
+    // A frame ends with `end`; bytes before `start` are dropped when it is set
+    Delimiter { start: Vec<u8>, end: Vec<u8> },
+    // Frame length is read from a field in the header
+    Length { offset: usize, size: usize, little_endian: bool, adjust: i64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FramingConfig {
+    pub mode: FramingMode,
+    pub max_length: usize,
+    // Drop an incomplete frame after this much silence (zero keeps it)
+    pub partial_timeout: Duration,
+}
+
+// Space-separated hex bytes, e.g. "0D 0A"
+pub fn parse_hex_bytes(value: &str) -> Option<Vec<u8>> {
+    value.split_whitespace().map(|part| u8::from_str_radix(part.trim_start_matches("0x"), 16).ok()).collect()
+}
+
+// Accumulates serial input until complete frames are available
+pub struct Framer {
+    config: FramingConfig,
+    buffer: Vec<u8>,
+    last_byte: Instant,
+}
+
+impl Framer {
+    pub fn new(config: FramingConfig) -> Framer {
+        Framer {
+            config,
+            buffer: Vec::new(),
+            last_byte: Instant::now(),
+        }
+    }
+
+    // Add received bytes and return every frame they complete
+    pub fn push(&mut self, data: &[u8], logger: &Logger) -> Vec<Vec<u8>> {
+        if self.config.mode == FramingMode::None {
+            return vec![data.to_vec()];
+        }
+        self.buffer.extend_from_slice(data);
+        self.last_byte = Instant::now();
+
+        let mut frames = Vec::new();
+        while let Some(frame) = self.next_frame(logger) {
+            frames.push(frame);
+        }
+
+        if self.buffer.len() > self.config.max_length {
+            logger.log(&format!("Discarding {} bytes without a complete frame (max_length {})", self.buffer.len(), self.config.max_length));
+            self.buffer.clear();
+        }
+        frames
+    }
+
+    // Whether frames end with each read rather than by a framing rule
+    pub fn per_read(&self) -> bool {
+        self.config.mode == FramingMode::None
+    }
+
+    // Remove and return the bytes of an incomplete frame
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+
+    // When the buffered bytes have to be flushed or dropped, if ever
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        match self.config.mode {
+            FramingMode::Idle(gap) => Some(self.last_byte + gap),
+            _ if !self.config.partial_timeout.is_zero() => Some(self.last_byte + self.config.partial_timeout),
+            _ => None,
+        }
+    }
+
+    // Called at the deadline: the idle frame is complete, anything else is a stale partial frame
+    pub fn expire(&mut self, logger: &Logger) -> Option<Vec<u8>> {
+        let data = std::mem::take(&mut self.buffer);
+        if data.is_empty() {
+            return None;
+        }
+        if let FramingMode::Idle(_) = self.config.mode {
+            return Some(data);
+        }
+        logger.log(&format!("Discarding incomplete frame: {:02X?}", data));
+        None
+    }
+
+    fn next_frame(&mut self, logger: &Logger) -> Option<Vec<u8>> {
+        match &self.config.mode {
+            FramingMode::None | FramingMode::Idle(_) => None,
+            FramingMode::Fixed(len) => {
+                let len = (*len).max(1);
+                (self.buffer.len() >= len).then(|| self.buffer.drain(..len).collect())
+            }
+            FramingMode::Delimiter { start, end } => {
+                if !start.is_empty() {
+                    match find(&self.buffer, start, 0) {
+                        Some(0) => {}
+                        Some(pos) => {
+                            logger.log(&format!("Discarding {} bytes before start delimiter", pos));
+                            self.buffer.drain(..pos);
+                        }
+                        None => {
+                            // Keep a possible partial start delimiter at the tail
+                            let keep = (start.len() - 1).min(self.buffer.len());
+                            let drop = self.buffer.len() - keep;
+                            if drop > 0 {
+                                logger.log(&format!("Discarding {} bytes before start delimiter", drop));
+                                self.buffer.drain(..drop);
+                            }
+                            return None;
+                        }
+                    }
+                }
+                let pos = find(&self.buffer, end, start.len())?;
+                Some(self.buffer.drain(..pos + end.len()).collect())
+            }
+            FramingMode::Length { offset, size, little_endian, adjust } => {
+                let header = offset + size;
+                loop {
+                    if self.buffer.len() < header {
+                        return None;
+                    }
+                    let field = &self.buffer[*offset..header];
+                    let value = if *little_endian {
+                        field.iter().rev().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+                    } else {
+                        field.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+                    };
+                    let total = header as i64 + value as i64 + adjust;
+                    if total >= header as i64 && total as usize <= self.config.max_length {
+                        let total = total as usize;
+                        return (self.buffer.len() >= total).then(|| self.buffer.drain(..total).collect());
+                    }
+                    // Not a plausible header; resynchronise on the next byte
+                    logger.log(&format!("Invalid frame length {}, skipping byte {:02X}", value, self.buffer[0]));
+                    self.buffer.remove(0);
+                }
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < from + needle.len() {
+        return None;
+    }
+    haystack[from..].windows(needle.len()).position(|w| w == needle).map(|pos| pos + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_mode(mode: FramingMode) -> Framer {
+        Framer::new(FramingConfig { mode, max_length: 16, partial_timeout: Duration::ZERO })
+    }
+
+    #[test]
+    fn idle_gap() {
+        let logger = Logger::new();
+        let gap = Duration::from_millis(20);
+        let mut framer = with_mode(FramingMode::Idle(gap));
+        assert_eq!(framer.deadline(), None);
+
+        // Reads are joined until the line has been silent for the gap
+        assert!(framer.push(&[0x01, 0x02], &logger).is_empty());
+        assert!(framer.push(&[0x03], &logger).is_empty());
+        assert_eq!(framer.deadline(), Some(framer.last_byte + gap));
+        assert_eq!(framer.expire(&logger), Some(vec![0x01, 0x02, 0x03]));
+        assert_eq!(framer.deadline(), None);
+        assert_eq!(framer.expire(&logger), None);
+    }
+
+    #[test]
+    fn fixed_length() {
+        let logger = Logger::new();
+        let mut framer = with_mode(FramingMode::Fixed(3));
+        assert_eq!(framer.push(&[1, 2, 3, 4, 5, 6, 7], &logger), [vec![1, 2, 3], vec![4, 5, 6]]);
+        assert_eq!(framer.push(&[8, 9], &logger), [vec![7, 8, 9]]);
+    }
+
+    #[test]
+    fn length_field() {
+        let logger = Logger::new();
+        // One sync byte, then a big-endian u16 payload length, then the payload and a checksum byte
+        let mut framer = with_mode(FramingMode::Length { offset: 1, size: 2, little_endian: false, adjust: 1 });
+        assert!(framer.push(&[0xAA, 0x00, 0x02, 0x10], &logger).is_empty());
+        assert_eq!(framer.push(&[0x20, 0xFF, 0xAA], &logger), [vec![0xAA, 0x00, 0x02, 0x10, 0x20, 0xFF]]);
+        assert_eq!(framer.take(), [0xAA]);
+
+        let mut framer = with_mode(FramingMode::Length { offset: 0, size: 2, little_endian: true, adjust: 0 });
+        assert_eq!(framer.push(&[0x01, 0x00, 0x55], &logger), [vec![0x01, 0x00, 0x55]]);
+    }
+
+    #[test]
+    fn length_field_resynchronises() {
+        let logger = Logger::new();
+        let mut framer = with_mode(FramingMode::Length { offset: 0, size: 1, little_endian: false, adjust: 0 });
+        // 0x40 is longer than max_length, so it is skipped as noise
+        assert_eq!(framer.push(&[0x40, 0x02, 0x0A, 0x0B], &logger), [vec![0x02, 0x0A, 0x0B]]);
+    }
+
+    #[test]
+    fn delimiters() {
+        let logger = Logger::new();
+        let mut framer = with_mode(FramingMode::Delimiter { start: vec![], end: vec![0x0D, 0x0A] });
+        assert_eq!(framer.push(b"ab\r", &logger), Vec::<Vec<u8>>::new());
+        assert_eq!(framer.push(b"\ncd\r\nef", &logger), [b"ab\r\n".to_vec(), b"cd\r\n".to_vec()]);
+        assert_eq!(framer.take(), b"ef");
+
+        // Bytes before the start delimiter are dropped, a partial start is kept
+        let mut framer = with_mode(FramingMode::Delimiter { start: b"<<".to_vec(), end: b">".to_vec() });
+        assert!(framer.push(b"xx<", &logger).is_empty());
+        assert_eq!(framer.push(b"<1>zz<<2>", &logger), [b"<<1>".to_vec(), b"<<2>".to_vec()]);
+    }
+
+    #[test]
+    fn max_length_and_partial_timeout() {
+        let logger = Logger::new();
+        let mut framer = Framer::new(FramingConfig {
+            mode: FramingMode::Delimiter { start: vec![], end: vec![0x0A] },
+            max_length: 4,
+            partial_timeout: Duration::from_millis(50),
+        });
+        assert!(framer.push(&[1, 2, 3, 4, 5], &logger).is_empty());
+        assert!(framer.take().is_empty());
+
+        assert!(framer.push(&[1, 2], &logger).is_empty());
+        assert_eq!(framer.deadline(), Some(framer.last_byte + Duration::from_millis(50)));
+        // A stale partial frame is dropped, not sent
+        assert_eq!(framer.expire(&logger), None);
+        assert_eq!(framer.deadline(), None);
+    }
+
+    #[test]
+    fn per_read() {
+        let logger = Logger::new();
+        let mut framer = with_mode(FramingMode::None);
+        assert!(framer.per_read());
+        assert_eq!(framer.push(&[1, 2], &logger), [vec![1, 2]]);
+        assert_eq!(framer.deadline(), None);
+    }
+
+    #[test]
+    fn hex_bytes() {
+        assert_eq!(parse_hex_bytes("0D 0A"), Some(vec![0x0D, 0x0A]));
+        assert_eq!(parse_hex_bytes("0x7E"), Some(vec![0x7E]));
+        assert_eq!(parse_hex_bytes("0D zz"), None);
+    }
+}
@@ -1,44 +1,41 @@
 use chrono::Local;
-use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS, Transport};
-use rumqttc::tokio_rustls::rustls::ClientConfig as RustlsClientConfig;
-use rustls_pemfile::{certs, pkcs8_private_keys};
+use rumqttc::QoS;
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::process::Command;
 use std::sync::{Arc, Mutex as StdMutex};
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::time::sleep;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use mqtt_link::spool;
+use rs485_line::direction;
 use tokio_serial::{DataBits, Parity, StopBits, SerialPortBuilderExt};
 
+mod encoding;
+mod framing;
+mod port;
+mod transaction;
+
 // Mqtt and Serial Configuration Structures
 #[derive(Debug, Clone, PartialEq)]
 struct Config {
     mqtt: MqttConfig,
-    serial: SerialConfig,
+    ports: Vec<PortConfig>,
+    buffer: BufferConfig,
 }
 
 // MQTT Configuration Structure
 #[derive(Debug, Clone, PartialEq)]
 struct MqttConfig {
     enabled: bool,
-    transport: String,
-    host: String,
-    port: u16,
-    username: Option<String>,
-    password: Option<String>,
-    client_id: String,
-    keepalive: u64,
+    broker: mqtt_link::Broker,
     uplink_topic: String,
     downlink_topic: String,
+    response_topic: String,
     qos_level: QoS,
     reconnect_delay: u64,
-    auth_mode: String,
-    ca_cert: Option<String>,
-    client_cert: Option<String>,
-    client_key: Option<String>,
     token: Option<String>,
+    encoding: encoding::Encoding,
 }
 
 // Serial Configuration Structure
@@ -51,18 +48,61 @@ struct SerialConfig {
     checkbit: Parity,
     flowcontrol: tokio_serial::FlowControl,
     timeout: Duration,
+    direction: direction::DirectionConfig,
+}
+
+// One bridged serial port with its own line settings, framing and topics
+#[derive(Debug, Clone, PartialEq)]
+struct PortConfig {
+    name: String,
+    serial: SerialConfig,
+    framing: framing::FramingConfig,
+    uplink_topic: String,
+    downlink_topic: String,
+    response_topic: String,
+}
+
+// Store-and-forward Buffer Configuration Structure
+#[derive(Debug, Clone, PartialEq)]
+struct BufferConfig {
+    enabled: bool,
+    path: String,
+    max_size: u64,
+    max_age: u64,
 }
 
 // RS485 -> MQTT Message Structure
 #[derive(Debug, Serialize)]
 struct UplinkMessage {
     data: String,
+    encoding: &'static str,
+    // Read time, only set on uplinks held in the buffer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
 }
 
 // MQTT -> RS485 Message Structure
 #[derive(Debug, Deserialize)]
 struct DownlinkMessage {
     data: String,
+    // Overrides the configured encoding for this message
+    #[serde(default)]
+    encoding: Option<String>,
+    // Set when the sender waits for the serial reply on the response topic
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+// Serial reply to a downlink request, published with the request id
+#[derive(Debug, Serialize)]
+struct ResponseMessage {
+    id: serde_json::Value,
+    // ok, timeout, busy or error
+    status: &'static str,
+    data: String,
+    encoding: &'static str,
 }
 
 // Logger Structure
@@ -115,324 +155,459 @@ impl Logger {
 
 // Load configuration from UCI
 fn load_config_from_uci() -> Result<Config, Box<dyn std::error::Error + Send + Sync>> {
-    // uci get helper function
-    let uci_get = |config: &str, section: &str, option: &str| -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let output = Command::new("uci")
-            .args(&["get", &format!("{}.{}.{}", config, section, option)])
-            .output()?;
-        if output.status.success() {
-            Ok(String::from_utf8(output.stdout)?.trim().to_string())
-        } else {
-            Err(format!("uci get failed: {}.{}.{}", config, section, option).into())
-        }
+    let uci = uci::load("rs485-module")?;
+    let uci_get = |section: &str, option: &str| -> Result<String, String> {
+        uci.get(section, option)
+            .map(|value| value.trim().to_string())
+            .ok_or_else(|| format!("rs485-module.{}.{} is not set", section, option))
     };
 
     // MQTT config
-    let enabled = uci_get("rs485-module", "mqtt", "enabled")
+    let broker = mqtt_link::Broker::load(&uci, "mqtt", "rs485_bridge");
+    let enabled = uci_get("mqtt", "enabled")
         .ok()
         .and_then(|s| s.parse::<u8>().ok())
         .unwrap_or(0)
         == 1;   
-    let host = uci_get("rs485-module", "mqtt", "host").unwrap_or_default();
-    let port = uci_get("rs485-module", "mqtt", "port")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(1883);
-    let username = uci_get("rs485-module", "mqtt", "username").ok();
-    let password = uci_get("rs485-module", "mqtt", "password").ok();
-    let client_id = uci_get("rs485-module", "mqtt", "client_id").unwrap_or_else(|_| "rs485_bridge".to_string());
-    let keepalive = uci_get("rs485-module", "mqtt", "keepalive")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(30);
-    let uplink_topic = uci_get("rs485-module", "mqtt", "uplink_topic").unwrap_or_else(|_| "rs485/uplink".to_string());
-    let downlink_topic = uci_get("rs485-module", "mqtt", "downlink_topic").unwrap_or_else(|_| "rs485/downlink".to_string());
-    let qos_level = uci_get("rs485-module", "mqtt", "qos")
+    let uplink_topic = uci_get("mqtt", "uplink_topic").unwrap_or_else(|_| "rs485/uplink".to_string());
+    let downlink_topic = uci_get("mqtt", "downlink_topic").unwrap_or_else(|_| "rs485/downlink".to_string());
+    let response_topic = uci_get("mqtt", "response_topic").unwrap_or_else(|_| "rs485/response".to_string());
+    let qos_level = uci_get("mqtt", "qos")
         .ok()
         .and_then(|s| s.parse::<u8>().ok())
         .unwrap_or(0);
-    let reconnect_delay = uci_get("rs485-module", "mqtt", "reconnect_delay")
+    let reconnect_delay = uci_get("mqtt", "reconnect_delay")
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(30);
 
-    let transport = uci_get("rs485-module", "mqtt", "transport").unwrap_or_else(|_| "tcp".to_string());
-    let auth_mode = uci_get("rs485-module", "mqtt", "auth_mode").unwrap_or_else(|_| "none".to_string());
-    let ca_cert = uci_get("rs485-module", "mqtt", "ca_cert").ok();
-    let client_cert = uci_get("rs485-module", "mqtt", "client_cert").ok();
-    let client_key = uci_get("rs485-module", "mqtt", "client_key").ok();
-    let token = uci_get("rs485-module", "mqtt", "token").ok();
+    let token = uci_get("mqtt", "token").ok();
+    let encoding = uci_get("mqtt", "encoding")
+        .ok()
+        .and_then(|s| encoding::Encoding::parse(&s))
+        .unwrap_or(encoding::Encoding::Utf8);
 
     let mqtt_config = MqttConfig {
         enabled,
-        transport,
-        host,
-        port,
-        username,
-        password,
-        client_id,
-        keepalive,
+        broker,
         uplink_topic,
         downlink_topic,
+        response_topic,
         qos_level: match qos_level {
             1 => QoS::AtLeastOnce,
             2 => QoS::ExactlyOnce,
             _ => QoS::AtMostOnce, 
         },
         reconnect_delay,
-        auth_mode,
-        ca_cert,
-        client_cert,
-        client_key,
         token,
+        encoding,
     };
 
-    // Serial config
-    let device = format!(
-        "/dev/{}",
-        uci_get("rs485-module", "serial", "device").unwrap_or_else(|_| "RS485-1".to_string())
-    );
-    let baudrate = uci_get("rs485-module", "serial", "baudrate")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(9600);
-    let databit = uci_get("rs485-module", "serial", "databit")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(8);
-    let stopbit = uci_get("rs485-module", "serial", "stopbit").unwrap_or_else(|_| "1".to_string());
-    let checkbit = uci_get("rs485-module", "serial", "checkbit").unwrap_or_else(|_| "none".to_string());
-    let flowcontrol = uci_get("rs485-module", "serial", "flowcontrol").unwrap_or_else(|_| "none".to_string());
-    let timeout = uci_get("rs485-module", "serial", "timeout")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(1000);
-
-    let serial_config = SerialConfig {
-        device,
-        baudrate,
-        databit: match databit {
-            5 => DataBits::Five,
-            6 => DataBits::Six,
-            7 => DataBits::Seven,
-            _ => DataBits::Eight,
-        },
-        stopbit: match stopbit.as_str() {
-            "2" => StopBits::Two,
-            _ => StopBits::One,
+    // Framing config (times in ms, delimiters as hex bytes)
+    let framing_mode = uci_get("framing", "mode").unwrap_or_else(|_| "none".to_string());
+    let uci_num = |option: &str, default: u64| -> u64 { uci.get_parsed("framing", option).unwrap_or(default) };
+    let idle_timeout = uci_num("idle_timeout", 0);
+    let framing_config = framing::FramingConfig {
+        mode: match framing_mode.as_str() {
+            // A zero idle time is resolved per port from its line settings
+            "idle" => framing::FramingMode::Idle(Duration::from_millis(idle_timeout)),
+            "fixed" => framing::FramingMode::Fixed(uci_num("length", 1).max(1) as usize),
+            "delimiter" => framing::FramingMode::Delimiter {
+                start: uci_get("framing", "start")
+                    .ok()
+                    .and_then(|s| framing::parse_hex_bytes(&s))
+                    .unwrap_or_default(),
+                end: uci_get("framing", "end")
+                    .ok()
+                    .and_then(|s| framing::parse_hex_bytes(&s))
+                    .filter(|end| !end.is_empty())
+                    .unwrap_or_else(|| vec![0x0D, 0x0A]),
+            },
+            "length" => framing::FramingMode::Length {
+                offset: uci_num("length_offset", 0) as usize,
+                size: uci_num("length_size", 1).clamp(1, 4) as usize,
+                little_endian: uci_get("framing", "length_endian").unwrap_or_default() == "little",
+                adjust: uci_get("framing", "length_adjust")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+            },
+            _ => framing::FramingMode::None,
         },
-        checkbit: match checkbit.as_str() {
-            "odd" => Parity::Odd,
-            "even" => Parity::Even,
-            _ => Parity::None,
-        },
-        flowcontrol: match flowcontrol.as_str() {
-            "rtscts" => tokio_serial::FlowControl::Hardware,
-            "xonxoff" => tokio_serial::FlowControl::Software,
-            _ => tokio_serial::FlowControl::None,
-        },
-        timeout: Duration::from_millis(timeout),
+        max_length: uci_num("max_length", 1024).max(1) as usize,
+        partial_timeout: Duration::from_millis(uci_num("partial_timeout", 0)),
+    };
+
+    // Serial ports: iterate `@serial[N]`; the first one is the port the init script checks
+    let mut ports = Vec::new();
+    let mut index = 0;
+    while uci.has_section(&format!("@serial[{}]", index)) {
+        let section = format!("@serial[{}]", index);
+        let primary = index == 0;
+        index += 1;
+
+        let enabled = uci_get(&section, "enabled")
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(1)
+            == 1;
+        if !primary && !enabled {
+            continue;
+        }
+
+        let device_name = uci_get(&section, "device").unwrap_or_else(|_| "RS485-1".to_string());
+        let device = format!("/dev/{}", device_name);
+        let baudrate = uci_get(&section, "baudrate")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(9600);
+        let databit = uci_get(&section, "databit")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(8);
+        let stopbit = uci_get(&section, "stopbit").unwrap_or_else(|_| "1".to_string());
+        let checkbit = uci_get(&section, "checkbit").unwrap_or_else(|_| "none".to_string());
+        let flowcontrol = uci_get(&section, "flowcontrol").unwrap_or_else(|_| "none".to_string());
+        let timeout = uci_get(&section, "timeout")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1000);
+
+        // Half-duplex direction control (delays in ms)
+        let delay = |option: &str| -> Duration {
+            Duration::from_millis(
+                uci_get(&section, option)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+            )
+        };
+        let direction_config = direction::DirectionConfig {
+            mode: match uci_get(&section, "direction").unwrap_or_default().as_str() {
+                "rts" => direction::DirectionMode::Rts {
+                    rts_on_send: uci_get(&section, "rts_on_send").map(|s| s != "0").unwrap_or(true),
+                },
+                "gpio" => match uci_get(&section, "gpio_line").ok().and_then(|s| s.parse().ok()) {
+                    Some(line) => direction::DirectionMode::Gpio {
+                        chip: uci_get(&section, "gpio_chip").unwrap_or_else(|_| "/dev/gpiochip0".to_string()),
+                        line,
+                        active_low: uci_get(&section, "gpio_active_low").map(|s| s == "1").unwrap_or(false),
+                    },
+                    None => direction::DirectionMode::Auto,
+                },
+                _ => direction::DirectionMode::Auto,
+            },
+            delay_before: delay("delay_before_send"),
+            delay_after: delay("delay_after_send"),
+        };
+
+        let serial_config = SerialConfig {
+            device,
+            baudrate,
+            databit: match databit {
+                5 => DataBits::Five,
+                6 => DataBits::Six,
+                7 => DataBits::Seven,
+                _ => DataBits::Eight,
+            },
+            stopbit: match stopbit.as_str() {
+                "2" => StopBits::Two,
+                _ => StopBits::One,
+            },
+            checkbit: match checkbit.as_str() {
+                "odd" => Parity::Odd,
+                "even" => Parity::Even,
+                _ => Parity::None,
+            },
+            flowcontrol: match flowcontrol.as_str() {
+                "rtscts" => tokio_serial::FlowControl::Hardware,
+                "xonxoff" => tokio_serial::FlowControl::Software,
+                _ => tokio_serial::FlowControl::None,
+            },
+            timeout: Duration::from_millis(timeout),
+            direction: direction_config,
+        };
+
+        let mut framing = framing_config.clone();
+        if framing.mode == framing::FramingMode::Idle(Duration::ZERO) {
+            framing.mode = framing::FramingMode::Idle(rs485_line::inter_frame_gap(
+                serial_config.baudrate, serial_config.databit, serial_config.checkbit, serial_config.stopbit,
+            ));
+        }
+
+        // Topics: the section's own, else the MQTT ones with `{port}` replaced by the port name;
+        // additional ports fall back to rs485/{port}/... when the MQTT topic has no placeholder
+        let name = uci_get(&section, "name").unwrap_or(device_name);
+        let topic = |option: &str, default: &str| -> String {
+            uci_get(&section, option)
+                .unwrap_or_else(|_| {
+                    if primary || default.contains("{port}") {
+                        default.to_string()
+                    } else {
+                        format!("rs485/{{port}}/{}", option.trim_end_matches("_topic"))
+                    }
+                })
+                .replace("{port}", &name)
+        };
+
+        ports.push(PortConfig {
+            uplink_topic: topic("uplink_topic", &mqtt_config.uplink_topic),
+            downlink_topic: topic("downlink_topic", &mqtt_config.downlink_topic),
+            response_topic: topic("response_topic", &mqtt_config.response_topic),
+            name,
+            serial: serial_config,
+            framing,
+        });
+    }
+    if ports.is_empty() {
+        return Err("no serial port configured".into());
+    }
+
+    // Store-and-forward buffer config (max_size in KiB, max_age in seconds)
+    let buffer_config = BufferConfig {
+        enabled: uci_get("buffer", "enabled")
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(0) == 1,
+        path: uci_get("buffer", "path").unwrap_or_else(|_| "/tmp/rs485/spool".to_string()),
+        max_size: uci_get("buffer", "max_size")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1024),
+        max_age: uci_get("buffer", "max_age")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(86400),
     };
 
     Ok(Config {
         mqtt: mqtt_config,
-        serial: serial_config,
+        ports,
+        buffer: buffer_config,
     })
 }
 
-// Setup MQTT client
-fn setup_mqtt_client(
-    config: &MqttConfig,
-) -> Result<(AsyncClient, rumqttc::EventLoop), Box<dyn std::error::Error + Send + Sync>> {
-    // Create MQTT options
-    let mut mqttoptions = MqttOptions::new(&config.client_id, &config.host, config.port);
+// Configure serial port settings
+async fn setup_serial(
+    config: &SerialConfig,
+) -> Result<tokio_serial::SerialStream, Box<dyn std::error::Error + Send + Sync>> {
 
-    // Set keep alive interval
-    mqttoptions.set_keep_alive(Duration::from_secs(config.keepalive));
+    // Open serial port with specified settings
+    let port = tokio_serial::new(&config.device, config.baudrate)
+        .data_bits(config.databit)
+        .stop_bits(config.stopbit)
+        .parity(config.checkbit)
+        .flow_control(config.flowcontrol)
+        .timeout(config.timeout)
+        .open_native_async()?;
     
-    // Set basic credentials if provided
-    if let (Some(username), Some(password)) = (&config.username, &config.password) {
-        mqttoptions.set_credentials(username, password);
+    Ok(port)
+}
+
+// MQTT payload for one RS485 frame in the configured encoding
+fn uplink_payload(frame: &[u8], config: &MqttConfig, timestamp: Option<String>) -> Result<Vec<u8>, serde_json::Error> {
+    if config.encoding == encoding::Encoding::Raw {
+        return Ok(frame.to_vec());
     }
+    let uplink_msg = UplinkMessage {
+        data: config.encoding.encode(frame),
+        encoding: config.encoding.name(),
+        timestamp,
+    };
+    serde_json::to_vec(&uplink_msg)
+}
 
-    // Configure transport based on protocol
-    match config.transport.as_str() {
-        "ssl" | "tls" => {
-            // TLS/SSL configuration
-            let mut root_cert_store = rumqttc::tokio_rustls::rustls::RootCertStore::empty();
-            
-            // Configure based on auth mode
-            match config.auth_mode.as_str() {
-                "tls-server" => {
-                    // Server verification only - load CA cert
-                    if let Some(ca_cert) = &config.ca_cert {
-                        let ca_cert_bytes = ca_cert.as_bytes();
-                        let mut cursor = std::io::Cursor::new(ca_cert_bytes);
-                        for cert in certs(&mut cursor) {
-                            root_cert_store.add(cert?)?;
-                        }
-                    } else {
-                        // Use system CA certificates
-                        root_cert_store.extend(
-                            webpki_roots::TLS_SERVER_ROOTS.iter().cloned()
-                        );
-                    }
-                    
-                    let tls_config = RustlsClientConfig::builder()
-                        .with_root_certificates(root_cert_store)
-                        .with_no_client_auth();
-                    
-                    mqttoptions.set_transport(Transport::tls_with_config(tls_config.into()));
-                }
-                "mutual-tls" => {
-                    // Mutual TLS - load CA, client cert and key
-                    if let Some(ca_cert) = &config.ca_cert {
-                        let ca_cert_bytes = ca_cert.as_bytes();
-                        let mut cursor = std::io::Cursor::new(ca_cert_bytes);
-                        for cert in certs(&mut cursor) {
-                            root_cert_store.add(cert?)?;
-                        }
-                    } else {
-                        root_cert_store.extend(
-                            webpki_roots::TLS_SERVER_ROOTS.iter().cloned()
-                        );
+// Publish one RS485 frame, or keep it in the buffer while the broker is unreachable
+fn forward_uplink(
+    frame: &[u8],
+    port: &PortConfig,
+    client: Option<&mqtt_link::Connection>,
+    spool: Option<&mut spool::Spool>,
+    config: &MqttConfig,
+    logger: &Arc<Logger>,
+) {
+    logger.log(&format!("[{}] RS485 received: {}", port.name, match config.encoding {
+        encoding::Encoding::Utf8 => config.encoding.encode(frame),
+        _ => format!("{:02X?}", frame),
+    }));
+
+    if let Some(client) = client {
+        match uplink_payload(frame, config, None) {
+            Ok(payload) => {
+                match client.publish(&port.uplink_topic, config.qos_level, false, payload.clone()) {
+                    Ok(_) => {
+                        logger.log(&format!("Published to MQTT: {}", String::from_utf8_lossy(&payload)));
+                        return;
                     }
-                    
-                    // Load client certificate and private key
-                    if let (Some(client_cert_pem), Some(client_key_pem)) = (&config.client_cert, &config.client_key) {
-                        let cert_bytes = client_cert_pem.as_bytes();
-                        let key_bytes = client_key_pem.as_bytes();
-                        
-                        let mut cert_cursor = std::io::Cursor::new(cert_bytes);
-                        let certs: Vec<_> = certs(&mut cert_cursor).collect::<Result<_, _>>()?;
-                        
-                        let mut key_cursor = std::io::Cursor::new(key_bytes);
-                        let mut keys = pkcs8_private_keys(&mut key_cursor).collect::<Result<Vec<_>, _>>()?;
-                        
-                        if keys.is_empty() {
-                            return Err("No private key found".into());
-                        }
-                        
-                        let tls_config = RustlsClientConfig::builder()
-                            .with_root_certificates(root_cert_store)
-                            .with_client_auth_cert(certs, keys.remove(0).into())?;
-                        
-                        mqttoptions.set_transport(Transport::tls_with_config(tls_config.into()));
-                    } else {
-                        return Err("Mutual TLS requires both client certificate and private key".into());
+                    Err(e) => {
+                        logger.log(&format!("MQTT publish failed: {}", e));
                     }
                 }
-                _ => {
-                    // Default TLS with system certs
-                    root_cert_store.extend(
-                        webpki_roots::TLS_SERVER_ROOTS.iter().cloned()
-                    );
-                    
-                    let tls_config = RustlsClientConfig::builder()
-                        .with_root_certificates(root_cert_store)
-                        .with_no_client_auth();
-                    
-                    mqttoptions.set_transport(Transport::tls_with_config(tls_config.into()));
-                }
             }
-        }
-        "ws" => {
-            // WebSocket - construct URL format
-            let ws_url = format!("ws://{}:{}/mqtt", config.host, config.port);
-            mqttoptions = MqttOptions::new(&config.client_id, &ws_url, config.port);
-            mqttoptions.set_keep_alive(Duration::from_secs(config.keepalive));
-            if let Some(username) = &config.username {
-                mqttoptions.set_credentials(username, config.password.as_deref().unwrap_or(""));
+            Err(e) => {
+                logger.log(&format!("JSON serialization failed: {}", e));
+                return;
             }
-            mqttoptions.set_transport(Transport::Ws);
         }
-        "wss" => {
-            // WebSocket Secure - construct URL format
-            let wss_url = format!("wss://{}:{}/mqtt", config.host, config.port);
-            mqttoptions = MqttOptions::new(&config.client_id, &wss_url, config.port);
-            mqttoptions.set_keep_alive(Duration::from_secs(config.keepalive));
-            if let Some(username) = &config.username {
-                mqttoptions.set_credentials(username, config.password.as_deref().unwrap_or(""));
-            }
-            
-            let mut root_cert_store = rumqttc::tokio_rustls::rustls::RootCertStore::empty();
-            
-            if let Some(ca_cert) = &config.ca_cert {
-                let ca_cert_bytes = ca_cert.as_bytes();
-                let mut cursor = std::io::Cursor::new(ca_cert_bytes);
-                for cert in certs(&mut cursor) {
-                    root_cert_store.add(cert?)?;
-                }
-            } else {
-                root_cert_store.extend(
-                    webpki_roots::TLS_SERVER_ROOTS.iter().cloned()
-                );
+    }
+
+    if let Some(spool) = spool {
+        if let Ok(payload) = uplink_payload(frame, config, Some(Local::now().to_rfc3339())) {
+            match spool.push(&port.uplink_topic, &payload) {
+                Ok(_) => logger.log(&format!("Buffered uplink ({} pending)", spool.pending())),
+                Err(e) => logger.log(&format!("Failed to buffer uplink: {}", e)),
             }
-            
-            // Check auth mode for WSS
-            let tls_config = match config.auth_mode.as_str() {
-                "mutual-tls" => {
-                    // Mutual TLS for WebSocket Secure
-                    if let (Some(client_cert_pem), Some(client_key_pem)) = (&config.client_cert, &config.client_key) {
-                        let cert_bytes = client_cert_pem.as_bytes();
-                        let key_bytes = client_key_pem.as_bytes();
-                        
-                        let mut cert_cursor = std::io::Cursor::new(cert_bytes);
-                        let certs: Vec<_> = certs(&mut cert_cursor).collect::<Result<_, _>>()?;
-                        
-                        let mut key_cursor = std::io::Cursor::new(key_bytes);
-                        let mut keys = pkcs8_private_keys(&mut key_cursor).collect::<Result<Vec<_>, _>>()?;
-                        
-                        if keys.is_empty() {
-                            return Err("No private key found".into());
-                        }
-                        
-                        RustlsClientConfig::builder()
-                            .with_root_certificates(root_cert_store)
-                            .with_client_auth_cert(certs, keys.remove(0).into())?
-                    } else {
-                        return Err("Mutual TLS requires both client certificate and private key".into());
-                    }
-                }
-                _ => {
-                    // Default: server verification only
-                    RustlsClientConfig::builder()
-                        .with_root_certificates(root_cert_store)
-                        .with_no_client_auth()
-                }
-            };
-            
-            mqttoptions.set_transport(Transport::wss_with_config(tls_config.into()));
         }
-        "tcp" | _ => {
-            // Plain TCP (default)
-            mqttoptions.set_transport(Transport::Tcp);
+    }
+}
+
+// Decode a downlink: a raw payload as-is, otherwise the JSON `data` field; an error keeps the
+// request id, if one could be read, so the sender still gets a reply
+fn decode_downlink(
+    payload: &[u8],
+    config: &MqttConfig,
+    default_timeout: Duration,
+) -> Result<port::Command, (Option<serde_json::Value>, String)> {
+    if config.encoding == encoding::Encoding::Raw {
+        return Ok(port::Command::Write(payload.to_vec()));
+    }
+    let msg = serde_json::from_slice::<DownlinkMessage>(payload).map_err(|_| {
+        let id = serde_json::from_slice::<serde_json::Value>(payload)
+            .ok()
+            .and_then(|value| value.get("id").cloned())
+            .filter(|id| !id.is_null());
+        (id, format!("Invalid message format, expected {{\"data\":\"...\"}}, got: {}", String::from_utf8_lossy(payload)))
+    })?;
+    let encoding = match msg.encoding.as_deref() {
+        // Raw carries the serial bytes as the whole MQTT payload, it cannot be set inside JSON
+        Some(name) => match encoding::Encoding::parse(name) {
+            Some(encoding::Encoding::Raw) => return Err((msg.id, "Encoding raw is only valid as the configured encoding".to_string())),
+            Some(encoding) => encoding,
+            None => return Err((msg.id, format!("Unknown encoding: {}", name))),
+        },
+        None => config.encoding,
+    };
+    let data = encoding.decode(&msg.data).map_err(|e| (msg.id.clone(), e))?;
+    Ok(match msg.id {
+        Some(id) => port::Command::Request(transaction::Request {
+            id,
+            data,
+            encoding,
+            timeout: msg.timeout_ms.map(Duration::from_millis).unwrap_or(default_timeout),
+        }),
+        None => port::Command::Write(data),
+    })
+}
+
+// Open a serial port and start its task; a port that fails to open is left out
+async fn open_port(
+    index: usize,
+    generation: u64,
+    port_config: &PortConfig,
+    events: &mpsc::Sender<port::Event>,
+    logger: &Arc<Logger>,
+) -> Option<port::Port> {
+    let serial = &port_config.serial;
+    logger.log(&format!(
+        "Opening serial port: {} @ {} baud, {:?} data bits, {:?} stop bits, {:?} parity, {:?} flow control, {:?} timeout",
+        serial.device, serial.baudrate, serial.databit, serial.stopbit, serial.checkbit, serial.flowcontrol, serial.timeout
+    ));
+    let opened = setup_serial(serial).await.and_then(|stream| {
+        let direction = direction::Direction::setup(&stream, &serial.direction)
+            .map_err(|e| format!("direction control ({:?}): {}", serial.direction.mode, e))?;
+        Ok((stream, direction))
+    });
+    match opened {
+        Ok((stream, direction)) => {
+            logger.log(&format!("Success opening serial port {} as {}", serial.device, port_config.name));
+            Some(port::Port::spawn(
+                index,
+                generation,
+                port_config,
+                stream,
+                direction,
+                events.clone(),
+                logger.clone(),
+            ))
+        }
+        Err(e) => {
+            logger.log(&format!("Failed opening serial port {}: {}", serial.device, e));
+            None
         }
     }
-    
-    // Create AsyncClient and EventLoop
-    let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
-    Ok((client, eventloop))
 }
 
-// Configure serial port settings
-async fn setup_serial(
-    config: &SerialConfig,
-) -> Result<tokio_serial::SerialStream, Box<dyn std::error::Error + Send + Sync>> {
+fn open_spool(config: &BufferConfig, logger: &Arc<Logger>) -> Option<spool::Spool> {
+    if !config.enabled {
+        return None;
+    }
+    match spool::Spool::new(&config.path, "rs485-module", config.max_size * 1024, config.max_age) {
+        Ok(spool) => {
+            logger.log(&format!("Uplink buffer in {} ({} pending)", config.path, spool.pending()));
+            Some(spool)
+        }
+        Err(e) => {
+            logger.log(&format!("Uplink buffer {} unavailable: {}", config.path, e));
+            None
+        }
+    }
+}
 
-    // Open serial port with specified settings
-    let port = tokio_serial::new(&config.device, config.baudrate)
-        .data_bits(config.databit)
-        .stop_bits(config.stopbit)
-        .parity(config.checkbit)
-        .flow_control(config.flowcontrol)
-        .timeout(config.timeout)
-        .open_native_async()
-        .map_err(|e| {
-            e
-        })?;
-    
-    Ok(port)
+// Publish a request reply on the port's response topic
+fn publish_response(
+    port: &PortConfig,
+    response: ResponseMessage,
+    client: Option<&mqtt_link::Connection>,
+    config: &MqttConfig,
+    logger: &Arc<Logger>,
+) {
+    let Some(client) = client else {
+        logger.log(&format!("[{}] Dropping response {}: MQTT not connected", port.name, response.id));
+        return;
+    };
+    match serde_json::to_string(&response) {
+        Ok(json) => match client.publish(&port.response_topic, config.qos_level, false, json.clone().into_bytes()) {
+            Ok(_) => logger.log(&format!("Published response to MQTT: {}", json)),
+            Err(e) => logger.log(&format!("MQTT publish failed: {}", e)),
+        },
+        Err(e) => logger.log(&format!("JSON serialization failed: {}", e)),
+    }
+}
+
+// Publish what a port task produced; uplinks go to the buffer until the broker acknowledged the connection
+fn handle_event(
+    event: port::Event,
+    ports: &[Option<port::Port>],
+    config: &Config,
+    client: Option<&mqtt_link::Connection>,
+    mqtt_connected: bool,
+    spool: Option<&mut spool::Spool>,
+    logger: &Arc<Logger>,
+) {
+    // Events still queued from a port that a reload closed or reopened are dropped
+    let (index, generation) = match &event {
+        port::Event::Uplink { port, generation, .. } | port::Event::Response { port, generation, .. } => (*port, *generation),
+    };
+    let current = ports.get(index).and_then(Option::as_ref).is_some_and(|port| port.generation() == generation);
+    let Some(port) = config.ports.get(index).filter(|_| current) else {
+        logger.log(&format!("Dropping event from closed serial port {}", index));
+        return;
+    };
+    match event {
+        port::Event::Uplink { frame, .. } => {
+            let client = if mqtt_connected || spool.is_none() { client } else { None };
+            forward_uplink(&frame, port, client, spool, &config.mqtt, logger);
+        }
+        port::Event::Response { id, status, encoding, data, .. } => {
+            let response = ResponseMessage {
+                id,
+                status,
+                data: encoding.encode(&data),
+                encoding: encoding.name(),
+            };
+            publish_response(port, response, client, &config.mqtt, logger);
+        }
+    }
 }
 
 #[tokio::main]
@@ -442,6 +617,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     logger.init()?;
     logger.log("RS485-MQTT Bridge starting...");
 
+    // SIGHUP (sent by `/etc/init.d/rs485-module reload`) reloads the configuration; installed
+    // before anything else so an early reload does not terminate the process
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut reload = false;
+
     // Load initial configuration from UCI
     let mut config = match load_config_from_uci() {
         Ok(cfg) => cfg,
@@ -451,196 +631,230 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         }
     };
 
-    // Initialize serial port
-    let mut serial_port = match setup_serial(&config.serial).await {
-        Ok(port) => {
-            logger.log(&format!(
-                "Opening serial port: {} @ {} baud, {:?} data bits, {:?} stop bits, {:?} parity, {:?} flow control, {:?} timeout",
-                config.serial.device, config.serial.baudrate, config.serial.databit, config.serial.stopbit, config.serial.checkbit, config.serial.flowcontrol, config.serial.timeout
-            ));
-            logger.log("Success opening serial port");
-            port
-        }
-        Err(e) => {
-            logger.log(&format!("Failed opening serial port: {}", e));
-            return Err(e);
-        }
-    };
+    // Initialize serial ports, each owned by its own task
+    let (event_tx, mut events) = mpsc::channel(64);
+    let mut ports = Vec::new();
+    let mut generation = 0;                                         // Bumped for every port opened
+    for (index, port_config) in config.ports.iter().enumerate() {
+        generation += 1;
+        ports.push(open_port(index, generation, port_config, &event_tx, &logger).await);
+    }
+    if ports.iter().all(Option::is_none) {
+        return Err("No serial port could be opened".into());
+    }
+
+    // Initialize store-and-forward buffer
+    let mut spool = open_spool(&config.buffer, &logger);
 
-    let mut mqtt_client: Option<AsyncClient> = None;                // MQTT client
-    let mut mqtt_eventloop: Option<rumqttc::EventLoop> = None;      // MQTT event loop
-    let mut mqtt_state = "not_connect";                             // MQTT connection state   
+    let mut mqtt: Option<(mqtt_link::Connection, mpsc::Receiver<mqtt_link::Event>)> = None; // MQTT connection task
+    let mut mqtt_connected = false;                                 // ConnAck received on the current connection
+    let mut retry_at: Option<tokio::time::Instant> = None;          // Next attempt after invalid MQTT settings
 
     loop {
-            // Load configuration
-            config = match load_config_from_uci() {
-                Ok(cfg) => cfg,
+        // Apply a reloaded configuration to the parts it changed
+        if reload {
+            reload = false;
+            match load_config_from_uci() {
+                Ok(new_config) => {
+                    logger.log("Configuration reloaded");
+
+                    // Reopen ports whose settings changed, close removed ones, open added ones
+                    let count = config.ports.len().max(new_config.ports.len());
+                    ports.resize_with(count, || None);
+                    for (index, slot) in ports.iter_mut().enumerate() {
+                        if config.ports.get(index) == new_config.ports.get(index) {
+                            continue;
+                        }
+                        if let Some(port) = slot.take() {
+                            port.close().await;
+                        }
+                        if let Some(port_config) = new_config.ports.get(index) {
+                            generation += 1;
+                            *slot = open_port(index, generation, port_config, &event_tx, &logger).await;
+                        }
+                    }
+                    ports.truncate(new_config.ports.len());
+
+                    // Topics are part of the connection (subscriptions), so any change reconnects
+                    let topics = |config: &Config| -> Vec<(String, String, String)> {
+                        config.ports.iter().map(|p| (p.uplink_topic.clone(), p.downlink_topic.clone(), p.response_topic.clone())).collect()
+                    };
+                    if config.mqtt != new_config.mqtt || topics(&config) != topics(&new_config) {
+                        if mqtt.is_some() {
+                            logger.log("MQTT settings changed, reconnecting");
+                        }
+                        mqtt = None;
+                        mqtt_connected = false;
+                        retry_at = None;
+                    }
+
+                    if config.buffer != new_config.buffer {
+                        spool = open_spool(&new_config.buffer, &logger);
+                    }
+
+                    config = new_config;
+                }
                 Err(e) => {
-                    logger.log(&format!("Failed to load config: {}", e));
-                    return Err(e);
+                    logger.log(&format!("Failed to reload config, keeping the current one: {}", e));
                 }
-            };
+            }
+        }
+        let was_online = mqtt_connected;
+
+        // Connection management; reconnects after a lost connection are made by the connection task
+        if !config.mqtt.enabled {
+            if mqtt.take().is_some() {
+                logger.log("MQTT disabled");
+            }
+            mqtt_connected = false;
+            retry_at = None;
+        } else if mqtt.is_none() && retry_at.is_none_or(|at| at <= tokio::time::Instant::now()) {
+            logger.log("MQTT enabled, start connection...");
+            match config.mqtt.broker.options() {
+                Ok(options) => {
+                    logger.log(&format!("Connecting to {}:{}", config.mqtt.broker.host, config.mqtt.broker.port));
+                    mqtt = Some(mqtt_link::Connection::spawn(options, Duration::from_secs(config.mqtt.reconnect_delay)));
+                    retry_at = None;
+                }
+                Err(e) => {
+                    logger.log(&format!("Failed connecting: {}", e));
+                    retry_at = Some(tokio::time::Instant::now() + Duration::from_secs(config.mqtt.reconnect_delay));
+                }
+            }
+        }
 
-            if config.mqtt.enabled {
-                if mqtt_state == "not_connect" {
-                    logger.log("MQTT enabled, start connection...");
-                    match setup_mqtt_client(&config.mqtt) {
-                        Ok((client, el)) => {
-                            mqtt_client = Some(client);
-                            mqtt_eventloop = Some(el);
+        // Replay buffered uplinks in order, a few at a time so live traffic keeps flowing
+        if let (true, Some(spool), Some((connection, _))) = (mqtt_connected, spool.as_mut(), &mqtt) {
+            if spool.pending() > 0 {
+                match spool.replay(connection, config.mqtt.qos_level) {
+                    Ok(sent) if sent > 0 => logger.log(&format!("Replayed {} buffered uplinks ({} pending)", sent, spool.pending())),
+                    Ok(_) => {}
+                    Err(e) => logger.log(&format!("Uplink buffer replay failed: {}", e)),
+                }
+            }
+        }
 
-                            logger.log(&format!("Success connecting to {}:{}", config.mqtt.host, config.mqtt.port));
-                            mqtt_state = "success_connect";
+        tokio::select! {
+            // Handle MQTT events
+            Some(event) = async {
+                match mqtt.as_mut() {
+                    Some((_, events)) => events.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                match event {
+                    mqtt_link::Event::Connected => {
+                        mqtt_connected = true;
+                        // Subscribe to the downlink topic of every port
+                        if let Some((connection, _)) = &mqtt {
+                            for port in &config.ports {
+                                match connection.subscribe(&port.downlink_topic, config.mqtt.qos_level) {
+                                    Ok(_) => {
+                                        logger.log(&format!("Subscribed [MQTT->RS485] to topic: {}", port.downlink_topic));
+                                    }
+                                    Err(e) => {
+                                        logger.log(&format!("Failed to topic: {}", e));
+                                    }
+                                }
+                            }
                         }
-                        Err(e) => {
-                            logger.log(&format!("Failed connecting: {}", e));
-                            mqtt_state = "failed_connect";
+                        for port in &config.ports {
+                            logger.log(&format!("Published [RS485->MQTT] to topic: {}", port.uplink_topic));
                         }
                     }
-                }
-                else if mqtt_state == "success_connect" {
-                    tokio::select! {
-                        // Handle MQTT events
-                        mqtt_result = async {
-                            if let Some(ref mut el) = mqtt_eventloop {
-                                el.poll().await
-                            } else {
-                                std::future::pending().await
-                            }
-                        } => {
-                            match mqtt_result {
-                                Ok(Event::Incoming(incoming)) => {
-                                    match incoming {
-                                        // Handle connection acknowledgment
-                                        Incoming::ConnAck(_) => {
-                                            // Subscribe to downlink topic
-                                            if let Some(ref client) = mqtt_client {
-                                                match client.subscribe(&config.mqtt.downlink_topic, config.mqtt.qos_level).await {
-                                                    Ok(_) => {
-                                                        logger.log(&format!("Subscribed [MQTT->RS485] to topic: {}", config.mqtt.downlink_topic));
-                                                    }
-                                                    Err(e) => {
-                                                        logger.log(&format!("Failed to topic: {}", e));
-                                                    }
-                                                }
-                                            }
-                                            logger.log(&format!("Published [RS485->MQTT] to topic: {}", config.mqtt.uplink_topic));
-                                        }
-                                        // Handle incoming publish messages
-                                        Incoming::Publish(p) => {
-                                            let payload = String::from_utf8_lossy(&p.payload);
-                                            logger.log(&format!("MQTT received: {}", payload));
-                                            
-                                            if let Ok(msg) = serde_json::from_str::<DownlinkMessage>(&payload) {
-                                                let data = msg.data.as_bytes();
-                                                match AsyncWriteExt::write_all(&mut serial_port, data).await {
-                                                    Ok(_) => {
-                                                        logger.log(&format!("Forwarded to RS485: {}", msg.data));
-                                                    }
-                                                    Err(e) => {
-                                                        logger.log(&format!("RS485 write failed: {}", e));
-                                                    }
-                                                }
-                                            } else {
-                                                logger.log(&format!("Invalid message format, expected {{\"data\":\"...\"}}, got: {}", payload));
-                                            }
+                    // Handle incoming publish messages
+                    mqtt_link::Event::Message(p) => {
+                        let payload = String::from_utf8_lossy(&p.payload);
+                        logger.log(&format!("MQTT received: {}", payload));
+
+                        match config.ports.iter().position(|port| port.downlink_topic == p.topic) {
+                            Some(index) => {
+                                match decode_downlink(&p.payload, &config.mqtt, config.ports[index].serial.timeout) {
+                                    Ok(command) => match (&ports[index], command) {
+                                        (Some(port), command) => port.send(command).await,
+                                        // The port failed to open; a request still gets its reply
+                                        (None, port::Command::Request(request)) => {
+                                            logger.log(&format!("[{}] Serial port not open, rejecting request {}", config.ports[index].name, request.id));
+                                            let response = ResponseMessage {
+                                                id: request.id,
+                                                status: "error",
+                                                data: String::new(),
+                                                encoding: request.encoding.name(),
+                                            };
+                                            let connection = mqtt.as_ref().map(|(connection, _)| connection);
+                                            publish_response(&config.ports[index], response, connection, &config.mqtt, &logger);
                                         }
-                                        // Handle subscription acknowledgment
-                                        Incoming::SubAck(_) => {
-                                            // logger.log("Subscription acknowledged");
+                                        (None, _) => {
+                                            logger.log(&format!("[{}] Serial port not open, dropping downlink", config.ports[index].name));
                                         }
-                                        // Handle disconnection
-                                        Incoming::Disconnect => {
-                                            logger.log("MQTT disconnected");
-                                            mqtt_state = "failed_connect";
-                                        }
-                                        // Handle other incoming events
-                                        _ => {
-                                            // logger.log(&format!("MQTT event: {:?}", other));
+                                    },
+                                    Err((id, e)) => {
+                                        logger.log(&format!("[{}] {}", config.ports[index].name, e));
+                                        // A request that carries an id still gets an answer
+                                        if let Some(id) = id {
+                                            let response = ResponseMessage {
+                                                id,
+                                                status: "error",
+                                                data: String::new(),
+                                                encoding: config.mqtt.encoding.name(),
+                                            };
+                                            let connection = mqtt.as_ref().map(|(connection, _)| connection);
+                                            publish_response(&config.ports[index], response, connection, &config.mqtt, &logger);
                                         }
                                     }
                                 }
-                                Ok(Event::Outgoing(_)) => {
-                                    // Outgoing events are normal
-                                }
-                                Err(e) => {
-                                    logger.log(&format!("MQTT error: {}", e));
-                                    mqtt_state = "failed_connect";
-                                }
                             }
-                        }
-
-                        serial_result = async { 
-                            let mut serial_buffer = vec![0u8; 1024];
-                            serial_port.read(&mut serial_buffer).await.map(|n| (n, serial_buffer))
-                        } => {
-                            match serial_result {
-                                Ok((n, buffer)) if n > 0 => {
-                                    let data_str = String::from_utf8_lossy(&buffer[..n]).trim().to_string();
-                                    logger.log(&format!("RS485 received: {}", data_str));
-                                    
-                                    if let Some(ref client) = mqtt_client {
-                                        let uplink_msg = UplinkMessage { data: data_str.clone() };
-                                        match serde_json::to_string(&uplink_msg) {
-                                            Ok(json) => {
-                                                match client.publish(&config.mqtt.uplink_topic, config.mqtt.qos_level, false, json.as_bytes()).await {
-                                                    Ok(_) => {
-                                                        logger.log(&format!("Published to MQTT: {}", json));
-                                                    }
-                                                    Err(e) => {
-                                                        logger.log(&format!("MQTT publish failed: {}", e));
-                                                    }
-                                                }
-                                            }
-                                            Err(e) => {
-                                                logger.log(&format!("JSON serialization failed: {}", e));
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(e) if e.kind() != std::io::ErrorKind::WouldBlock => {
-                                    logger.log(&format!("Serial read error: {}", e));
-                                }
-                                _ => {}
+                            None => {
+                                logger.log(&format!("No serial port for topic: {}", p.topic));
                             }
                         }
-                    }     
-                }
-                else if mqtt_state == "failed_connect" {
-                    tokio::select! {
-                        // Reconnect timer
-                        _ = tokio::time::sleep(Duration::from_secs(config.mqtt.reconnect_delay)) => {
-                            logger.log("Retrying connection...");
-                            match setup_mqtt_client(&config.mqtt) {
-                                Ok((client, el)) => {
-                                    mqtt_client = Some(client);
-                                    mqtt_eventloop = Some(el);
-
-                                    logger.log(&format!("Success connecting to {}:{}", config.mqtt.host, config.mqtt.port));
-                                    mqtt_state = "success_connect";
-                                }
-                                Err(e) => {
-                                    mqtt_client = None;
-                                    mqtt_eventloop = None;
-
-                                    logger.log(&format!("Reconnect failed: {}", e));
-                                    mqtt_state = "failed_connect";
-                                }
+                    }
+                    // A replayed uplink reached the broker and leaves the buffer
+                    mqtt_link::Event::Delivered(id) => {
+                        if let Some(spool) = spool.as_mut() {
+                            if let Err(e) = spool.ack(id) {
+                                logger.log(&format!("Uplink buffer update failed: {}", e));
                             }
                         }
                     }
+                    // The connection task retries after reconnect_delay
+                    mqtt_link::Event::Disconnected(e) => {
+                        logger.log(&format!("MQTT error: {}", e));
+                        mqtt_connected = false;
+                        // Unacknowledged replays are sent again on the next connection
+                        if let Some(spool) = spool.as_mut() {
+                            spool.rewind();
+                        }
+                    }
                 }
             }
-            else {
-                if mqtt_state != "not_connect" {
-                    mqtt_client = None;
-                    mqtt_eventloop = None;
 
-                    logger.log("MQTT disabled");
-                    mqtt_state = "not_connect";
+            // Frames and request replies from the serial ports; without MQTT they are dropped
+            Some(event) = events.recv() => {
+                if config.mqtt.enabled {
+                    let connection = mqtt.as_ref().map(|(connection, _)| connection);
+                    handle_event(event, &ports, &config, connection, mqtt_connected, spool.as_mut(), &logger);
                 }
             }
 
-        sleep(Duration::from_millis(1)).await;
+            _ = hangup.recv() => {
+                reload = true;
+            }
+
+            // Retry invalid MQTT settings
+            _ = async {
+                match retry_at {
+                    Some(at) => tokio::time::sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            } => {}
+        }
+
+        // Replies could not be delivered, so pending requests are dropped
+        if was_online && !mqtt_connected {
+            for port in ports.iter().flatten() {
+                port.send(port::Command::Clear).await;
+            }
+        }
     }
 }
@@ -0,0 +1,216 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_serial::SerialStream;
+use rs485_line::direction::Direction;
+
+use crate::encoding::Encoding;
+use crate::framing::Framer;
+use crate::transaction::{Request, Transactions};
+use crate::{Logger, PortConfig};
+
+// How long `Port::close` waits for the task to finish its current work
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Work for a port task, from MQTT downlinks
+pub enum Command {
+    // Written as-is
+    Write(Vec<u8>),
+    // Written in turn; the reply comes back as a Response event
+    Request(Request),
+    // Drop queued and active requests whose replies can no longer be delivered
+    Clear,
+}
+
+// Data from a port task, tagged with the port index and the generation of the task that
+// produced it, so events queued before a reload closed or replaced the port can be dropped
+pub enum Event {
+    Uplink {
+        port: usize,
+        generation: u64,
+        frame: Vec<u8>,
+    },
+    Response {
+        port: usize,
+        generation: u64,
+        id: serde_json::Value,
+        // ok, timeout, busy or error
+        status: &'static str,
+        encoding: Encoding,
+        data: Vec<u8>,
+    },
+}
+
+// Handle to the task that owns one serial port
+pub struct Port {
+    generation: u64,
+    commands: mpsc::Sender<Command>,
+    task: JoinHandle<()>,
+}
+
+impl Port {
+    pub fn spawn(
+        index: usize,
+        generation: u64,
+        config: &PortConfig,
+        stream: SerialStream,
+        direction: Direction,
+        events: mpsc::Sender<Event>,
+        logger: Arc<Logger>,
+    ) -> Port {
+        let (commands, rx) = mpsc::channel(32);
+        let task = PortTask {
+            index,
+            generation,
+            name: config.name.clone(),
+            direction,
+            framer: Framer::new(config.framing.clone()),
+            transactions: Transactions::default(),
+            events,
+            logger,
+        };
+        let task = tokio::spawn(task.run(stream, rx));
+        Port { generation, commands, task }
+    }
+
+    // Distinguishes this task from earlier ones opened on the same index
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub async fn send(&self, command: Command) {
+        let _ = self.commands.send(command).await;
+    }
+
+    // Stop the task and wait until the serial port is closed, so it can be reopened; a write
+    // in progress is finished first, closing the command channel ends the task after it
+    pub async fn close(self) {
+        let Port { commands, mut task, .. } = self;
+        drop(commands);
+        // A task stuck on a full event queue is aborted; the queue is only read by the caller
+        if tokio::time::timeout(CLOSE_TIMEOUT, &mut task).await.is_err() {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+}
+
+struct PortTask {
+    index: usize,
+    generation: u64,
+    name: String,
+    direction: Direction,
+    framer: Framer,
+    transactions: Transactions,
+    events: mpsc::Sender<Event>,
+    logger: Arc<Logger>,
+}
+
+impl PortTask {
+    async fn run(mut self, mut stream: SerialStream, mut commands: mpsc::Receiver<Command>) {
+        loop {
+            // Write the next queued request once the previous one is answered
+            if let Some(request) = self.transactions.next() {
+                // Bytes received before the request are not part of its reply
+                self.framer.take();
+                match self.direction.write(&mut stream, &request.data).await {
+                    Ok(_) => {
+                        self.log(&format!("Request {} forwarded to RS485: {:02X?}", request.id, request.data));
+                        self.transactions.start(request.id, request.encoding, request.timeout);
+                    }
+                    Err(e) => {
+                        self.log(&format!("RS485 write failed: {}", e));
+                        self.respond(request.id, "error", request.encoding, Vec::new()).await;
+                    }
+                }
+            }
+
+            let frame_deadline = self.framer.deadline();
+            let request_deadline = self.transactions.deadline();
+            tokio::select! {
+                command = commands.recv() => {
+                    match command {
+                        Some(Command::Write(data)) => {
+                            match self.direction.write(&mut stream, &data).await {
+                                Ok(_) => self.log(&format!("Forwarded to RS485: {:02X?}", data)),
+                                Err(e) => self.log(&format!("RS485 write failed: {}", e)),
+                            }
+                        }
+                        Some(Command::Request(request)) => {
+                            self.log(&format!("Queued request {}", request.id));
+                            if let Err(request) = self.transactions.push(request) {
+                                self.log(&format!("Request queue full, rejecting {}", request.id));
+                                self.respond(request.id, "busy", request.encoding, Vec::new()).await;
+                            }
+                        }
+                        Some(Command::Clear) => {
+                            let dropped = self.transactions.clear();
+                            if dropped > 0 {
+                                self.log(&format!("Dropped {} pending requests", dropped));
+                            }
+                        }
+                        // Closed by `Port::close` or the bridge is shutting down
+                        None => return,
+                    }
+                }
+
+                serial_result = async {
+                    let mut serial_buffer = vec![0u8; 1024];
+                    stream.read(&mut serial_buffer).await.map(|n| (n, serial_buffer))
+                } => {
+                    match serial_result {
+                        Ok((n, buffer)) if n > 0 => {
+                            if self.transactions.is_active() && self.framer.per_read() {
+                                // Without a framing rule the reply is everything read until the timeout
+                                self.transactions.collect(&buffer[..n]);
+                            } else {
+                                for frame in self.framer.push(&buffer[..n], &self.logger) {
+                                    self.complete(frame).await;
+                                }
+                            }
+                        }
+                        Err(e) if e.kind() != std::io::ErrorKind::WouldBlock => {
+                            self.log(&format!("Serial read error: {}", e));
+                        }
+                        _ => {}
+                    }
+                }
+
+                // Idle gap ended the frame, or a partial frame went stale
+                _ = tokio::time::sleep_until(frame_deadline.unwrap_or_else(tokio::time::Instant::now)), if frame_deadline.is_some() => {
+                    if let Some(frame) = self.framer.expire(&self.logger) {
+                        self.complete(frame).await;
+                    }
+                }
+
+                // Request timeout; replies collected per read are complete now
+                _ = tokio::time::sleep_until(request_deadline.unwrap_or_else(tokio::time::Instant::now)), if request_deadline.is_some() => {
+                    let per_read = self.framer.per_read();
+                    if let Some((active, status)) = self.transactions.expire(self.framer.take(), per_read) {
+                        self.respond(active.id, status, active.encoding, active.response).await;
+                    }
+                }
+            }
+        }
+    }
+
+    // The first complete frame answers the active request, any other is an uplink
+    async fn complete(&mut self, frame: Vec<u8>) {
+        match self.transactions.finish() {
+            Some(active) => self.respond(active.id, "ok", active.encoding, frame).await,
+            None => {
+                let _ = self.events.send(Event::Uplink { port: self.index, generation: self.generation, frame }).await;
+            }
+        }
+    }
+
+    async fn respond(&self, id: serde_json::Value, status: &'static str, encoding: Encoding, data: Vec<u8>) {
+        let _ = self.events.send(Event::Response { port: self.index, generation: self.generation, id, status, encoding, data }).await;
+    }
+
+    fn log(&self, message: &str) {
+        self.logger.log(&format!("[{}] {}", self.name, message));
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::encoding::Encoding;
+
+// Requests waiting beyond this are answered with status "busy"
+const MAX_QUEUED: usize = 32;
+
+// Downlink that expects the serial reply published under the same id
+#[derive(Debug)]
+pub struct Request {
+    pub id: serde_json::Value,
+    pub data: Vec<u8>,
+    // Encoding of the request, also used for the reply
+    pub encoding: Encoding,
+    pub timeout: Duration,
+}
+
+// Request written to the port and waiting for its reply
+#[derive(Debug)]
+pub struct Active {
+    pub id: serde_json::Value,
+    pub encoding: Encoding,
+    pub deadline: Instant,
+    pub response: Vec<u8>,
+}
+
+// Serializes request/response exchanges on the port, one at a time in arrival order
+#[derive(Default)]
+pub struct Transactions {
+    queue: VecDeque<Request>,
+    active: Option<Active>,
+}
+
+impl Transactions {
+    // Queue a request; it is handed back when the queue is full
+    pub fn push(&mut self, request: Request) -> Result<(), Request> {
+        if self.queue.len() >= MAX_QUEUED {
+            return Err(request);
+        }
+        self.queue.push_back(request);
+        Ok(())
+    }
+
+    // Next request to write, once the port is free
+    pub fn next(&mut self) -> Option<Request> {
+        if self.active.is_some() {
+            return None;
+        }
+        self.queue.pop_front()
+    }
+
+    pub fn start(&mut self, id: serde_json::Value, encoding: Encoding, timeout: Duration) {
+        self.active = Some(Active {
+            id,
+            encoding,
+            deadline: Instant::now() + timeout,
+            response: Vec::new(),
+        });
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.active.as_ref().map(|active| active.deadline)
+    }
+
+    // Add reply bytes to the active request
+    pub fn collect(&mut self, data: &[u8]) {
+        if let Some(active) = self.active.as_mut() {
+            active.response.extend_from_slice(data);
+        }
+    }
+
+    // End the active request with a complete frame as its reply
+    pub fn finish(&mut self) -> Option<Active> {
+        self.active.take()
+    }
+
+    // End the active request at its deadline, with the bytes the framer still holds; without a
+    // framing rule whatever arrived is the reply, otherwise the request timed out
+    pub fn expire(&mut self, rest: Vec<u8>, per_read: bool) -> Option<(Active, &'static str)> {
+        let mut active = self.active.take()?;
+        active.response.extend(rest);
+        let status = if per_read && !active.response.is_empty() { "ok" } else { "timeout" };
+        Some((active, status))
+    }
+
+    // Drop everything, e.g. when the broker connection is lost
+    pub fn clear(&mut self) -> usize {
+        let dropped = self.queue.len() + self.active.take().map_or(0, |_| 1);
+        self.queue.clear();
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::{Framer, FramingConfig, FramingMode};
+    use crate::Logger;
+
+    fn request(id: u64, timeout: Duration) -> Request {
+        Request { id: id.into(), data: vec![id as u8], encoding: Encoding::Hex, timeout }
+    }
+
+    // Write the next request, as the port task does once the port is free
+    fn start_next(transactions: &mut Transactions) -> Option<serde_json::Value> {
+        let request = transactions.next()?;
+        transactions.start(request.id.clone(), request.encoding, request.timeout);
+        Some(request.id)
+    }
+
+    #[test]
+    fn requests_run_one_at_a_time_in_order() {
+        let mut transactions = Transactions::default();
+        for id in 0..MAX_QUEUED as u64 {
+            assert!(transactions.push(request(id, Duration::from_secs(1))).is_ok());
+        }
+        // A full queue hands the request back to be answered with "busy"
+        let rejected = transactions.push(request(99, Duration::from_secs(1))).unwrap_err();
+        assert_eq!(rejected.id, 99);
+
+        assert_eq!(start_next(&mut transactions), Some(0.into()));
+        // Nothing else is written while a request waits for its reply
+        assert!(transactions.next().is_none());
+        assert_eq!(transactions.finish().unwrap().id, 0);
+        assert_eq!(start_next(&mut transactions), Some(1.into()));
+        transactions.finish();
+        assert_eq!(start_next(&mut transactions), Some(2.into()));
+    }
+
+    #[test]
+    fn timeout_answers_with_the_request_id() {
+        let mut transactions = Transactions::default();
+        transactions.push(request(7, Duration::ZERO)).unwrap();
+        start_next(&mut transactions);
+        assert!(transactions.deadline().unwrap() <= Instant::now());
+
+        // With a framing rule a partial reply does not count
+        let (active, status) = transactions.expire(vec![0x01], false).unwrap();
+        assert_eq!((active.id, status), (7.into(), "timeout"));
+        assert!(!transactions.is_active());
+        assert!(transactions.expire(Vec::new(), false).is_none());
+
+        // Without one, whatever arrived before the deadline is the reply
+        transactions.push(request(8, Duration::ZERO)).unwrap();
+        start_next(&mut transactions);
+        transactions.collect(&[0x0A]);
+        let (active, status) = transactions.expire(vec![0x0B], true).unwrap();
+        assert_eq!((active.id, status, active.response), (8.into(), "ok", vec![0x0A, 0x0B]));
+
+        transactions.push(request(9, Duration::ZERO)).unwrap();
+        start_next(&mut transactions);
+        assert_eq!(transactions.expire(Vec::new(), true).unwrap().1, "timeout");
+    }
+
+    #[test]
+    fn complete_frame_answers_before_the_timeout() {
+        let logger = Logger::new();
+        let mut framer = Framer::new(FramingConfig {
+            mode: FramingMode::Delimiter { start: vec![], end: vec![0x0A] },
+            max_length: 16,
+            partial_timeout: Duration::ZERO,
+        });
+        let mut transactions = Transactions::default();
+        transactions.push(request(3, Duration::from_secs(60))).unwrap();
+        start_next(&mut transactions);
+
+        assert!(framer.push(b"o", &logger).is_empty());
+        let frames = framer.push(b"k\n", &logger);
+        assert_eq!(frames, [b"ok\n".to_vec()]);
+        assert!(Instant::now() < transactions.deadline().unwrap());
+        let active = transactions.finish().unwrap();
+        assert_eq!(active.id, 3);
+        assert_eq!(transactions.deadline(), None);
+    }
+
+    #[test]
+    fn clear_drops_queued_and_active_requests() {
+        let mut transactions = Transactions::default();
+        for id in 0..3 {
+            transactions.push(request(id, Duration::from_secs(1))).unwrap();
+        }
+        start_next(&mut transactions);
+        assert_eq!(transactions.clear(), 3);
+        assert!(!transactions.is_active());
+        assert!(transactions.next().is_none());
+        assert_eq!(transactions.clear(), 0);
+    }
+}
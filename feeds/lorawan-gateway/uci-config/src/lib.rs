@@ -0,0 +1,347 @@
+// Reader for OpenWrt UCI configuration files (/etc/config/<package>).
+//
+// The file is parsed directly rather than through `uci get`, so a package is
+// read once per load and the config logic of the daemons can be tested
+// off-target. Changes staged with `uci set` are only seen after `uci commit`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+mod parser;
+
+pub const CONFIG_DIR: &str = "/etc/config";
+
+#[derive(Debug)]
+pub enum Error {
+    Io { path: PathBuf, error: std::io::Error },
+    // `path` is set when the text was read by `load` or `load_file`
+    Parse { path: Option<PathBuf>, line: usize, message: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            Error::Parse { path: Some(path), line, message } => write!(f, "{}: line {}: {}", path.display(), line, message),
+            Error::Parse { path: None, line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Value of an `option` or the values of a `list`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Option(String),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    section_type: String,
+    // None for anonymous sections, which are addressed as `@type[index]`
+    name: Option<String>,
+    options: Vec<(String, Value)>,
+}
+
+impl Section {
+    pub fn section_type(&self) -> &str {
+        &self.section_type
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    // Options in file order
+    pub fn options(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.options.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn value(&self, option: &str) -> Option<&Value> {
+        self.options.iter().find(|(name, _)| name == option).map(|(_, value)| value)
+    }
+
+    // Value of a plain option; lists are not returned
+    pub fn get(&self, option: &str) -> Option<&str> {
+        match self.value(option)? {
+            Value::Option(value) => Some(value),
+            Value::List(_) => None,
+        }
+    }
+
+    // Values of a list; a plain option reads as a list of one
+    pub fn get_list(&self, option: &str) -> &[String] {
+        match self.value(option) {
+            Some(Value::List(values)) => values,
+            Some(Value::Option(value)) => std::slice::from_ref(value),
+            None => &[],
+        }
+    }
+
+    // Boolean as understood by `config_get_bool`; None when unset or not a boolean
+    pub fn get_bool(&self, option: &str) -> Option<bool> {
+        match self.get(option)?.to_ascii_lowercase().as_str() {
+            "1" | "on" | "true" | "yes" | "enabled" => Some(true),
+            "0" | "off" | "false" | "no" | "disabled" => Some(false),
+            _ => None,
+        }
+    }
+
+    // Option parsed as `T`; None when unset or invalid
+    pub fn get_parsed<T: FromStr>(&self, option: &str) -> Option<T> {
+        self.get(option)?.trim().parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Package {
+    sections: Vec<Section>,
+}
+
+impl Package {
+    pub fn parse(text: &str) -> Result<Package, Error> {
+        Ok(Package {
+            sections: parser::parse(text)?,
+        })
+    }
+
+    // Sections in file order
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    pub fn sections_of_type<'a>(&'a self, section_type: &'a str) -> impl Iterator<Item = &'a Section> {
+        self.sections.iter().filter(move |section| section.section_type == section_type)
+    }
+
+    // Section by name, or by type and position as in `@serial[0]` (negative counts from the end)
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        let Some(reference) = name.strip_prefix('@') else {
+            return self.sections.iter().find(|section| section.name.as_deref() == Some(name));
+        };
+        let (section_type, index) = reference.strip_suffix(']')?.split_once('[')?;
+        let index: isize = index.parse().ok()?;
+        let mut matching = self.sections.iter().filter(|section| section.section_type == section_type);
+        if index >= 0 {
+            matching.nth(index as usize)
+        } else {
+            let matching: Vec<&Section> = matching.collect();
+            let index = matching.len().checked_sub(index.unsigned_abs())?;
+            matching.get(index).copied()
+        }
+    }
+
+    pub fn has_section(&self, name: &str) -> bool {
+        self.section(name).is_some()
+    }
+
+    pub fn get(&self, section: &str, option: &str) -> Option<&str> {
+        self.section(section)?.get(option)
+    }
+
+    pub fn get_list(&self, section: &str, option: &str) -> &[String] {
+        self.section(section).map_or(&[], |section| section.get_list(option))
+    }
+
+    pub fn get_bool(&self, section: &str, option: &str) -> Option<bool> {
+        self.section(section)?.get_bool(option)
+    }
+
+    pub fn get_parsed<T: FromStr>(&self, section: &str, option: &str) -> Option<T> {
+        self.section(section)?.get_parsed(option)
+    }
+}
+
+// Read /etc/config/<package>
+pub fn load(package: &str) -> Result<Package, Error> {
+    load_file(Path::new(CONFIG_DIR).join(package))
+}
+
+pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Package, Error> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|error| Error::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    Package::parse(&text).map_err(|error| match error {
+        Error::Parse { line, message, .. } => Error::Parse { path: Some(path.to_path_buf()), line, message },
+        error => error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Package {
+        Package::parse(text).unwrap()
+    }
+
+    fn parse_error(text: &str) -> (usize, String) {
+        match Package::parse(text) {
+            Err(Error::Parse { line, message, .. }) => (line, message),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn named_and_anonymous_sections() {
+        let package = parse(
+            "config mqtt 'mqtt'\n\
+             \toption host 'broker.local'\n\
+             \toption port '1883'\n\
+             \n\
+             config serial\n\
+             \toption device '/dev/ttyS1'\n\
+             \n\
+             config serial 'port2'\n\
+             \toption device '/dev/ttyS2'\n",
+        );
+        assert_eq!(package.sections().len(), 3);
+        assert_eq!(package.get("mqtt", "host"), Some("broker.local"));
+        assert_eq!(package.get_parsed::<u16>("mqtt", "port"), Some(1883));
+
+        let anonymous = package.section("@serial[0]").unwrap();
+        assert_eq!(anonymous.name(), None);
+        assert_eq!(anonymous.section_type(), "serial");
+        assert_eq!(anonymous.get("device"), Some("/dev/ttyS1"));
+
+        assert_eq!(package.get("@serial[1]", "device"), Some("/dev/ttyS2"));
+        assert_eq!(package.get("@serial[-1]", "device"), Some("/dev/ttyS2"));
+        assert_eq!(package.get("@serial[-2]", "device"), Some("/dev/ttyS1"));
+        assert_eq!(package.get("port2", "device"), Some("/dev/ttyS2"));
+        assert!(!package.has_section("@serial[2]"));
+        assert!(!package.has_section("@serial[-3]"));
+        assert!(!package.has_section("@serial"));
+        assert_eq!(package.sections_of_type("serial").count(), 2);
+    }
+
+    #[test]
+    fn missing_values() {
+        let package = parse("config mqtt 'mqtt'\n\toption port 'abc'\n");
+        assert_eq!(package.get("mqtt", "host"), None);
+        assert_eq!(package.get("other", "host"), None);
+        assert_eq!(package.get_parsed::<u16>("mqtt", "port"), None);
+        assert!(package.get_list("mqtt", "host").is_empty());
+    }
+
+    #[test]
+    fn quoting() {
+        let package = parse(concat!(
+            "config ups 'cmd'\n",
+            "\toption single 'echo \"Power Outage\"'\n",
+            "\toption double \"say \\\"hi\\\" \\\\ 'there'\"\n",
+            "\toption bare plain\\ word\n",
+            "\toption joined 'it'\\''s'\n",
+            "\toption mixed ab'c d'\"e\"\n",
+            "\toption empty ''\n",
+            "\toption hash 'a # b'\n",
+        ));
+        assert_eq!(package.get("cmd", "single"), Some("echo \"Power Outage\""));
+        assert_eq!(package.get("cmd", "double"), Some("say \"hi\" \\ 'there'"));
+        assert_eq!(package.get("cmd", "bare"), Some("plain word"));
+        assert_eq!(package.get("cmd", "joined"), Some("it's"));
+        assert_eq!(package.get("cmd", "mixed"), Some("abc de"));
+        assert_eq!(package.get("cmd", "empty"), Some(""));
+        assert_eq!(package.get("cmd", "hash"), Some("a # b"));
+    }
+
+    #[test]
+    fn multiline_values() {
+        let package = parse("config x 'x'\n\toption script 'line 1\nline 2'\n\toption next \"a\\\nb\"\n\toption after '1'\n");
+        assert_eq!(package.get("x", "script"), Some("line 1\nline 2"));
+        assert_eq!(package.get("x", "next"), Some("ab"));
+        assert_eq!(package.get("x", "after"), Some("1"));
+    }
+
+    #[test]
+    fn lists() {
+        let package = parse(
+            "config ups 'cmd'\n\
+             \tlist commands 'echo one'\n\
+             \tlist commands \"echo 'two'\"\n\
+             \toption single 'a'\n\
+             \tlist single 'b'\n\
+             \tlist replaced 'a'\n\
+             \toption replaced 'b'\n",
+        );
+        assert_eq!(package.get_list("cmd", "commands"), ["echo one", "echo 'two'"]);
+        assert_eq!(package.get("cmd", "commands"), None);
+        // `list` after `option` extends it, `option` after `list` replaces it
+        assert_eq!(package.get_list("cmd", "single"), ["a", "b"]);
+        assert_eq!(package.get("cmd", "replaced"), Some("b"));
+        assert_eq!(package.get_list("cmd", "replaced"), ["b"]);
+    }
+
+    #[test]
+    fn comments_and_separators() {
+        let package = parse(
+            "# header\n\
+             package 'test'\n\
+             \n\
+             config log 'ui' # trailing comment\n\
+             \t# option hidden '1'\n\
+             \toption a '1'; option b '2'\n",
+        );
+        let section = package.section("ui").unwrap();
+        assert_eq!(section.get("hidden"), None);
+        assert_eq!(section.get("a"), Some("1"));
+        assert_eq!(section.get("b"), Some("2"));
+        let names: Vec<&str> = section.options().map(|(name, _)| name).collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn repeated_named_section_is_merged() {
+        let package = parse("config a 'x'\n\toption one '1'\nconfig b 'y'\nconfig a 'x'\n\toption two '2'\n");
+        assert_eq!(package.sections().len(), 2);
+        assert_eq!(package.get("x", "one"), Some("1"));
+        assert_eq!(package.get("x", "two"), Some("2"));
+    }
+
+    #[test]
+    fn booleans() {
+        let package = parse(
+            "config s 's'\n\
+             \toption a '1'\n\toption b 'yes'\n\toption c 'On'\n\toption d 'enabled'\n\
+             \toption e '0'\n\toption f 'no'\n\toption g 'off'\n\toption h 'maybe'\n",
+        );
+        for option in ["a", "b", "c", "d"] {
+            assert_eq!(package.get_bool("s", option), Some(true), "{}", option);
+        }
+        for option in ["e", "f", "g"] {
+            assert_eq!(package.get_bool("s", option), Some(false), "{}", option);
+        }
+        assert_eq!(package.get_bool("s", "h"), None);
+        assert_eq!(package.get_bool("s", "missing"), None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_error("option a '1'\n").0, 1);
+        assert_eq!(parse_error("config a 'x'\n\n\toption a\n").0, 3);
+        assert_eq!(parse_error("config a 'x'\n\tfoo a 'b'\n"), (2, "unknown keyword \"foo\"".to_string()));
+        assert_eq!(parse_error("config a 'bad-name'\n").0, 1);
+        assert_eq!(parse_error("config a 'x'\n\toption a 'open\n").1, "unterminated '");
+        assert_eq!(parse_error("config a 'x'\n\toption a \"open\n").1, "unterminated \"");
+    }
+
+    #[test]
+    fn load_missing_file() {
+        let error = load_file("/nonexistent/uci-config-test").unwrap_err();
+        assert!(matches!(error, Error::Io { .. }));
+        assert!(error.to_string().starts_with("/nonexistent/uci-config-test: "));
+    }
+
+    #[test]
+    fn load_invalid_file_names_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken");
+        std::fs::write(&path, "config a 'x'\n\tfoo a 'b'\n").unwrap();
+        let error = load_file(&path).unwrap_err();
+        assert_eq!(error.to_string(), format!("{}: line 2: unknown keyword \"foo\"", path.display()));
+    }
+}
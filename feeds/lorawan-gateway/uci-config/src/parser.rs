@@ -0,0 +1,197 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::{Error, Section, Value};
+
+// Splits the file into statements of words, resolving quotes and escapes
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(text: &'a str) -> Lexer<'a> {
+        Lexer {
+            chars: text.chars().peekable(),
+            line: 1,
+        }
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::Parse {
+            path: None,
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+
+    // Next non-empty statement and the line it starts on; statements end at a newline or `;`
+    fn statement(&mut self) -> Result<Option<(usize, Vec<String>)>, Error> {
+        let mut words = Vec::new();
+        let mut line = self.line;
+        loop {
+            match self.chars.peek() {
+                None => break,
+                Some('\n') | Some(';') => {
+                    self.next_char();
+                    if !words.is_empty() {
+                        break;
+                    }
+                    line = self.line;
+                }
+                Some(c) if c.is_whitespace() => {
+                    self.next_char();
+                }
+                // A comment runs to the end of the line
+                Some('#') => {
+                    while !matches!(self.chars.peek(), None | Some('\n')) {
+                        self.next_char();
+                    }
+                }
+                Some(_) => words.push(self.word()?),
+            }
+        }
+        Ok((!words.is_empty()).then_some((line, words)))
+    }
+
+    // One word; quoted and unquoted parts next to each other are joined, as in the shell
+    fn word(&mut self) -> Result<String, Error> {
+        let mut word = String::new();
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                '\'' => {
+                    self.next_char();
+                    // No escapes inside single quotes; the value may span lines
+                    loop {
+                        match self.next_char() {
+                            Some('\'') => break,
+                            Some(c) => word.push(c),
+                            None => return Err(self.error("unterminated '")),
+                        }
+                    }
+                }
+                '"' => {
+                    self.next_char();
+                    loop {
+                        match self.next_char() {
+                            Some('"') => break,
+                            Some('\\') => match self.next_char() {
+                                // Line continuation
+                                Some('\n') => {}
+                                Some(c) => word.push(c),
+                                None => return Err(self.error("unterminated \"")),
+                            },
+                            Some(c) => word.push(c),
+                            None => return Err(self.error("unterminated \"")),
+                        }
+                    }
+                }
+                '\\' => {
+                    self.next_char();
+                    match self.next_char() {
+                        Some('\n') | None => {}
+                        Some(c) => word.push(c),
+                    }
+                }
+                ';' => break,
+                c if c.is_whitespace() => break,
+                c => {
+                    self.next_char();
+                    word.push(c);
+                }
+            }
+        }
+        Ok(word)
+    }
+}
+
+// Parse the text of a package into its sections, in file order
+pub fn parse(text: &str) -> Result<Vec<Section>, Error> {
+    let mut lexer = Lexer::new(text);
+    let mut sections: Vec<Section> = Vec::new();
+    // Index of the section that options are added to
+    let mut current: Option<usize> = None;
+
+    while let Some((line, words)) = lexer.statement()? {
+        let error = |message: String| Error::Parse { path: None, line, message };
+        match words[0].as_str() {
+            "package" => {
+                if words.len() != 2 {
+                    return Err(error("expected: package <name>".to_string()));
+                }
+            }
+            "config" => {
+                if words.len() < 2 || words.len() > 3 || !valid_type(&words[1]) {
+                    return Err(error("expected: config <type> [name]".to_string()));
+                }
+                let section_type = words[1].clone();
+                let name = words.get(2).filter(|name| !name.is_empty()).cloned();
+                if let Some(name) = &name {
+                    if !valid_name(name) {
+                        return Err(error(format!("invalid section name {:?}", name)));
+                    }
+                }
+                // A repeated named section continues the earlier one
+                let existing = name
+                    .as_ref()
+                    .and_then(|name| sections.iter().position(|s| s.name.as_ref() == Some(name)));
+                match existing {
+                    Some(index) => {
+                        sections[index].section_type = section_type;
+                        current = Some(index);
+                    }
+                    None => {
+                        sections.push(Section {
+                            section_type,
+                            name,
+                            options: Vec::new(),
+                        });
+                        current = Some(sections.len() - 1);
+                    }
+                }
+            }
+            keyword @ ("option" | "list") => {
+                if words.len() != 3 || !valid_name(&words[1]) {
+                    return Err(error(format!("expected: {} <name> <value>", keyword)));
+                }
+                let Some(index) = current else {
+                    return Err(error(format!("{} outside of a section", keyword)));
+                };
+                let section = &mut sections[index];
+                let name = words[1].clone();
+                let value = words[2].clone();
+                let existing = section.options.iter_mut().find(|(option, _)| *option == name);
+                match (keyword, existing) {
+                    ("option", Some((_, current))) => *current = Value::Option(value),
+                    ("option", None) => section.options.push((name, Value::Option(value))),
+                    // Adding to a plain option turns it into a list, like `uci add_list`
+                    (_, Some((_, current))) => match current {
+                        Value::List(values) => values.push(value),
+                        Value::Option(first) => *current = Value::List(vec![std::mem::take(first), value]),
+                    },
+                    (_, None) => section.options.push((name, Value::List(vec![value]))),
+                }
+            }
+            keyword => return Err(error(format!("unknown keyword {:?}", keyword))),
+        }
+    }
+    Ok(sections)
+}
+
+// Section and option names: letters, digits and underscores
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Section types may use any printable character
+fn valid_type(section_type: &str) -> bool {
+    !section_type.is_empty() && section_type.chars().all(|c| c.is_ascii_graphic())
+}
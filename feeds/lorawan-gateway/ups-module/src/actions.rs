@@ -0,0 +1,154 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Output, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::{mpsc, watch};
+use tokio::time::sleep;
+
+use crate::Logger;
+
+// Commands of one outage stage or of the restore, run in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    // "<name> stage" or "restore", for the log
+    pub label: String,
+    pub commands: Vec<String>,
+    // Limit for each command; None waits forever
+    pub timeout: Option<Duration>,
+    // UPS_* variables describing the event
+    pub env: Vec<(&'static str, String)>,
+}
+
+// A job with the generation it was queued in; it only runs while that is still current
+struct Queued {
+    job: Job,
+    generation: u64,
+}
+
+// Runs jobs on a task of its own so a slow command never stalls the monitor.
+// Jobs run one after the other, in the order they were queued.
+pub struct Runner {
+    jobs: mpsc::UnboundedSender<Queued>,
+    // Bumped by `cancel`; jobs of an older generation are dropped or killed
+    generation: watch::Sender<u64>,
+}
+
+impl Runner {
+    pub fn spawn(logger: Arc<Logger>) -> Runner {
+        let (jobs, mut queue) = mpsc::unbounded_channel::<Queued>();
+        let (generation, current) = watch::channel(0);
+        tokio::spawn(async move {
+            while let Some(queued) = queue.recv().await {
+                if *current.borrow() != queued.generation {
+                    logger.log(&format!("Dropped queued {} commands, power restored", queued.job.label));
+                    continue;
+                }
+                execute(&queued, current.clone(), &logger).await;
+            }
+        });
+        Runner { jobs, generation }
+    }
+
+    pub fn run(&self, job: Job) {
+        if job.commands.is_empty() {
+            return;
+        }
+        let generation = *self.generation.borrow();
+        let _ = self.jobs.send(Queued { job, generation });
+    }
+
+    // Drop the queued jobs and kill the running one, e.g. the outage stages once power is back;
+    // jobs queued afterwards run as usual
+    pub fn cancel(&self) {
+        self.generation.send_modify(|generation| *generation += 1);
+    }
+}
+
+async fn execute(queued: &Queued, mut current: watch::Receiver<u64>, logger: &Logger) {
+    let job = &queued.job;
+    logger.log(&format!("Starting {} command execution...", job.label));
+
+    for (index, cmd) in job.commands.iter().enumerate() {
+        if *current.borrow() != queued.generation {
+            logger.log(&format!("Skipped remaining {} commands, power restored", job.label));
+            return;
+        }
+        logger.log(&format!("Executing command {}/{}: {}", index + 1, job.commands.len(), cmd));
+
+        let cancelled = current.wait_for(|generation| *generation != queued.generation);
+        match run_command(cmd, job.timeout, &job.env, cancelled).await {
+            Ok(result) => {
+                if result.status.success() {
+                    logger.log(&format!("Command {} completed successfully", index + 1));
+                    if !result.stdout.is_empty() {
+                        let stdout = String::from_utf8_lossy(&result.stdout);
+                        logger.log(&format!("Output: {}", stdout.trim()));
+                    }
+                } else {
+                    match result.status.signal() {
+                        Some(signal) => logger.log(&format!("Command {} killed by signal {}", index + 1, signal)),
+                        None => logger.log(&format!("Command {} failed with exit code: {:?}",
+                            index + 1, result.status.code())),
+                    }
+                    if !result.stderr.is_empty() {
+                        let stderr = String::from_utf8_lossy(&result.stderr);
+                        logger.log(&format!("Error: {}", stderr.trim()));
+                    }
+                }
+            }
+            Err(e) => {
+                logger.log(&format!("Failed to execute command {}: {}", index + 1, e));
+            }
+        }
+
+        // Small delay between commands
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+// Run one command through the shell, killing it and everything it started on timeout
+// or once `cancelled` completes
+async fn run_command(
+    cmd: &str,
+    timeout: Option<Duration>,
+    env: &[(&'static str, String)],
+    cancelled: impl std::future::Future,
+) -> Result<Output, String> {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Own process group, so background children can be killed with the shell
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let pid = child.id();
+    let kill = || {
+        if let Some(pid) = pid {
+            unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+        }
+    };
+
+    let limit = async {
+        match timeout {
+            Some(timeout) => sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        result = child.wait_with_output() => result.map_err(|e| e.to_string()),
+        _ = limit => {
+            kill();
+            Err(format!("timed out after {}s, killed", timeout.map_or(0, |timeout| timeout.as_secs())))
+        }
+        _ = cancelled => {
+            kill();
+            Err("cancelled, power restored".to_string())
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Where the battery is read from (UCI battery option `source`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    // IIO ADC channel (`in_voltageX_raw`); the scale is in mV per step as in `in_voltage_scale`
+    Iio {
+        raw_path: PathBuf,
+        scale_path: Option<PathBuf>,
+        scale: f64,
+        offset: f64,
+        // Ratio of the voltage divider in front of the ADC
+        divider: f64,
+    },
+    // Kernel power_supply device, e.g. /sys/class/power_supply/<gauge> of an I2C fuel gauge
+    PowerSupply { path: PathBuf },
+}
+
+// UCI `config battery 'battery'`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryConfig {
+    pub source: Source,
+    pub interval: Duration,
+    // Voltages read as 0% and 100% when the source has no capacity of its own
+    pub empty_voltage: f64,
+    pub full_voltage: f64,
+}
+
+impl BatteryConfig {
+    // None when battery monitoring is disabled or not configured
+    pub fn load(package: &uci::Package) -> Result<Option<BatteryConfig>, String> {
+        if !package.get_bool("battery", "enabled").unwrap_or(false) {
+            return Ok(None);
+        }
+        let path = |option: &str| package.get("battery", option).filter(|path| !path.is_empty()).map(PathBuf::from);
+        let number = |option: &str, default: f64| package.get_parsed("battery", option).unwrap_or(default);
+
+        let source = match package.get("battery", "source").unwrap_or("iio") {
+            "iio" => Source::Iio {
+                raw_path: path("raw_path").ok_or("battery.raw_path is not set")?,
+                scale_path: path("scale_path"),
+                scale: number("scale", 1.0),
+                offset: number("offset", 0.0),
+                divider: number("divider", 1.0),
+            },
+            "power_supply" => Source::PowerSupply {
+                path: path("power_supply_path").ok_or("battery.power_supply_path is not set")?,
+            },
+            other => return Err(format!("unknown battery source {:?}", other)),
+        };
+        Ok(Some(BatteryConfig {
+            source,
+            interval: Duration::from_secs(package.get_parsed("battery", "interval").unwrap_or(30).max(1)),
+            empty_voltage: number("empty_voltage", 3.3),
+            full_voltage: number("full_voltage", 4.2),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reading {
+    // Volts
+    pub voltage: f64,
+    pub percentage: f64,
+    // charging, discharging, full or not_charging
+    pub charging: String,
+}
+
+// Take one reading; `mains` stands in for the charging state when the source has none
+pub fn sample(config: &BatteryConfig, mains: bool) -> Result<Reading, String> {
+    let (voltage, capacity, charging) = match &config.source {
+        Source::Iio { raw_path, scale_path, scale, offset, divider } => {
+            let raw: f64 = read_number(raw_path)?;
+            let scale = match scale_path {
+                Some(path) => read_number(path)?,
+                None => *scale,
+            };
+            ((raw + offset) * scale / 1000.0 * divider, None, None)
+        }
+        Source::PowerSupply { path } => {
+            // voltage_now is in microvolts
+            let voltage = read_number::<f64>(&path.join("voltage_now"))? / 1_000_000.0;
+            let capacity = read_number::<f64>(&path.join("capacity")).ok();
+            let charging = std::fs::read_to_string(path.join("status"))
+                .ok()
+                .map(|status| status.trim().to_ascii_lowercase().replace(' ', "_"));
+            (voltage, capacity, charging)
+        }
+    };
+
+    let percentage = capacity.unwrap_or_else(|| {
+        let span = config.full_voltage - config.empty_voltage;
+        if span > 0.0 {
+            (voltage - config.empty_voltage) / span * 100.0
+        } else {
+            0.0
+        }
+    });
+    let percentage = percentage.clamp(0.0, 100.0);
+    let charging = charging.unwrap_or_else(|| {
+        match (mains, percentage >= 99.0) {
+            (false, _) => "discharging",
+            (true, true) => "full",
+            (true, false) => "charging",
+        }
+        .to_string()
+    });
+
+    Ok(Reading {
+        voltage: (voltage * 1000.0).round() / 1000.0,
+        percentage: (percentage * 10.0).round() / 10.0,
+        charging,
+    })
+}
+
+fn read_number<T: std::str::FromStr>(path: &Path) -> Result<T, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{}: not a number: {:?}", path.display(), text.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fake sysfs tree with the given files
+    fn sysfs(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    fn config(text: &str) -> Result<Option<BatteryConfig>, String> {
+        BatteryConfig::load(&uci::Package::parse(text).unwrap())
+    }
+
+    fn iio(dir: &Path, options: &str) -> BatteryConfig {
+        let text = format!(
+            "config battery 'battery'\n\toption enabled '1'\n\toption raw_path '{}'\n{}",
+            dir.join("in_voltage0_raw").display(),
+            options
+        );
+        config(&text).unwrap().unwrap()
+    }
+
+    #[test]
+    fn load() {
+        assert_eq!(config("config battery 'battery'\n\toption enabled '0'\n"), Ok(None));
+        assert!(config("config battery 'battery'\n\toption enabled '1'\n").is_err());
+        assert!(config("config battery 'battery'\n\toption enabled '1'\n\toption source 'acpi'\n").is_err());
+    }
+
+    #[test]
+    fn iio_voltage_to_percentage() {
+        let tree = sysfs(&[("in_voltage0_raw", "1900\n"), ("in_voltage_scale", "1.0\n")]);
+        let dir = tree.path();
+        let scale = format!("\toption scale_path '{}'\n\toption divider '2'\n", dir.join("in_voltage_scale").display());
+        let config = iio(dir, &scale);
+        // 1900 steps of 1 mV behind a 1:2 divider, between 3.3 V and 4.2 V
+        let reading = sample(&config, false).unwrap();
+        assert_eq!(reading.voltage, 3.8);
+        assert_eq!(reading.percentage, 55.6);
+        assert_eq!(reading.charging, "discharging");
+        assert_eq!(sample(&config, true).unwrap().charging, "charging");
+    }
+
+    #[test]
+    fn threshold_crossing() {
+        let tree = sysfs(&[("in_voltage0_raw", "1700\n")]);
+        let dir = tree.path();
+        let config = iio(dir, "\toption divider '2'\n");
+        let percentage = || sample(&config, false).unwrap().percentage;
+        assert_eq!(percentage(), 11.1);
+
+        // Dropping through 10% on the next sample
+        std::fs::write(dir.join("in_voltage0_raw"), "1690\n").unwrap();
+        assert_eq!(percentage(), 8.9);
+
+        // Clamped outside the empty and full voltages
+        std::fs::write(dir.join("in_voltage0_raw"), "1500\n").unwrap();
+        assert_eq!(percentage(), 0.0);
+        std::fs::write(dir.join("in_voltage0_raw"), "2200\n").unwrap();
+        assert_eq!(percentage(), 100.0);
+        assert_eq!(sample(&config, true).unwrap().charging, "full");
+    }
+
+    #[test]
+    fn power_supply() {
+        let tree = sysfs(&[("voltage_now", "3712000\n"), ("capacity", "18\n"), ("status", "Not charging\n")]);
+        let dir = tree.path();
+        let text = format!(
+            "config battery 'battery'\n\toption enabled '1'\n\toption source 'power_supply'\n\toption power_supply_path '{}'\n",
+            dir.display()
+        );
+        let config = config(&text).unwrap().unwrap();
+        let reading = sample(&config, true).unwrap();
+        assert_eq!(reading, Reading { voltage: 3.712, percentage: 18.0, charging: "not_charging".to_string() });
+
+        // Without capacity and status the percentage comes from the voltage
+        std::fs::remove_file(dir.join("capacity")).unwrap();
+        std::fs::remove_file(dir.join("status")).unwrap();
+        let reading = sample(&config, false).unwrap();
+        assert_eq!(reading.percentage, 45.8);
+        assert_eq!(reading.charging, "discharging");
+    }
+
+    #[test]
+    fn missing_or_unreadable_files() {
+        let tree = sysfs(&[]);
+        let dir = tree.path();
+        let config = iio(dir, "");
+        let error = sample(&config, false).unwrap_err();
+        assert!(error.starts_with(&dir.join("in_voltage0_raw").display().to_string()), "{}", error);
+
+        std::fs::write(dir.join("in_voltage0_raw"), "garbage\n").unwrap();
+        assert!(sample(&config, false).unwrap_err().contains("not a number"));
+
+        // A configured scale file has to be readable too
+        std::fs::write(dir.join("in_voltage0_raw"), "1900\n").unwrap();
+        let scale = format!("\toption scale_path '{}'\n", dir.join("in_voltage_scale").display());
+        assert!(sample(&iio(dir, &scale), false).is_err());
+
+        // A directory where a file is expected
+        std::fs::create_dir(dir.join("voltage_now")).unwrap();
+        let supply = BatteryConfig { source: Source::PowerSupply { path: dir.to_path_buf() }, ..config };
+        assert!(sample(&supply, false).is_err());
+    }
+}
@@ -1,16 +1,94 @@
 use chrono::Local;
-use gpio_cdev::{Chip, LineRequestFlags, EventRequestFlags, EventType};
+use gpio_cdev::{Chip, LineEventHandle, LineRequestFlags, EventRequestFlags, EventType};
 use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::process::Command;
 use std::sync::{Arc, Mutex as StdMutex};
-use std::time::{Duration, Instant};
-use tokio::time::sleep;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
+
+mod actions;
+mod battery;
+mod mqtt;
+mod status;
+
+// The UPS line has to stay at a level this long before it counts
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+// Seconds a command may run before it is killed (UCI ups option `command_timeout`)
+const DEFAULT_COMMAND_TIMEOUT: u64 = 60;
 
 // UPS Configuration Structure
 #[derive(Debug, Clone, PartialEq)]
 struct Config {
+    // Outage actions by delay; a stage not reached when power returns is cancelled
+    stages: Vec<Stage>,
+    // Run when mains power returns after an outage stage ran
+    restore_commands: Vec<String>,
+    // Limit for each command unless a stage sets its own; None waits forever
+    command_timeout: Option<Duration>,
+}
+
+// Commands run once power has been out for `delay` or the battery drops below
+// `battery_below` percent, whichever comes first
+#[derive(Debug, Clone, PartialEq)]
+struct Stage {
+    name: String,
+    delay: Option<Duration>,
+    battery_below: Option<f64>,
     commands: Vec<String>,
+    timeout: Option<Duration>,
+}
+
+impl Stage {
+    fn describe(&self) -> String {
+        let mut triggers = Vec::new();
+        if let Some(delay) = self.delay {
+            triggers.push(format!("at {}s", delay.as_secs()));
+        }
+        if let Some(threshold) = self.battery_below {
+            triggers.push(format!("below {}%", threshold));
+        }
+        format!("{} {}", self.name, triggers.join(" or "))
+    }
+}
+
+// Power outage in progress
+struct Outage {
+    started: Instant,
+    // Wall-clock start, passed to commands as UPS_OUTAGE_START
+    since: String,
+    // Stages not reached yet, next first
+    stages: Vec<Stage>,
+    stages_run: usize,
+}
+
+impl Outage {
+    fn new(stages: Vec<Stage>) -> Outage {
+        Outage {
+            started: Instant::now(),
+            since: status::now(),
+            stages,
+            stages_run: 0,
+        }
+    }
+
+    // When the next stage is due by its delay
+    fn next_at(&self) -> Option<Instant> {
+        self.stages.iter().filter_map(|stage| stage.delay).min().map(|delay| self.started + delay)
+    }
+
+    // Remove the first stage whose delay has passed or whose battery threshold is crossed
+    fn take_due(&mut self, battery: Option<f64>) -> Option<Stage> {
+        let now = Instant::now();
+        let index = self.stages.iter().position(|stage| {
+            stage.delay.is_some_and(|delay| self.started + delay <= now)
+                || stage.battery_below.zip(battery).is_some_and(|(threshold, level)| level < threshold)
+        })?;
+        Some(self.stages.remove(index))
+    }
 }
 
 // Logger Structure
@@ -62,85 +140,248 @@ impl Logger {
 }
 
 fn load_config() -> Result<Config, String> {
-    let mut commands = Vec::new();
-    
-    let config_content = std::fs::read_to_string("/etc/config/ups-module")
+    let package = uci::load("ups-module")
         .map_err(|e| format!("Failed to read config file: {}", e))?;
-    
-    for line in config_content.lines() {
-        let trimmed = line.trim();
-        if trimmed.starts_with("list commands") {
-            if let Some(start) = trimmed.find('\'') {
-                if let Some(end) = trimmed.rfind('\'') {
-                    if start < end {
-                        let cmd = &trimmed[start + 1..end];
-                        commands.push(cmd.to_string());
-                    }
-                }
+
+    // Lists of every ups section, in file order
+    let list = |option: &str| -> Vec<String> {
+        package
+            .sections_of_type("ups")
+            .flat_map(|section| section.get_list(option))
+            .cloned()
+            .collect()
+    };
+    let grace_period = package
+        .sections_of_type("ups")
+        .find_map(|section| section.get_parsed("grace_period"))
+        .unwrap_or(0);
+    // Seconds, 0 for no limit
+    let timeout = |seconds: u64| (seconds > 0).then(|| Duration::from_secs(seconds));
+    let command_timeout = timeout(
+        package
+            .sections_of_type("ups")
+            .find_map(|section| section.get_parsed("command_timeout"))
+            .unwrap_or(DEFAULT_COMMAND_TIMEOUT),
+    );
+
+    // The ups commands are a stage of their own, delayed by grace_period
+    let mut stages = Vec::new();
+    let commands = list("commands");
+    if !commands.is_empty() {
+        stages.push(Stage {
+            name: "outage".to_string(),
+            delay: Some(Duration::from_secs(grace_period)),
+            battery_below: None,
+            commands,
+            timeout: command_timeout,
+        });
+    }
+    for (index, section) in package.sections_of_type("stage").enumerate() {
+        if section.get_bool("enabled") == Some(false) {
+            continue;
+        }
+        let delay = section.get_parsed("delay").map(Duration::from_secs);
+        let battery_below = section.get_parsed("battery_below");
+        stages.push(Stage {
+            name: section.get("name").map_or_else(|| format!("stage{}", index), str::to_string),
+            // A stage without any trigger runs at once
+            delay: if delay.is_none() && battery_below.is_none() { Some(Duration::ZERO) } else { delay },
+            battery_below,
+            commands: section.get_list("commands").to_vec(),
+            timeout: section.get_parsed("timeout").map_or(command_timeout, timeout),
+        });
+    }
+    // Stable sort: stages with the same delay run in file order, battery-only stages last
+    stages.sort_by_key(|stage| stage.delay.unwrap_or(Duration::MAX));
+
+    Ok(Config {
+        stages,
+        restore_commands: list("restore_commands"),
+        command_timeout,
+    })
+}
+
+// Report the level of the UPS line on every edge (true = mains power present)
+fn watch_line(mut line_handle: LineEventHandle, levels: mpsc::UnboundedSender<Result<bool, String>>) {
+    loop {
+        let level = match line_handle.next() {
+            Some(Ok(evt)) => Ok(evt.event_type() == EventType::RisingEdge),
+            Some(Err(e)) => {
+                std::thread::sleep(Duration::from_secs(1));
+                Err(e.to_string())
+            }
+            None => {
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
             }
+        };
+        if levels.send(level).is_err() {
+            return;
+        }
+    }
+}
+
+fn load_config_or_log(logger: &Logger) -> Option<Config> {
+    match load_config() {
+        Ok(config) => Some(config),
+        Err(e) => {
+            logger.log(&format!("Failed to load config: {}", e));
+            None
         }
     }
-    
-    Ok(Config { commands })
 }
 
-// Execute power outage commands
-async fn execute_commands(commands: &[String], logger: &Logger) {
-    logger.log("Power outage detected! Starting command execution...");
+// UPS_* variables for the commands of `event` ("outage" or "restore")
+fn event_env(event: &str, stage: &str, outage: &Outage, battery: Option<f64>) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+        ("UPS_EVENT", event.to_string()),
+        ("UPS_STAGE", stage.to_string()),
+        ("UPS_TIMESTAMP", status::now()),
+        ("UPS_OUTAGE_START", outage.since.clone()),
+        ("UPS_OUTAGE_DURATION", outage.started.elapsed().as_secs().to_string()),
+    ];
+    if let Some(level) = battery {
+        env.push(("UPS_BATTERY_PERCENT", level.to_string()));
+    }
+    env
+}
 
-    for (index, cmd) in commands.iter().enumerate() {
-        logger.log(&format!("Executing command {}/{}: {}", index + 1, commands.len(), cmd));
+// Falling edge: schedule the outage stages, running those without a delay now
+async fn start_outage(
+    status: &mut status::Status,
+    reporter: &status::Reporter,
+    runner: &actions::Runner,
+    logger: &Logger,
+) -> Outage {
+    logger.log("Power outage detected!");
+    status.outage();
+    reporter.publish(status, "outage").await;
+    schedule_outage(status.battery_percentage(), runner, logger)
+}
 
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .output();
+// Outage with the configured stages, the due ones already queued
+fn schedule_outage(battery: Option<f64>, runner: &actions::Runner, logger: &Logger) -> Outage {
+    let stages = load_config_or_log(logger).map(|config| config.stages).unwrap_or_default();
+    if !stages.is_empty() {
+        let schedule: Vec<String> = stages.iter().map(Stage::describe).collect();
+        logger.log(&format!("Outage stages: {}", schedule.join(", ")));
+    }
+    let mut outage = Outage::new(stages);
+    run_due_stages(&mut outage, battery, runner, logger);
+    outage
+}
 
-        match output {
-            Ok(result) => {
-                if result.status.success() {
-                    logger.log(&format!("Command {} completed successfully", index + 1));
-                    if !result.stdout.is_empty() {
-                        let stdout = String::from_utf8_lossy(&result.stdout);
-                        logger.log(&format!("Output: {}", stdout.trim()));
-                    }
-                } else {
-                    logger.log(&format!("Command {} failed with exit code: {:?}", 
-                        index + 1, result.status.code()));
-                    if !result.stderr.is_empty() {
-                        let stderr = String::from_utf8_lossy(&result.stderr);
-                        logger.log(&format!("Error: {}", stderr.trim()));
-                    }
-                }
+// Queue every stage that is due by delay or battery level
+fn run_due_stages(outage: &mut Outage, battery: Option<f64>, runner: &actions::Runner, logger: &Logger) {
+    while let Some(stage) = outage.take_due(battery) {
+        if let Some(level) = battery.filter(|_| stage.battery_below.is_some()) {
+            logger.log(&format!("Battery at {}% for {}", level, stage.describe()));
+        }
+        runner.run(actions::Job {
+            label: format!("{} stage", stage.name),
+            env: event_env("outage", &stage.name, outage, battery),
+            commands: stage.commands,
+            timeout: stage.timeout,
+        });
+        outage.stages_run += 1;
+    }
+}
+
+// Rising edge: cancel the stages not reached, and run the restore commands if any stage ran
+async fn end_outage(
+    outage: Outage,
+    status: &mut status::Status,
+    reporter: &status::Reporter,
+    runner: &actions::Runner,
+    logger: &Logger,
+) {
+    let duration = outage.started.elapsed().as_secs_f64();
+    logger.log(&format!("Power restored after {:.1}s on battery", duration));
+    status.restore(duration);
+    reporter.publish(status, "restore").await;
+    // Stages queued or still running are of no use any more
+    runner.cancel();
+    if !outage.stages.is_empty() {
+        let names: Vec<&str> = outage.stages.iter().map(|stage| stage.name.as_str()).collect();
+        logger.log(&format!("Cancelled outage stages: {}", names.join(", ")));
+    }
+    if outage.stages_run > 0 {
+        if let Some(config) = load_config_or_log(logger) {
+            runner.run(actions::Job {
+                label: "restore".to_string(),
+                env: event_env("restore", "restore", &outage, status.battery_percentage()),
+                commands: config.restore_commands,
+                timeout: config.command_timeout,
+            });
+        }
+    }
+}
+
+// MQTT publisher and battery sampling, as last read from UCI
+#[derive(Default)]
+struct Settings {
+    mqtt: Option<mqtt::MqttConfig>,
+    mqtt_task: Option<JoinHandle<()>>,
+    battery: Option<battery::BatteryConfig>,
+}
+
+impl Settings {
+    // Read the settings again, restarting the MQTT publisher if its options changed;
+    // true when the battery settings changed
+    fn apply(&mut self, mqtt_status: &watch::Receiver<String>, logger: &Arc<Logger>) -> bool {
+        let package = match uci::load("ups-module") {
+            Ok(package) => package,
+            Err(e) => {
+                logger.log(&format!("Failed to load MQTT and battery config: {}", e));
+                return false;
             }
+        };
+
+        let mqtt_config = Some(mqtt::MqttConfig::load(&package)).filter(|config| config.enabled);
+        if mqtt_config != self.mqtt {
+            if let Some(task) = self.mqtt_task.take() {
+                logger.log("MQTT settings changed, reconnecting");
+                task.abort();
+            }
+            self.mqtt_task = mqtt_config
+                .clone()
+                .map(|config| tokio::spawn(mqtt::run(config, mqtt_status.clone(), logger.clone())));
+            self.mqtt = mqtt_config;
+        }
+
+        let battery_config = match battery::BatteryConfig::load(&package) {
+            Ok(config) => config,
             Err(e) => {
-                logger.log(&format!("Failed to execute command {}: {}", index + 1, e));
+                logger.log(&format!("Battery monitoring disabled: {}", e));
+                None
             }
+        };
+        if battery_config == self.battery {
+            return false;
         }
+        self.battery = battery_config;
+        true
+    }
 
-        // Small delay between commands
-        sleep(Duration::from_millis(100)).await;
+    fn battery_timer(&self) -> tokio::time::Interval {
+        tokio::time::interval(self.battery.as_ref().map_or(Duration::from_secs(60), |config| config.interval))
     }
 }
 
-// Monitor UPS GPIO for falling edge
+// Monitor UPS GPIO for power outages and restores
 async fn monitor_gpio(logger: Arc<Logger>) -> Result<(), Box<dyn std::error::Error>> {
     logger.log("Initializing UPS monitoring ...");
 
-    let uci_get = |config: &str, section: &str, option: &str| -> Result<String, Box<dyn std::error::Error>> {
-        let output = Command::new("uci")
-            .args(&["get", &format!("{}.{}.{}", config, section, option)])
-            .output()?;
-        if output.status.success() {
-            Ok(String::from_utf8(output.stdout)?.trim().to_string())
-        } else {
-            Err(format!("uci get failed: {}.{}.{}", config, section, option).into())
-        }
-    };
-
     // Load GPIO configuration from UCI
-    let ups_gpio_chip = uci_get("hardware", "hardware", "ups_gpio_chip")?;
-    let ups_gpio_line: u32 = uci_get("hardware", "hardware", "ups_gpio_line")?.parse()?;
+    let hardware = uci::load("hardware")?;
+    let ups_gpio_chip = hardware
+        .get("hardware", "ups_gpio_chip")
+        .ok_or("hardware.hardware.ups_gpio_chip is not set")?
+        .to_string();
+    let ups_gpio_line: u32 = hardware
+        .get("hardware", "ups_gpio_line")
+        .ok_or("hardware.hardware.ups_gpio_line is not set")?
+        .parse()?;
         
     logger.log(&format!("Using GPIO chip: {}, line: {}", ups_gpio_chip, ups_gpio_line));
 
@@ -150,54 +391,114 @@ async fn monitor_gpio(logger: Arc<Logger>) -> Result<(), Box<dyn std::error::Err
     // Get UPS GPIO line
     let line = chip.get_line(ups_gpio_line)?;
     
-    // Request line for input with falling edge events
-    let mut line_handle = line.events(
+    // Request line for input with events on both edges
+    let line_handle = line.events(
         LineRequestFlags::INPUT,
-        EventRequestFlags::FALLING_EDGE,
+        EventRequestFlags::BOTH_EDGES,
         "ups-monitor",
     )?;
 
+    let mut mains = line_handle.get_value()? == 1;
+
+    // SIGHUP (sent by `/etc/init.d/ups-module reload`) reapplies the MQTT and battery settings;
+    // outage stages are read from UCI whenever an outage starts
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    // Power state for ubus and MQTT
+    let mut status = status::Status::new(mains);
+    let (reporter, mqtt_status) = status::Reporter::new(logger.clone());
+    reporter.publish(&status, "start").await;
+    let mut settings = Settings::default();
+    settings.apply(&mqtt_status, &logger);
+    let mut battery_timer = settings.battery_timer();
+    // Last sampling error, logged once until it changes
+    let mut battery_error: Option<String> = None;
+
+    // Commands run off the event loop, one job at a time
+    let runner = actions::Runner::spawn(logger.clone());
+
+    // Starting on battery counts as an outage from now on; battery thresholds
+    // are checked from the first sample
+    let mut outage = if mains {
+        None
+    } else {
+        logger.log("Starting on battery power");
+        Some(schedule_outage(None, &runner, &logger))
+    };
+
+    // Edge events are read on their own thread, the blocking read would stall the runtime
+    let (levels_tx, mut levels) = mpsc::unbounded_channel();
+    std::thread::spawn(move || watch_line(line_handle, levels_tx));
+
     logger.log("UPS monitoring started, waiting for power outage signal...");
 
-    let debounce_duration = Duration::from_millis(500);
-    let mut last_event_time: Option<Instant> = None;
+    // Level of the last edge, acted on once it has been stable for DEBOUNCE
+    let mut pending: Option<(bool, Instant)> = None;
 
     loop {
-        match line_handle.next() {
-            Some(Ok(evt)) => {
-                if evt.event_type() == EventType::FallingEdge {
-                    let now = Instant::now();
-                    
-                    if let Some(last_time) = last_event_time {
-                        let elapsed = last_time.elapsed();
-                        if elapsed < debounce_duration {
-                            continue;
-                        }
-                    }
-                    
-                    let current_value = line_handle.get_value()?;
-                    if current_value == 0 {
-                        match load_config() {
-                            Ok(config) => {
-                                if !config.commands.is_empty() {
-                                    execute_commands(&config.commands, &logger).await;
-                                }
-                            }
-                            Err(e) => {
-                                logger.log(&format!("Failed to load config: {}", e));
+        let settle_at = pending.map(|(_, at)| at);
+        let stage_at = outage.as_ref().and_then(Outage::next_at);
+        tokio::select! {
+            level = levels.recv() => {
+                match level {
+                    Some(Ok(level)) => pending = Some((level, Instant::now() + DEBOUNCE)),
+                    Some(Err(e)) => logger.log(&format!("GPIO event error: {}", e)),
+                    None => return Err("GPIO event reader stopped".into()),
+                }
+            }
+
+            _ = sleep_until(settle_at.unwrap_or_else(Instant::now)), if settle_at.is_some() => {
+                match pending.take() {
+                    Some((level, _)) if level != mains => {
+                        mains = level;
+                        if mains {
+                            if let Some(outage) = outage.take() {
+                                end_outage(outage, &mut status, &reporter, &runner, &logger).await;
                             }
+                        } else {
+                            outage = Some(start_outage(&mut status, &reporter, &runner, &logger).await);
                         }
-                        
-                        last_event_time = Some(now);
                     }
+                    // Bounced back to the level it had
+                    _ => {}
                 }
             }
-            Some(Err(e)) => {
-                logger.log(&format!("GPIO event error: {}", e));
-                sleep(Duration::from_secs(1)).await;
+
+            // Next stage due and power still out
+            _ = sleep_until(stage_at.unwrap_or_else(Instant::now)), if stage_at.is_some() => {
+                if let Some(outage) = outage.as_mut() {
+                    run_due_stages(outage, status.battery_percentage(), &runner, &logger);
+                }
             }
-            None => {
-                sleep(Duration::from_millis(100)).await;
+
+            _ = hangup.recv() => {
+                logger.log("Reloading configuration");
+                if settings.apply(&mqtt_status, &logger) {
+                    battery_timer = settings.battery_timer();
+                    battery_error = None;
+                }
+            }
+
+            _ = battery_timer.tick(), if settings.battery.is_some() => {
+                let Some(config) = settings.battery.as_ref() else { continue };
+                match battery::sample(config, mains) {
+                    Ok(reading) => {
+                        if battery_error.take().is_some() {
+                            logger.log("Battery readings resumed");
+                        }
+                        status.battery = Some(reading);
+                        reporter.update(&status);
+                        if let Some(outage) = outage.as_mut() {
+                            run_due_stages(outage, status.battery_percentage(), &runner, &logger);
+                        }
+                    }
+                    Err(e) => {
+                        if battery_error.as_ref() != Some(&e) {
+                            logger.log(&format!("Battery reading failed: {}", e));
+                            battery_error = Some(e);
+                        }
+                    }
+                }
             }
         }
     }
@@ -219,3 +520,51 @@ async fn main() {
         logger.log(&format!("UPS monitoring failed: {}", e));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage(name: &str, delay: Option<u64>, battery_below: Option<f64>) -> Stage {
+        Stage {
+            name: name.to_string(),
+            delay: delay.map(Duration::from_secs),
+            battery_below,
+            commands: vec!["true".to_string()],
+            timeout: None,
+        }
+    }
+
+    fn names(outage: &mut Outage, battery: Option<f64>) -> Vec<String> {
+        std::iter::from_fn(|| outage.take_due(battery)).map(|stage| stage.name).collect()
+    }
+
+    #[test]
+    fn battery_threshold_crossing() {
+        let mut outage = Outage::new(vec![stage("shutdown", Some(3600), Some(20.0)), stage("notify", None, Some(50.0))]);
+        assert!(names(&mut outage, None).is_empty());
+        assert!(names(&mut outage, Some(50.0)).is_empty());
+        assert_eq!(names(&mut outage, Some(49.9)), ["notify"]);
+        assert_eq!(names(&mut outage, Some(19.5)), ["shutdown"]);
+        assert_eq!(outage.next_at(), None);
+    }
+
+    #[test]
+    fn threshold_stage_runs_once() {
+        let mut outage = Outage::new(vec![stage("shutdown", None, Some(20.0))]);
+        assert_eq!(names(&mut outage, Some(19.9)), ["shutdown"]);
+        // A reading bouncing around the threshold does not run it again
+        assert!(names(&mut outage, Some(20.1)).is_empty());
+        assert!(names(&mut outage, Some(19.9)).is_empty());
+    }
+
+    #[test]
+    fn delay_or_threshold_first() {
+        let mut outage = Outage::new(vec![stage("outage", Some(0), None), stage("shutdown", Some(600), Some(20.0))]);
+        assert_eq!(names(&mut outage, Some(80.0)), ["outage"]);
+        assert_eq!(outage.next_at(), Some(outage.started + Duration::from_secs(600)));
+        // Ten minutes into the outage
+        outage.started -= Duration::from_secs(600);
+        assert_eq!(names(&mut outage, Some(80.0)), ["shutdown"]);
+    }
+}
@@ -0,0 +1,98 @@
+use mqtt_link::{Broker, Connection, Event};
+use rumqttc::{LastWill, QoS};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+use crate::Logger;
+
+// Retained power status on a broker (UCI `config mqtt 'mqtt'`), with the
+// transport and authentication options of the rs485 bridges
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    pub enabled: bool,
+    broker: Broker,
+    topic: String,
+    qos_level: QoS,
+    reconnect_delay: u64,
+}
+
+impl MqttConfig {
+    pub fn load(package: &uci::Package) -> MqttConfig {
+        MqttConfig {
+            enabled: package.get_bool("mqtt", "enabled").unwrap_or(false),
+            broker: Broker::load(package, "mqtt", "ups_module"),
+            topic: package.get("mqtt", "topic").unwrap_or("ups/status").to_string(),
+            qos_level: match package.get_parsed::<u8>("mqtt", "qos").unwrap_or(1) {
+                0 => QoS::AtMostOnce,
+                2 => QoS::ExactlyOnce,
+                _ => QoS::AtLeastOnce,
+            },
+            reconnect_delay: package.get_parsed("mqtt", "reconnect_delay").unwrap_or(30),
+        }
+    }
+}
+
+// Payload the broker keeps on the topic when the connection is lost
+const OFFLINE: &str = r#"{"state":"offline"}"#;
+
+// Keep the latest status retained on the broker, reconnecting as needed
+pub async fn run(config: MqttConfig, mut status: watch::Receiver<String>, logger: Arc<Logger>) {
+    let reconnect_delay = Duration::from_secs(config.reconnect_delay);
+    let mut options = loop {
+        match config.broker.options() {
+            Ok(options) => break options,
+            Err(e) => {
+                logger.log(&format!("MQTT setup failed: {}", e));
+                sleep(reconnect_delay).await;
+            }
+        }
+    };
+    options.set_last_will(LastWill::new(&config.topic, OFFLINE, config.qos_level, true));
+    logger.log(&format!("Connecting to MQTT broker {}:{}", config.broker.host, config.broker.port));
+    let (connection, mut events) = Connection::spawn(options, reconnect_delay);
+
+    let mut connected = false;
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                match event {
+                    Some(Event::Connected) => {
+                        logger.log(&format!("MQTT connected, publishing status to {}", config.topic));
+                        connected = true;
+                        let payload = status.borrow_and_update().clone();
+                        publish(&connection, &config, payload, &logger);
+                    }
+                    Some(Event::Disconnected(e)) => {
+                        logger.log(&format!("MQTT connection error: {}", e));
+                        connected = false;
+                    }
+                    Some(_) => {}
+                    None => return,
+                }
+            }
+
+            changed = status.changed() => {
+                // The monitor stopped
+                if changed.is_err() {
+                    return;
+                }
+                if connected {
+                    let payload = status.borrow_and_update().clone();
+                    publish(&connection, &config, payload, &logger);
+                }
+            }
+        }
+    }
+}
+
+fn publish(connection: &Connection, config: &MqttConfig, payload: String, logger: &Logger) {
+    // Nothing reported yet
+    if payload.is_empty() {
+        return;
+    }
+    if let Err(e) = connection.publish(&config.topic, config.qos_level, true, payload.into_bytes()) {
+        logger.log(&format!("MQTT publish failed: {}", e));
+    }
+}
@@ -0,0 +1,128 @@
+use chrono::Local;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::watch;
+
+use crate::battery::Reading;
+use crate::Logger;
+
+// Read by the rpcd plugin behind `ubus call ups status`
+pub const STATUS_PATH: &str = "/var/run/ups/status.json";
+
+// How long `ubus send` may take before it is killed, so a hung ubusd does not hold up the
+// outage and restore handling that publishes the event
+const UBUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Power state as reported over ubus and MQTT
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    // "mains" or "battery"
+    pub state: &'static str,
+    // Time of the last transition, or of the service start
+    pub since: String,
+    // Outages since the service started
+    pub outage_count: u64,
+    // Seconds on battery of the last finished outage
+    pub last_outage_duration: Option<f64>,
+    // Latest sample when battery monitoring is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery: Option<Reading>,
+}
+
+impl Status {
+    pub fn new(mains: bool) -> Status {
+        Status {
+            state: if mains { "mains" } else { "battery" },
+            since: now(),
+            outage_count: 0,
+            last_outage_duration: None,
+            battery: None,
+        }
+    }
+
+    pub fn battery_percentage(&self) -> Option<f64> {
+        self.battery.as_ref().map(|battery| battery.percentage)
+    }
+
+    pub fn outage(&mut self) {
+        self.state = "battery";
+        self.since = now();
+        self.outage_count += 1;
+    }
+
+    pub fn restore(&mut self, duration: f64) {
+        self.state = "mains";
+        self.since = now();
+        self.last_outage_duration = Some(duration);
+    }
+}
+
+pub fn now() -> String {
+    Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+// Publishes every status change to the status file, ubus listeners and MQTT
+pub struct Reporter {
+    mqtt: watch::Sender<String>,
+    logger: Arc<Logger>,
+}
+
+impl Reporter {
+    // The receiver feeds the MQTT publisher, if one is started
+    pub fn new(logger: Arc<Logger>) -> (Reporter, watch::Receiver<String>) {
+        let (mqtt, rx) = watch::channel(String::new());
+        (Reporter { mqtt, logger }, rx)
+    }
+
+    // `event` is sent as the ubus event `ups` ("start", "outage" or "restore")
+    pub async fn publish(&self, status: &Status, event: &str) {
+        self.update(status);
+
+        let mut message = serde_json::to_value(status).unwrap_or_default();
+        message["event"] = event.into();
+        let mut child = match Command::new("ubus").args(["send", "ups", &message.to_string()]).kill_on_drop(true).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                self.logger.log(&format!("ubus send failed: {}", e));
+                return;
+            }
+        };
+        match tokio::time::timeout(UBUS_TIMEOUT, child.wait()).await {
+            Ok(Ok(result)) if !result.success() => self.logger.log(&format!("ubus send failed: {}", result)),
+            Ok(Err(e)) => self.logger.log(&format!("ubus send failed: {}", e)),
+            Ok(Ok(_)) => {}
+            Err(_) => {
+                let _ = child.kill().await;
+                self.logger.log(&format!("ubus send timed out after {}s, killed", UBUS_TIMEOUT.as_secs()));
+            }
+        }
+    }
+
+    // Refresh the status file and MQTT without an event, e.g. for a battery sample
+    pub fn update(&self, status: &Status) {
+        let json = match serde_json::to_string(status) {
+            Ok(json) => json,
+            Err(e) => {
+                self.logger.log(&format!("Failed to encode status: {}", e));
+                return;
+            }
+        };
+        if let Err(e) = write_status(&json) {
+            self.logger.log(&format!("Failed to write {}: {}", STATUS_PATH, e));
+        }
+        let _ = self.mqtt.send(json);
+    }
+}
+
+// Replace the file in one step so readers never see a partial status
+fn write_status(json: &str) -> std::io::Result<()> {
+    let path = std::path::Path::new(STATUS_PATH);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, path)
+}
@@ -143,6 +143,14 @@ return view.extend({
         o.rmempty = false;
         o.default = "lorawan/downlink";
 
+        o = s.option(form.ListValue, "encoding", _("Payload Encoding"),
+            _("How serial data is carried in the JSON data field; Raw publishes the bytes without JSON"));
+        o.value("utf8", "UTF-8");
+        o.value("hex", "Hex");
+        o.value("base64", "Base64");
+        o.value("raw", _("Raw"));
+        o.default = "utf8";
+
         o = s.option(form.DynamicList, "event_topics", _("Additional Event Topics"));
         o.placeholder = "lorawan/event/#";
 
@@ -13,6 +13,35 @@ var callFileRead = rpc.declare({
     expect: { data: '' }
 });
 
+// Run one request against the rs485-modbus control socket
+function callModbus(method, params) {
+    var args = ['call', method];
+    if (params)
+        args.push(JSON.stringify(params));
+    return fs.exec('/usr/bin/rs485-modbus', args).then(function(res) {
+        // Nothing on stdout and a non-zero exit when the socket cannot be reached
+        if (res.code !== 0 || !res.stdout)
+            throw new Error(_('rs485-modbus daemon not running') + (res.stderr ? ' (' + res.stderr.trim() + ')' : ''));
+        var reply = JSON.parse(res.stdout);
+        if (reply.error)
+            throw new Error(reply.error);
+        return reply.result;
+    });
+}
+
+// Show a transaction result in the frame data area
+function showResult(resultArea, result) {
+    if (!resultArea || !result)
+        return;
+    if (result.status === 'ok') {
+        resultArea.value = result.data;
+        resultArea.style.color = '#000';
+    } else {
+        resultArea.value = 'Error [' + result.status + ']: ' + (result.error || '');
+        resultArea.style.color = '#d00';
+    }
+}
+
 return view.extend({
     load: function() {
         return Promise.all([
@@ -175,52 +204,11 @@ return view.extend({
                 btn.disabled = true;
                 btn.innerText = _('Reading...');
                 
-                // Clean up old files first
-                return fs.exec('/bin/sh', ['-c', 'rm -f /tmp/rs485/modbus_read /tmp/rs485/modbus_result'])
-                    .then(function() {
-                        // Create trigger file
-                        return fs.exec('/bin/sh', ['-c', 'mkdir -p /tmp/rs485 && touch /tmp/rs485/modbus_read']);
-                    })
-                    .then(function () {
-                        // Poll for result file (max 5 seconds)
-                        var pollCount = 0;
-                        var pollInterval = setInterval(function () {
-                            pollCount++;
-
-                            L.resolveDefault(fs.read('/tmp/rs485/modbus_result'))
-                                .then(function (content) {
-                                    if (content) {
-                                        clearInterval(pollInterval);
-                                        if (resultArea) {
-                                            if (content.startsWith('Error:')) {
-                                                resultArea.value = content;
-                                                resultArea.style.color = '#d00';
-                                            } else {
-                                                resultArea.value = content;
-                                                resultArea.style.color = '#000';
-                                            }
-                                        }
-                                        btn.disabled = false;
-                                        btn.innerText = _('Read Data');
-                                        // Clean up files
-                                        fs.exec('/bin/sh', ['-c', 'rm -f /tmp/rs485/modbus_read /tmp/rs485/modbus_result']);
-                                    }
-                                })
-                                .catch(function (err) {
-                                    if (pollCount >= 50) {
-                                        clearInterval(pollInterval);
-                                        if (resultArea) {
-                                            resultArea.value = 'Timeout: No response from Modbus device';
-                                            resultArea.style.color = '#d00';
-                                        }
-                                        btn.disabled = false;
-                                        btn.innerText = _('Read Data');
-                                        // Clean up files
-                                        fs.exec('/bin/sh', ['-c', 'rm -f /tmp/rs485/modbus_read /tmp/rs485/modbus_result']);
-                                    }
-                                });
-                        }, 100);
-                    });
+                return callModbus('read').then(function(result) {
+                    showResult(resultArea, result);
+                    btn.disabled = false;
+                    btn.innerText = _('Read Data');
+                });
             }).catch(function(err) {
                 if (resultArea) {
                     resultArea.value = 'Error: ' + (err.message || err);
@@ -268,51 +256,11 @@ return view.extend({
                 btn.disabled = true;
                 btn.innerText = _('Writing...');
                 
-                // Clean up old files first
-                return fs.exec('/bin/sh', ['-c', 'rm -f /tmp/rs485/modbus_write /tmp/rs485/modbus_result'])
-                    .then(function() {
-                        return fs.exec('/bin/sh', ['-c', 'mkdir -p /tmp/rs485 && touch /tmp/rs485/modbus_write']);
-                    })
-                    .then(function () {
-                        var pollCount = 0;
-                        // Poll for result file (max 5 seconds)
-                        var pollInterval = setInterval(function () {
-                            pollCount++;
-
-                            L.resolveDefault(fs.read('/tmp/rs485/modbus_result'))
-                                .then(function (content) {
-                                    if (content) {
-                                        clearInterval(pollInterval);
-                                        if (resultArea) {
-                                            if (content.startsWith('Error:')) {
-                                                resultArea.value = content;
-                                                resultArea.style.color = '#d00';
-                                            } else {
-                                                resultArea.value = content;
-                                                resultArea.style.color = '#000';
-                                            }
-                                        }
-                                        btn.disabled = false;
-                                        btn.innerText = _('Write Data');
-                                        // Clean up files
-                                        fs.exec('/bin/sh', ['-c', 'rm -f /tmp/rs485/modbus_write /tmp/rs485/modbus_result']);
-                                    }
-                                })
-                                .catch(function (err) {
-                                    if (pollCount >= 50) {
-                                        clearInterval(pollInterval);
-                                        if (resultArea) {
-                                            resultArea.value = 'Timeout: No response from Modbus device';
-                                            resultArea.style.color = '#d00';
-                                        }
-                                        btn.disabled = false;
-                                        btn.innerText = _('Write Data');
-                                        // Clean up files
-                                        fs.exec('/bin/sh', ['-c', 'rm -f /tmp/rs485/modbus_write /tmp/rs485/modbus_result']);
-                                    }
-                                });
-                        }, 100);
-                    });
+                return callModbus('write').then(function(result) {
+                    showResult(resultArea, result);
+                    btn.disabled = false;
+                    btn.innerText = _('Write Data');
+                });
             }).catch(function(err) {
                 if (resultArea) {
                     resultArea.value = 'Error: ' + (err.message || err);
@@ -371,36 +319,21 @@ return view.extend({
                             var resultArea = document.getElementById('modbus_result');
                             if (!resultArea) return;
                             
-                            // Create trigger file
-                            fs.exec('/bin/sh', ['-c', 'mkdir -p /tmp/rs485 && touch /tmp/rs485/modbus_read'])
-                                .then(function() {
-                                    // Poll for result file (max 5 seconds)
-                                    var pollCount = 0;
-                                    var pollInterval = setInterval(function() {
-                                        pollCount++;
-                                        
-                                        L.resolveDefault(fs.read('/tmp/rs485/modbus_result'))
-                                            .then(function(content) {
-                                                if (content) {
-                                                    clearInterval(pollInterval);
-                                                    if (content.startsWith('Error:')) {
-                                                        resultArea.value = content;
-                                                        resultArea.style.color = '#d00';
-                                                    } else {
-                                                        resultArea.value = content;
-                                                        resultArea.style.color = '#000';
-                                                    }
-                                                    fs.exec('/bin/sh', ['-c', 'rm -f /tmp/rs485/modbus_read /tmp/rs485/modbus_result']);
-                                                }
-                                            })
-                                            .catch(function() {
-                                                if (pollCount >= 50) {
-                                                    clearInterval(pollInterval);
-                                                    fs.exec('/bin/sh', ['-c', 'rm -f /tmp/rs485/modbus_read /tmp/rs485/modbus_result']);
-                                                }
-                                            });
-                                    }, 100);
-                                });
+                            // Show the latest result of the daemon's own periodic read; that read
+                            // only runs without poll sections, otherwise show the latest of any entry
+                            callModbus('last_result', { name: 'protocol' }).then(function(result) {
+                                return result || callModbus('last_result');
+                            }).then(function(result) {
+                                if (!result) {
+                                    resultArea.value = _('No result yet');
+                                    resultArea.style.color = '#888';
+                                    return;
+                                }
+                                showResult(resultArea, result);
+                            }).catch(function(err) {
+                                resultArea.value = 'Error: ' + (err.message || err);
+                                resultArea.style.color = '#d00';
+                            });
                         }, pollInterval * 1000);
                     }
                 });
@@ -139,6 +139,48 @@ return view.extend({
         o.placeholder = '1000';
         o.default = '1000';
 
+        o = s.option(form.ListValue, 'direction', _('Direction Control'),
+            _('How a half-duplex transceiver is switched to transmit'));
+        o.value('auto', _('Automatic (hardware)'));
+        o.value('rts', _('RTS (kernel RS485 mode)'));
+        o.value('gpio', _('GPIO line'));
+        o.default = 'auto';
+
+        o = s.option(form.ListValue, 'rts_on_send', _('RTS Level While Sending'));
+        o.value('1', _('High'));
+        o.value('0', _('Low'));
+        o.default = '1';
+        o.depends('direction', 'rts');
+
+        o = s.option(form.Value, 'gpio_chip', _('GPIO Chip'));
+        o.placeholder = '/dev/gpiochip0';
+        o.depends('direction', 'gpio');
+
+        o = s.option(form.Value, 'gpio_line', _('GPIO Line'));
+        o.datatype = 'uinteger';
+        o.depends('direction', 'gpio');
+
+        o = s.option(form.Flag, 'gpio_active_low', _('GPIO Active Low'));
+        o.default = '0';
+        o.depends('direction', 'gpio');
+
+        o = s.option(form.Value, 'delay_before_send', _('Delay Before Send (ms)'));
+        o.datatype = 'range(0,1000)';
+        o.placeholder = '0';
+        o.depends('direction', 'rts');
+        o.depends('direction', 'gpio');
+
+        o = s.option(form.Value, 'delay_after_send', _('Delay After Send (ms)'));
+        o.datatype = 'range(0,1000)';
+        o.placeholder = '0';
+        o.depends('direction', 'rts');
+        o.depends('direction', 'gpio');
+
+        o = s.option(form.Value, 'frame_gap', _('Minimum Frame Gap (ms)'),
+            _('Modbus RTU: silence that ends a frame and precedes the next request; the 3.5 character time is used when longer'));
+        o.datatype = 'range(0,100)';
+        o.placeholder = '3';
+
         return m.render();
     }
 });
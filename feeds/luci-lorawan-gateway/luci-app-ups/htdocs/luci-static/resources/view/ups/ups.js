@@ -2,28 +2,214 @@
 'require view';
 'require form';
 'require uci';
+'require rpc';
+
+var callStatus = rpc.declare({
+    object: 'ups',
+    method: 'status'
+});
 
 return view.extend({
     load: function() {
-        return uci.load('ups-module');
+        return Promise.all([
+            uci.load('ups-module'),
+            L.resolveDefault(callStatus(), {})
+        ]);
     },
 
-    render: function() {
+    render: function(data) {
         var m, s, o;
+        var status = data[1] || {};
 
         m = new form.Map('ups-module', _('UPS Configuration'),
             _('Configure UPS power outage commands.'));
 
+        s = m.section(form.NamedSection, 'cmd', 'ups', _('Power Status'));
+        s.anonymous = true;
+        s.addremove = false;
+
+        o = s.option(form.DummyValue, '_state', _('Power Source'));
+        o.cfgvalue = function() {
+            return { mains: _('Mains'), battery: _('Battery') }[status.state] || _('Unknown');
+        };
+
+        o = s.option(form.DummyValue, '_since', _('Since'));
+        o.cfgvalue = function() { return status.since || '-'; };
+
+        o = s.option(form.DummyValue, '_outage_count', _('Outages'));
+        o.cfgvalue = function() { return status.outage_count != null ? String(status.outage_count) : '-'; };
+
+        if (status.battery) {
+            o = s.option(form.DummyValue, '_battery', _('Battery'));
+            o.cfgvalue = function() {
+                return '%.1f%% (%.2f V, %s)'.format(status.battery.percentage, status.battery.voltage, status.battery.charging);
+            };
+        }
+
         s = m.section(form.NamedSection, 'cmd', 'ups', _('UPS Settings'));
         s.anonymous = true;
         s.addremove = false;
 
         o = s.option(form.DynamicList, 'commands', _('Power Outage Commands'));
 
+        o = s.option(form.Value, 'grace_period', _('Grace Period (s)'),
+            _('Wait this long before running the outage commands; they are cancelled if power returns sooner.'));
+        o.datatype = 'uinteger';
+        o.placeholder = '0';
+        o.rmempty = true;
+
+        o = s.option(form.DynamicList, 'restore_commands', _('Power Restore Commands'),
+            _('Run when mains power returns after the outage commands ran.'));
+
+        o = s.option(form.Value, 'command_timeout', _('Command Timeout (s)'),
+            _('Kill a command still running after this long; 0 for no limit. Commands see UPS_EVENT, UPS_STAGE, UPS_TIMESTAMP, UPS_OUTAGE_START, UPS_OUTAGE_DURATION and UPS_BATTERY_PERCENT.'));
+        o.datatype = 'uinteger';
+        o.placeholder = '60';
+        o.rmempty = true;
+
         o = s.option(form.FileUpload, 'script', _('Power Outage Scripts'));
         o.optional = true;
         o.rmempty = true;
 
+        s = m.section(form.TypedSection, 'stage', _('Outage Stages'),
+            _('Each stage runs once power has been out for its delay or the battery drops below its threshold. Stages not reached when power returns are cancelled.'));
+        s.anonymous = true;
+        s.addremove = true;
+
+        o = s.option(form.Flag, 'enabled', _('Enable'));
+        o.default = '1';
+        o.rmempty = false;
+
+        o = s.option(form.Value, 'name', _('Name'));
+        o.datatype = 'string';
+
+        o = s.option(form.Value, 'delay', _('Delay (s)'));
+        o.datatype = 'uinteger';
+        o.placeholder = '0';
+
+        o = s.option(form.Value, 'battery_below', _('Battery Below (%)'),
+            _('Also run the stage when the battery level drops below this. Requires battery monitoring.'));
+        o.datatype = 'range(0,100)';
+        o.optional = true;
+
+        o = s.option(form.Value, 'timeout', _('Command Timeout (s)'),
+            _('Overrides the command timeout for this stage; 0 for no limit.'));
+        o.datatype = 'uinteger';
+        o.optional = true;
+
+        o = s.option(form.DynamicList, 'commands', _('Commands'));
+
+        s = m.section(form.NamedSection, 'battery', 'battery', _('Battery Monitoring'),
+            _('Sample the battery voltage and report it with the power status. Applied on save, without restarting the service.'));
+        s.anonymous = true;
+        s.addremove = false;
+
+        o = s.option(form.Flag, 'enabled', _('Enable'));
+        o.rmempty = false;
+
+        o = s.option(form.ListValue, 'source', _('Source'));
+        o.value('iio', _('IIO ADC channel'));
+        o.value('power_supply', _('Power supply (fuel gauge)'));
+        o.default = 'iio';
+
+        o = s.option(form.Value, 'raw_path', _('Raw Value Path'));
+        o.placeholder = '/sys/bus/iio/devices/iio:device0/in_voltage0_raw';
+        o.depends('source', 'iio');
+
+        o = s.option(form.Value, 'scale_path', _('Scale Path'),
+            _('File with the scale in mV per step; leave empty to use the fixed scale.'));
+        o.optional = true;
+        o.depends('source', 'iio');
+
+        o = s.option(form.Value, 'scale', _('Scale (mV/step)'));
+        o.datatype = 'ufloat';
+        o.placeholder = '1';
+        o.optional = true;
+        o.depends('source', 'iio');
+
+        o = s.option(form.Value, 'divider', _('Divider Ratio'));
+        o.datatype = 'ufloat';
+        o.placeholder = '1';
+        o.depends('source', 'iio');
+
+        o = s.option(form.Value, 'power_supply_path', _('Power Supply Path'));
+        o.placeholder = '/sys/class/power_supply/battery';
+        o.depends('source', 'power_supply');
+
+        o = s.option(form.Value, 'interval', _('Interval (s)'));
+        o.datatype = 'min(1)';
+        o.placeholder = '30';
+
+        o = s.option(form.Value, 'empty_voltage', _('Empty Voltage (V)'));
+        o.datatype = 'ufloat';
+        o.placeholder = '3.3';
+
+        o = s.option(form.Value, 'full_voltage', _('Full Voltage (V)'));
+        o.datatype = 'ufloat';
+        o.placeholder = '4.2';
+
+        s = m.section(form.NamedSection, 'mqtt', 'mqtt', _('MQTT Status Reporting'),
+            _('Publish the power status as a retained message. Applied on save, without restarting the service.'));
+        s.anonymous = true;
+        s.addremove = false;
+
+        o = s.option(form.Flag, 'enabled', _('Enable'));
+        o.rmempty = false;
+
+        o = s.option(form.ListValue, 'transport', _('Transport Protocol'));
+        o.value('tcp', 'TCP');
+        o.value('ssl', 'SSL/TLS');
+        o.value('ws', 'WebSocket');
+        o.value('wss', 'WebSocket Secure');
+        o.default = 'tcp';
+
+        o = s.option(form.Value, 'host', _('Server Address'));
+        o.datatype = 'or(hostname,ipaddr)';
+        o.placeholder = 'mqtt.example.com';
+
+        o = s.option(form.Value, 'port', _('Server Port'));
+        o.datatype = 'port';
+        o.placeholder = '1883';
+
+        o = s.option(form.Value, 'client_id', _('Client ID'));
+        o.datatype = 'maxlength(32)';
+        o.placeholder = 'ups_module';
+
+        o = s.option(form.Value, 'username', _('Username'));
+        o.optional = true;
+
+        o = s.option(form.Value, 'password', _('Password'));
+        o.password = true;
+        o.optional = true;
+
+        o = s.option(form.ListValue, 'auth_mode', _('Authentication Mode'));
+        o.value('none', _('Username/Password Only'));
+        o.value('tls-server', _('TLS Server Verification'));
+        o.value('mutual-tls', _('Mutual TLS'));
+        o.default = 'none';
+
+        o = s.option(form.FileUpload, 'ca_cert', _('CA Certificate'));
+        o.optional = true;
+        o.depends('auth_mode', 'tls-server');
+        o.depends('auth_mode', 'mutual-tls');
+
+        o = s.option(form.FileUpload, 'client_cert', _('Client Certificate'));
+        o.optional = true;
+        o.depends('auth_mode', 'mutual-tls');
+
+        o = s.option(form.FileUpload, 'client_key', _('Client Private Key'));
+        o.optional = true;
+        o.depends('auth_mode', 'mutual-tls');
+
+        o = s.option(form.Value, 'topic', _('Status Topic'));
+        o.placeholder = 'ups/status';
+
+        o = s.option(form.ListValue, 'qos', _('QoS Level'));
+        o.value('0', '0 - At most once');
+        o.value('1', '1 - At least once');
+        o.value('2', '2 - Exactly once');
+        o.default = '1';
+
         return m.render();
     }
 });
\ No newline at end of file